@@ -13,27 +13,225 @@ use nalgebra::{Point3, Rotation3, Unit, UnitQuaternion, Vector3};
 
 use rtracer::geometric::{InfinitePlane, Sphere};
 use rtracer::light::AreaLight;
-use rtracer::renderer::{render, RenderImage};
+use rtracer::progress::{Progress, ProgressMode};
+use rtracer::renderer::{render_progressive, to_image, RenderImage};
 use rtracer::Color3;
 use rtracer::SceneObject;
 
+use crate::rtracer::animation::{frame_path, Animation};
 use crate::rtracer::parser::RenderConfig;
-use crate::rtracer::SceneData;
-use std::io::{stdout, Write};
+use crate::rtracer::{Camera, Scene, SceneData};
+use crate::utils::overrides::Override;
+use std::path::{Path, PathBuf};
+
+use structopt::StructOpt;
 
 mod rtracer;
 mod utils;
 
+#[derive(StructOpt)]
+#[structopt(about = "Render ron or glTF scene files")]
+enum Command {
+    /// Render the scene to an image, or every frame of its animation
+    Render {
+        #[structopt(flatten)]
+        scene: SceneArgs,
+        /// Image file, instead of `config.output_file`
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// Image width, `config.image_size`
+        #[structopt(long)]
+        width: Option<u32>,
+        /// Image height, same as the width if not set
+        #[structopt(long)]
+        height: Option<u32>,
+        /// Samples per pixel
+        #[structopt(long)]
+        spp: Option<u32>,
+        /// Number of render threads, default to one per core
+        #[structopt(long)]
+        threads: Option<usize>,
+        /// Seed of the random numbers
+        #[structopt(long)]
+        seed: Option<u64>,
+        /// How to report progress: human, json (one object per line) or quiet
+        #[structopt(long, default_value = "human")]
+        progress: ProgressMode,
+        /// Same as `--progress quiet`
+        #[structopt(short, long)]
+        quiet: bool,
+        /// Also write the render statistics as json next to the image
+        #[structopt(long)]
+        stats_json: bool,
+    },
+    /// Print object and light count and the BVH size
+    Info {
+        #[structopt(flatten)]
+        scene: SceneArgs,
+    },
+    /// Check scene files, print every problem found
+    Validate {
+        #[structopt(parse(from_os_str), required = true)]
+        scenes: Vec<PathBuf>,
+    },
+    /// Convert a scene (ron or glTF) to a ron scene file
+    Convert {
+        #[structopt(flatten)]
+        scene: SceneArgs,
+        #[structopt(parse(from_os_str))]
+        output: PathBuf,
+    },
+}
+
+#[derive(StructOpt)]
+struct SceneArgs {
+    #[structopt(parse(from_os_str))]
+    scene: PathBuf,
+    /// Replace a value of the scene file, e.g. `config.image_size=512`
+    /// or `scene.objects.0.material=Named("red")`, value is ron
+    #[structopt(long = "set", number_of_values = 1)]
+    overrides: Vec<Override>,
+}
+
+impl SceneArgs {
+    fn load(&self) -> SceneData {
+        self.load_with(Vec::new())
+    }
+
+    /// Load with `flags` (`path=value`) applied like `--set`, so they are validated the same.
+    /// A flag win over a `--set` of the same path
+    fn load_with(&self, flags: Vec<String>) -> SceneData {
+        let flags = flags
+            .iter()
+            .map(|flag| flag.parse().expect("flag is path=value"))
+            .collect::<Vec<Override>>();
+        let mut overrides = self
+            .overrides
+            .iter()
+            .filter(|o| !flags.iter().any(|flag| flag.path() == o.path()))
+            .cloned()
+            .collect::<Vec<_>>();
+        overrides.extend(flags);
+        match rtracer::parser::load_scene_data_with(&self.scene, &overrides) {
+            Ok(scene) => scene,
+            Err(e) => {
+                eprintln!("error: can't load {}: {}", self.scene.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
 fn main() {
     // let scene_data = setup();
     // rtracer::parser::save_scene_data("data.ron", &scene_data).unwrap();
     // test();
-    let mut args = std::env::args();
-    let path = args.nth(1).unwrap_or("data.ron".to_owned());
-    let scene = rtracer::parser::load_scene_data(&path).unwrap();
-    println!("Rendering {}...", &path);
-    stdout().flush().unwrap();
-    test_render(&scene);
+    match Command::from_args() {
+        Command::Render {
+            scene,
+            output,
+            width,
+            height,
+            spp,
+            threads,
+            seed,
+            progress,
+            quiet,
+            stats_json,
+        } => {
+            let mode = if quiet { ProgressMode::Quiet } else { progress };
+            let start_time = Instant::now();
+            let flags = vec![
+                width.map(|width| format!("config.image_size={}", width)),
+                height.map(|height| format!("config.image_height={}", height)),
+                spp.map(|spp| format!("config.samples_per_pixel={}", spp)),
+                seed.map(|seed| format!("config.seed={}", seed)),
+            ];
+            let mut scene_data = scene.load_with(flags.into_iter().flatten().collect());
+            let render_output = RenderOutput {
+                mode,
+                build_time: start_time.elapsed().as_secs_f32(),
+                stats_json,
+            };
+            if let Some(output) = output {
+                scene_data.config.output_file = output;
+            }
+            if let Some(threads) = threads {
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build_global()
+                    .unwrap();
+            }
+
+            if mode == ProgressMode::Human {
+                println!("Rendering {}...", scene.scene.display());
+            }
+            scene_data.scene.set_shutter(scene_data.config.shutter);
+            match scene_data.animation.take() {
+                Some(animation) => render_animation(scene_data, &animation, &render_output),
+                None => render_to(
+                    &scene_data.scene,
+                    &scene_data.camera,
+                    &scene_data.config,
+                    &scene_data.config.output_file,
+                    render_output.build_time,
+                    &render_output,
+                    new_progress(&scene_data.config, mode),
+                ),
+            }
+        }
+        Command::Info { scene } => print_info(&scene.load()),
+        Command::Validate { scenes } => {
+            let mut failed = false;
+            for path in scenes {
+                match rtracer::parser::load_scene_data(&path) {
+                    Ok(_) => println!("{}: ok", path.display()),
+                    Err(e) => {
+                        println!("{}: {}", path.display(), e);
+                        failed = true;
+                    }
+                }
+            }
+            if failed {
+                std::process::exit(1);
+            }
+        }
+        Command::Convert { scene, output } => {
+            let scene_data = scene.load();
+            if output.extension().and_then(|e| e.to_str()) != Some("ron") {
+                eprintln!("error: can only convert to a .ron scene file");
+                std::process::exit(1);
+            }
+            if let Err(e) = rtracer::parser::save_scene_data(&output, &scene_data) {
+                eprintln!("error: can't save {}: {}", output.display(), e);
+                std::process::exit(1);
+            }
+            println!("Saved to {}", output.display());
+        }
+    }
+}
+
+fn print_info(scene_data: &SceneData) {
+    let scene = &scene_data.scene;
+    let (width, height) = scene_data.config.image_dimensions();
+    println!(
+        "objects:  {} ({} unbounded)",
+        scene.object_count(),
+        scene.unbounded().len()
+    );
+    println!("lights:   {}", scene.lights().len());
+    println!(
+        "bvh:      {} nodes, depth {}",
+        scene.bvh().node_count(),
+        scene.bvh().depth()
+    );
+    println!(
+        "image:    {}x{}, {} spp",
+        width, height, scene_data.config.samples_per_pixel
+    );
+    if let Some(animation) = &scene_data.animation {
+        println!("frames:   {}..={}", animation.start, animation.end);
+    }
 }
 /*8
 fn test() {
@@ -122,31 +320,101 @@ fn setup() -> SceneData {
     SceneData { scene, camera }
 }*/
 
-fn test_render(scene_data: &rtracer::SceneData) {
-    // render
-    let RenderConfig {
-        image_size,
-        output_file,
-        viewport_size,
-        color_map,
-    } = &scene_data.config;
-    let unit_per_pixel: f32 = *viewport_size as f32 / *image_size as f32;
+// how `render` subcommand report
+struct RenderOutput {
+    mode: ProgressMode,
+    // time to load the scene, in the first frame's stats
+    build_time: f32,
+    stats_json: bool,
+}
+
+// render every frame to numbered file next to output_file
+fn render_animation(scene_data: rtracer::SceneData, animation: &Animation, output: &RenderOutput) {
+    let SceneData {
+        mut scene,
+        mut camera,
+        config,
+        ..
+    } = scene_data;
+    if let Err(e) = animation.check(&scene) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
 
-    let (scene, camera) = (&scene_data.scene, &scene_data.camera);
+    let mut load_time = output.build_time;
+    for frame in animation.frames() {
+        if output.mode == ProgressMode::Human {
+            println!("Frame {}/{}", frame, animation.end);
+        }
+        // moving objects rebuild the top level tree
+        let start_time = Instant::now();
+        animation.apply(frame as f32, &mut scene, &mut camera);
+        let build_time = load_time + start_time.elapsed().as_secs_f32();
+        load_time = 0.0;
+        let progress = new_progress(&config, output.mode).with_frame(frame);
+        let output_file = frame_path(&config.output_file, frame);
+        render_to(
+            &scene,
+            &camera,
+            &config,
+            &output_file,
+            build_time,
+            output,
+            progress,
+        );
+    }
+}
 
-    dbg!(scene.bvh().flat_tree());
-    dbg!(scene.unbounded());
+fn new_progress(config: &RenderConfig, mode: ProgressMode) -> Progress {
+    let (width, height) = config.image_dimensions();
+    let budget = config.progressive.as_ref().and_then(|p| p.time_budget);
+    Progress::new(
+        mode,
+        width as u64 * height as u64 * config.samples_per_pixel as u64,
+    )
+    .with_budget(budget)
+}
 
-    print!("Start Rendering...");
-    std::io::stdout().flush().unwrap();
-    let start_time = Instant::now();
-    let rendered_image: RenderImage = render(scene, camera, *image_size, unit_per_pixel, color_map);
-    let duration = start_time.elapsed();
-    println!("\nRendering Finish In {:.2}s", duration.as_secs_f32());
+fn render_to(
+    scene: &Scene,
+    camera: &Camera,
+    config: &RenderConfig,
+    output_file: &Path,
+    build_time: f32,
+    output: &RenderOutput,
+    progress: Progress,
+) {
+    // the image so far is written over the output file, until the final one
+    let intermediate = |image: &RenderImage| match image.save(output_file) {
+        Ok(()) => progress.saved(output_file),
+        Err(e) => eprintln!("warning: can't save {}: {}", output_file.display(), e),
+    };
+    let (accumulator, mut stats) =
+        render_progressive(scene, camera, config, &progress, intermediate);
+    stats.build_time = build_time;
+    progress.stats(&stats);
 
     // save
-    rendered_image
+    to_image(&accumulator, config)
         .save(output_file)
         .expect("Unable to save Image");
-    println!("Saving to {}", output_file.display());
+    progress.saved(output_file);
+    if config
+        .adaptive
+        .as_ref()
+        .map_or(false, |adaptive| adaptive.heatmap)
+    {
+        let heatmap_file = output_file.with_extension("samples.png");
+        accumulator
+            .heatmap()
+            .save(&heatmap_file)
+            .expect("Unable to save heatmap");
+        progress.saved(&heatmap_file);
+    }
+    if output.stats_json {
+        let stats_file = output_file.with_extension("json");
+        let json = serde_json::to_string_pretty(&stats).unwrap();
+        std::fs::write(&stats_file, json).expect("Unable to save stats");
+        progress.saved(&stats_file);
+    }
 }
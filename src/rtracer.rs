@@ -11,19 +11,33 @@ pub use scene_object::SceneObject;
 pub use shape::geometric;
 pub use shape::Shape;
 
+pub mod accumulator;
+pub mod animation;
 mod bvh;
 mod camera;
+pub mod curve;
+pub mod gltf_import;
+pub mod heightfield;
 pub mod helper;
 mod hitinfo;
 pub mod light;
 pub mod material;
+pub mod mesh;
+pub mod obj_import;
 pub mod parser;
+pub mod progress;
 mod raycast_info;
 pub mod renderer;
-mod scene;
+pub mod sampler;
+pub mod scene;
 mod scene_object;
 mod shape;
+pub mod stats;
+pub mod texture;
 mod thread_buffer;
+pub mod transform;
+pub mod validation;
+pub mod volume;
 
 pub type Color3 = Vector3<f32>;
 
@@ -31,7 +45,7 @@ pub type Color3 = Vector3<f32>;
 // number of ray casted = AREALIGHT_MONTECARLO_SAMPLE
 const AREALIGHT_MONTECARLO_SAMPLE: u32 = 121;
 
-// square root of number of grid (sample) in finite differential ray tracing of area light
+// number of point sampled on area light per shading point
 // number of ray casted = (2*AREALIGHT_FINITEDIFF_LENGTH + 1)^2
 const AREALIGHT_FINITEDIFF_LENGTH: u32 = 3;
 
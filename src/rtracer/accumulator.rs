@@ -0,0 +1,182 @@
+use image::{GrayImage, ImageBuffer, Luma, Rgb};
+
+use super::Color3;
+
+pub type RenderBuffer = ImageBuffer<Rgb<f32>, Vec<f32>>;
+
+fn luminance(c: &Color3) -> f32 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+/// Running estimate of one pixel, mean of its samples and variance of their luminance
+#[derive(Clone, Default)]
+pub struct PixelEstimate {
+    sum: Color3,
+    // luminance squared
+    sum_sq: f32,
+    samples: u32,
+}
+
+impl PixelEstimate {
+    pub fn add(&mut self, light: Color3) {
+        let l = luminance(&light);
+        self.sum += light;
+        self.sum_sq += l * l;
+        self.samples += 1;
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    pub fn mean(&self) -> Color3 {
+        self.sum / self.samples.max(1) as f32
+    }
+
+    /// Variance of the mean's luminance relative to its square, infinite before there's 2 samples
+    pub fn relative_variance(&self) -> f32 {
+        if self.samples < 2 {
+            return f32::INFINITY;
+        }
+        let n = self.samples as f32;
+        let mean = luminance(&self.sum) / n;
+        let variance = ((self.sum_sq / n - mean * mean) * n / (n - 1.0)).max(0.0);
+        // very dark pixel is compared with a fixed level so it doesn't need forever to converge
+        variance / n / (mean * mean).max(1e-4)
+    }
+}
+
+/// Float image that sample passes are added to
+pub struct Accumulator {
+    width: u32,
+    height: u32,
+    pixels: Vec<PixelEstimate>,
+}
+
+impl Accumulator {
+    pub fn new(width: u32, height: u32) -> Self {
+        Accumulator {
+            width,
+            height,
+            pixels: vec![PixelEstimate::default(); width as usize * height as usize],
+        }
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// pixels in row order
+    pub fn pixels(&self) -> &[PixelEstimate] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [PixelEstimate] {
+        &mut self.pixels
+    }
+
+    pub fn image(&self) -> RenderBuffer {
+        ImageBuffer::from_fn(self.width, self.height, |x, y| {
+            let mean = self.pixels[(y * self.width + x) as usize].mean();
+            Rgb([mean.x, mean.y, mean.z])
+        })
+    }
+
+    pub fn converged(&self, threshold: f32) -> bool {
+        self.pixels
+            .iter()
+            .all(|p| p.relative_variance() <= threshold)
+    }
+
+    /// Split `budget` more samples between pixels by their relative variance, pixel that has
+    /// `max_samples` or a variance below `threshold` get none. All 0 when every pixel is done
+    pub fn allocate(&self, budget: u64, max_samples: u32, threshold: Option<f32>) -> Vec<u32> {
+        let weights: Vec<f64> = self
+            .pixels
+            .iter()
+            .map(|p| {
+                let variance = p.relative_variance();
+                if p.samples >= max_samples || threshold.map_or(false, |t| variance <= t) {
+                    0.0
+                } else {
+                    // zero variance may be just luck with few samples, keep it in the running
+                    (variance as f64).max(1e-9).min(1e6)
+                }
+            })
+            .collect();
+        let total: f64 = weights.iter().sum();
+        if total == 0.0 {
+            return vec![0; self.pixels.len()];
+        }
+
+        let mut counts: Vec<u32> = weights
+            .iter()
+            .zip(&self.pixels)
+            .map(|(w, p)| {
+                ((budget as f64 * w / total).round() as u32)
+                    .min(max_samples - p.samples.min(max_samples))
+            })
+            .collect();
+        // budget too small to round to anything, the noisiest pixel still get one
+        if counts.iter().all(|&c| c == 0) {
+            let noisiest = (0..weights.len())
+                .max_by(|&a, &b| weights[a].partial_cmp(&weights[b]).unwrap())
+                .unwrap();
+            counts[noisiest] = 1;
+        }
+        counts
+    }
+
+    /// Grayscale image of the samples taken by each pixel, white is the most
+    pub fn heatmap(&self) -> GrayImage {
+        let most = self
+            .pixels
+            .iter()
+            .map(|p| p.samples)
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        ImageBuffer::from_fn(self.width, self.height, |x, y| {
+            let samples = self.pixels[(y * self.width + x) as usize].samples;
+            Luma([(samples as u64 * 255 / most as u64) as u8])
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixel_variance() {
+        let mut flat = PixelEstimate::default();
+        let mut noisy = PixelEstimate::default();
+        assert_eq!(flat.relative_variance(), f32::INFINITY);
+        for i in 0..10 {
+            flat.add(Color3::new(0.5, 0.5, 0.5));
+            noisy.add(Color3::repeat((i % 2) as f32));
+        }
+        assert!(flat.relative_variance() < 1e-6);
+        assert_eq!(noisy.mean(), Color3::repeat(0.5));
+        // sample variance 0.25 * 10/9, of the mean / 10, relative to 0.5^2
+        assert!((noisy.relative_variance() - 1.0 / 9.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn allocate_to_noisy_pixels() {
+        let mut accumulator = Accumulator::new(3, 1);
+        for i in 0..4 {
+            let pixels = accumulator.pixels_mut();
+            pixels[0].add(Color3::repeat(0.5));
+            pixels[1].add(Color3::repeat((i % 2) as f32));
+            pixels[2].add(Color3::repeat(0.5 + 0.1 * (i % 2) as f32));
+        }
+        let counts = accumulator.allocate(300, 1000, None);
+        assert!(counts[1] > counts[2] && counts[2] > counts[0]);
+        assert!(counts.iter().sum::<u32>() <= 301);
+
+        // capped by max_samples, the flat pixel is under the threshold
+        assert_eq!(accumulator.allocate(1000, 10, Some(1e-6))[..2], [0, 6]);
+        assert_eq!(accumulator.allocate(1000, 4, None), [0, 0, 0]);
+    }
+}
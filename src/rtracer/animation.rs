@@ -0,0 +1,314 @@
+use std::ops::{Add, Mul, Sub};
+use std::path::{Path, PathBuf};
+
+use custom_error::custom_error;
+use nalgebra::{Rotation3, UnitQuaternion, Vector3};
+use serde::{Deserialize, Serialize};
+
+use super::light::Light;
+use super::transform::Transform;
+use super::{Camera, Color3, Scene};
+
+custom_error! { pub AnimationError
+    ObjectOutOfRange {index: usize, count: usize} = "Animated object {index} doesn't exist, scene has {count} objects",
+    LightOutOfRange {index: usize, count: usize} = "Animated light {index} doesn't exist, scene has {count} lights",
+    NoKeyframe = "Animation track must have at least one keyframe",
+    DuplicateFrame {frame: f32} = "Animation track has two keyframes at frame {frame}"
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    Linear,
+    // Catmull-Rom spline through the keyframes
+    Cubic,
+}
+
+impl Default for Interpolation {
+    fn default() -> Self {
+        Interpolation::Linear
+    }
+}
+
+/// Value changing over frames, held constant before the first and after the last keyframe
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(
+    try_from = "crate::utils::proxy_serialize::TrackProxy<T>",
+    into = "crate::utils::proxy_serialize::TrackProxy<T>",
+    bound(
+        serialize = "T: Serialize + Clone",
+        deserialize = "T: Deserialize<'de>"
+    )
+)]
+pub struct Track<T> {
+    pub interpolation: Interpolation,
+    // (frame, value), sorted by frame
+    pub keys: Vec<(f32, T)>,
+}
+
+impl<T> Track<T> {
+    pub fn new(
+        interpolation: Interpolation,
+        mut keys: Vec<(f32, T)>,
+    ) -> Result<Self, AnimationError> {
+        if keys.is_empty() {
+            return Err(AnimationError::NoKeyframe);
+        }
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+        // segment between them would have zero length
+        if let Some(pair) = keys.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+            return Err(AnimationError::DuplicateFrame { frame: pair[0].0 });
+        }
+        Ok(Track {
+            interpolation,
+            keys,
+        })
+    }
+}
+
+impl<T> Track<T>
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>,
+{
+    pub fn sample(&self, frame: f32) -> T {
+        let keys = &self.keys;
+        let last = keys.len() - 1;
+        if frame <= keys[0].0 {
+            return keys[0].1;
+        }
+        if frame >= keys[last].0 {
+            return keys[last].1;
+        }
+
+        // segment i .. i+1 containing frame
+        let i = keys.partition_point(|k| k.0 <= frame) - 1;
+        let ((f0, p0), (f1, p1)) = (keys[i], keys[i + 1]);
+        let span = f1 - f0;
+        let t = (frame - f0) / span;
+
+        match self.interpolation {
+            Interpolation::Linear => p0 + (p1 - p0) * t,
+            Interpolation::Cubic => {
+                // tangent per frame from the neighbours, one sided at the ends
+                let tangent = |k: usize| {
+                    let (a, b) = (k.saturating_sub(1), (k + 1).min(last));
+                    (keys[b].1 - keys[a].1) * (1.0 / (keys[b].0 - keys[a].0))
+                };
+                let (m0, m1) = (tangent(i) * span, tangent(i + 1) * span);
+
+                // cubic hermite basis
+                let (t2, t3) = (t * t, t * t * t);
+                p0 * (2.0 * t3 - 3.0 * t2 + 1.0)
+                    + m0 * (t3 - 2.0 * t2 + t)
+                    + p1 * (-2.0 * t3 + 3.0 * t2)
+                    + m1 * (t3 - t2)
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct CameraAnimation {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<Track<Vector3<f32>>>,
+    // euler angles (roll, pitch, yaw)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rotation: Option<Track<Vector3<f32>>>,
+}
+
+/// Where an animated object is on a frame
+#[derive(Clone, Debug, PartialEq)]
+pub enum ObjectPose {
+    // object space transform, applied before the object's own transform
+    Transformed(Transform),
+    // zero scale keyframe collapse the object, it isn't rendered
+    Hidden,
+}
+
+/// Move object at `object` (index in the scene file) every frame, the keyframed transform is
+/// in object space so it's applied before the object's own. Missing part is identity
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ObjectAnimation {
+    pub object: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub translation: Option<Track<Vector3<f32>>>,
+    // euler angles (roll, pitch, yaw)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotation: Option<Track<Vector3<f32>>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale: Option<Track<Vector3<f32>>>,
+}
+
+impl ObjectAnimation {
+    fn pose_at(&self, frame: f32) -> ObjectPose {
+        let sample = |track: &Option<Track<Vector3<f32>>>, default: Vector3<f32>| {
+            track.as_ref().map_or(default, |t| t.sample(frame))
+        };
+        let translation = sample(&self.translation, Vector3::zeros());
+        let euler = sample(&self.rotation, Vector3::zeros());
+        let scale = sample(&self.scale, Vector3::repeat(1.0));
+        let rotation = UnitQuaternion::from_euler_angles(euler.x, euler.y, euler.z);
+
+        match Transform::from_trs(translation, rotation, scale) {
+            Ok(transform) => ObjectPose::Transformed(transform),
+            Err(_) => ObjectPose::Hidden,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LightAnimation {
+    pub light: usize,
+    pub intensity: Track<Color3>,
+}
+
+/// Keyframed changes applied on top of the scene, frames `start..=end` are rendered
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Animation {
+    pub start: u32,
+    pub end: u32,
+    #[serde(default)]
+    pub camera: CameraAnimation,
+    #[serde(default)]
+    pub objects: Vec<ObjectAnimation>,
+    #[serde(default)]
+    pub lights: Vec<LightAnimation>,
+}
+
+impl Animation {
+    /// check that every animated object and light exist in the scene
+    pub fn check(&self, scene: &Scene) -> Result<(), AnimationError> {
+        let object_count = scene.object_count();
+        let light_count = scene.lights().len();
+        if let Some(anim) = self.objects.iter().find(|a| a.object >= object_count) {
+            return Err(AnimationError::ObjectOutOfRange {
+                index: anim.object,
+                count: object_count,
+            });
+        }
+        if let Some(anim) = self.lights.iter().find(|a| a.light >= light_count) {
+            return Err(AnimationError::LightOutOfRange {
+                index: anim.light,
+                count: light_count,
+            });
+        }
+        Ok(())
+    }
+
+    /// Move everything to where they are at `frame`,
+    /// the scene's top level tree is only rebuilt when a bounded object moved
+    pub fn apply(&self, frame: f32, scene: &mut Scene, camera: &mut Camera) {
+        if let Some(position) = &self.camera.position {
+            camera.pos = position.sample(frame).into();
+        }
+        if let Some(rotation) = &self.camera.rotation {
+            let euler = rotation.sample(frame);
+            camera.set_rotation(Rotation3::from_euler_angles(euler.x, euler.y, euler.z));
+        }
+
+        scene.set_poses(
+            self.objects
+                .iter()
+                .map(|anim| (anim.object, anim.pose_at(frame))),
+        );
+
+        let lights = scene.lights_mut();
+        for anim in &self.lights {
+            lights[anim.light].set_intensity(anim.intensity.sample(frame));
+        }
+    }
+
+    pub fn frames(&self) -> impl Iterator<Item = u32> {
+        self.start..=self.end
+    }
+}
+
+/// `dir/name.png` -> `dir/name_0001.png`
+pub fn frame_path(output_file: &Path, frame: u32) -> PathBuf {
+    let stem = output_file
+        .file_stem()
+        .map_or_else(|| "frame".into(), |s| s.to_string_lossy());
+    let mut name = format!("{}_{:04}", stem, frame);
+    if let Some(ext) = output_file.extension() {
+        name.push('.');
+        name.push_str(&ext.to_string_lossy());
+    }
+    output_file.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn track_interpolation() {
+        let keys = vec![(10.0, 2.0), (0.0, 0.0), (20.0, 0.0)];
+        let linear = Track::new(Interpolation::Linear, keys.clone()).unwrap();
+        assert_approx_eq!(linear.sample(-5.0), 0.0);
+        assert_approx_eq!(linear.sample(5.0), 1.0);
+        assert_approx_eq!(linear.sample(15.0), 1.0);
+        assert_approx_eq!(linear.sample(25.0), 0.0);
+
+        // pass through the keyframes, smooth peak at the middle one
+        let cubic = Track::new(Interpolation::Cubic, keys).unwrap();
+        assert_approx_eq!(cubic.sample(10.0), 2.0);
+        assert_approx_eq!(cubic.sample(20.0), 0.0);
+        assert!(cubic.sample(9.0) > 1.9 && cubic.sample(9.0) < 2.0);
+        assert_approx_eq!(cubic.sample(9.0), cubic.sample(11.0));
+
+        let duplicate = Track::new(
+            Interpolation::Cubic,
+            vec![(0.0, 1.0), (5.0, 2.0), (5.0, 3.0)],
+        );
+        assert!(matches!(
+            duplicate,
+            Err(AnimationError::DuplicateFrame { .. })
+        ));
+    }
+
+    #[test]
+    fn object_pose_on_top_of_its_transform() {
+        use crate::rtracer::scene::SceneBuilder;
+        use crate::rtracer::validation::parse_ron;
+        use nalgebra::Point3;
+
+        let scene: SceneBuilder = parse_ron(
+            "(objects: [(material: Emission((light: [1, 1, 1])),
+                         shape: Sphere((pos: [0, 0, 0], radius: 1)),
+                         transform: Some(TRS(translation: [5, 0, 0])))])",
+            "test.ron",
+        )
+        .unwrap();
+        let animation: Animation = parse_ron(
+            "(start: 0, end: 10, objects: [(object: 0,
+                translation: Some((keys: [(0, [0, 0, 0]), (10, [0, 2, 0])])),
+                scale: Some((keys: [(0, [0, 0, 0]), (10, [1, 1, 1])])))])",
+            "test.ron",
+        )
+        .unwrap();
+        let mut scene = scene.build().unwrap();
+        let mut camera = Camera::new(Point3::origin(), Rotation3::identity());
+        let hit_x = |scene: &Scene, y: f32| {
+            let origin = Point3::new(-10.0, y, 0.0);
+            scene.object(0).intersect_at(origin, Vector3::x_axis(), 0.0)
+        };
+
+        // collapsed on the first frame, not back at its untransformed place
+        animation.apply(0.0, &mut scene, &mut camera);
+        assert!(hit_x(&scene, 0.0).is_none());
+
+        // moved up from where the scene file put it
+        animation.apply(10.0, &mut scene, &mut camera);
+        assert!(hit_x(&scene, 0.0).is_none());
+        assert_approx_eq!(hit_x(&scene, 2.0).unwrap().intersection.x, 4.0, 1e-4);
+    }
+
+    #[test]
+    fn numbered_frame_path() {
+        assert_eq!(
+            frame_path(Path::new("out/frame.png"), 1),
+            PathBuf::from("out/frame_0001.png")
+        );
+    }
+}
@@ -1,12 +1,19 @@
 use crate::rtracer::bvh::BVHChild::Leaf;
 use crate::rtracer::scene::SceneObjectIndex;
 use crate::rtracer::shape::Shape;
+use crate::rtracer::stats::count_nested_traversal;
 use crate::rtracer::SceneObject;
 use crate::utils::aabb::AABB;
 use crate::utils::cell_vec::CellVec;
 
+use nalgebra::{Point3, Unit, Vector3};
+use ordered_float::OrderedFloat;
 use typed_index_collections::TiVec;
 
+// maximum depth of tree that `closest_hit` can traverse,
+// tree is always balanced so this is enough for any practical number of leaves
+const TRAVERSAL_STACK_SIZE: usize = 64;
+
 fn calculate_capacity(mut n: usize) -> usize {
     let mut c = 0;
     while n > 0 {
@@ -17,26 +24,29 @@ fn calculate_capacity(mut n: usize) -> usize {
 }
 
 #[derive(Clone, Debug)]
-pub enum BVHChild {
+pub enum BVHChild<I = SceneObjectIndex> {
     Node { left: usize, right: usize },
-    Leaf(SceneObjectIndex),
+    Leaf(I),
 }
 
 #[derive(Clone, Debug)]
-pub struct BVHNode {
+pub struct BVHNode<I = SceneObjectIndex> {
     pub bounding_box: AABB,
-    pub child: BVHChild,
+    pub child: BVHChild<I>,
 }
 
 /// This structure most likely will behave incorrectly if the slice used to generate this tree
 /// get modify/reorder
-pub struct BVHTree {
-    data: Vec<BVHNode>,
+///
+/// `I` is the index of whatever the leaves refer to, scene objects for top level tree
+/// or primitive (eg. triangle) for tree of a single shape
+pub struct BVHTree<I = SceneObjectIndex> {
+    data: Vec<BVHNode<I>>,
     //    buffer: CellVec<usize>,
 }
 
-impl BVHTree {
-    fn new(flat_tree: Vec<BVHNode>) -> Self {
+impl<I: Clone> BVHTree<I> {
+    fn new(flat_tree: Vec<BVHNode<I>>) -> Self {
         let capacity = calc_dfs_required_capacity(&flat_tree);
         BVHTree {
             data: flat_tree,
@@ -44,12 +54,12 @@ impl BVHTree {
         }
     }
 
-    pub fn flat_tree(&self) -> &Vec<BVHNode> {
+    pub fn flat_tree(&self) -> &Vec<BVHNode<I>> {
         &self.data
     }
 
     // partition pattern: left[..len/2], right[len/2..]
-    pub fn generate(objects: impl Iterator<Item = (SceneObjectIndex, AABB)>) -> Self {
+    pub fn generate(objects: impl Iterator<Item = (I, AABB)>) -> Self {
         // TODO: preallocated/calculated capacity
         let mut flat_tree = Vec::new();
 
@@ -80,38 +90,11 @@ impl BVHTree {
         assert_eq!(flat_tree.len(), capacity);*/
     }
 
-    pub fn from_scene_objects<U, I>(
-        scene_objs: I,
-    ) -> (Self, TiVec<SceneObjectIndex, SceneObject>, U)
-    where
-        U: Default + Extend<SceneObject>,
-        I: IntoIterator<Item = SceneObject>,
-    {
-        let mut flat_tree = Vec::new();
-        let mut bounded_obj = TiVec::new();
-        let mut unbounded_obj = U::default();
-
-        for obj in scene_objs {
-            if let Some(bb) = obj.shape.bounding_box() {
-                let idx = bounded_obj.push_and_get_key(obj);
-                flat_tree.push(BVHNode {
-                    bounding_box: bb,
-                    child: Leaf(idx),
-                })
-            } else {
-                unbounded_obj.extend(Some(obj));
-            }
-        }
-
-        recursive_build_tree_entry(&mut flat_tree);
-        (BVHTree::new(flat_tree), bounded_obj, unbounded_obj)
-    }
-
     pub fn query_leaf<'a>(
         &'a self,
-        pruner: impl Fn(&'a BVHNode) -> bool + 'a,
+        pruner: impl Fn(&'a BVHNode<I>) -> bool + 'a,
         buffer: &'a mut Vec<usize>,
-    ) -> impl Iterator<Item = SceneObjectIndex> + 'a {
+    ) -> impl Iterator<Item = I> + 'a {
         // TODO: unchecked indexing & pushing
         let flat_tree = &self.data;
         buffer.clear();
@@ -148,31 +131,122 @@ impl BVHTree {
             }
         })
     }
+
+    /// Find the closest hit along the ray without allocation
+    ///
+    /// `hit_leaf` return distance of hit (and anything else) of the leaf,
+    /// node farther than the closest hit found so far are skipped
+    pub fn closest_hit<T>(
+        &self,
+        origin: Point3<f32>,
+        dir: Unit<Vector3<f32>>,
+        mut hit_leaf: impl FnMut(&I) -> Option<(f32, T)>,
+    ) -> Option<(f32, T)> {
+        let flat_tree = &self.data;
+        let root = flat_tree.len().checked_sub(1)?;
+
+        let mut stack = [root; TRAVERSAL_STACK_SIZE];
+        let mut stack_len = 1;
+        let mut closest: Option<(f32, T)> = None;
+        let (mut visited, mut tested) = (0, 0);
+
+        while stack_len > 0 {
+            stack_len -= 1;
+            let node = &flat_tree[stack[stack_len]];
+            visited += 1;
+
+            let t_max = closest.as_ref().map_or(f32::INFINITY, |(t, _)| *t);
+            if !node.bounding_box.does_ray_hit(origin, dir, &(0.0..t_max)) {
+                continue;
+            }
+
+            match &node.child {
+                &BVHChild::Node { left, right } => {
+                    stack[stack_len] = right;
+                    stack[stack_len + 1] = left;
+                    stack_len += 2;
+                }
+                Leaf(id) => {
+                    tested += 1;
+                    if let Some((t, value)) = hit_leaf(id) {
+                        if t < t_max {
+                            closest = Some((t, value));
+                        }
+                    }
+                }
+            }
+        }
+
+        count_nested_traversal(visited, tested);
+        closest
+    }
+
+    /// number of nodes, including leaves
+    pub fn node_count(&self) -> usize {
+        self.data.len()
+    }
+
+    /// number of nodes on the longest path from root to leaf
+    pub fn depth(&self) -> usize {
+        calc_dfs_required_capacity(&self.data)
+    }
 }
 
-// TODO: better sorting function (generic?)
-fn recursive_build_tree_entry(arr: &mut Vec<BVHNode>) {
+impl BVHTree<SceneObjectIndex> {
+    pub fn from_scene_objects<U, I>(
+        scene_objs: I,
+    ) -> (Self, TiVec<SceneObjectIndex, SceneObject>, U)
+    where
+        U: Default + Extend<SceneObject>,
+        I: IntoIterator<Item = SceneObject>,
+    {
+        let mut flat_tree = Vec::new();
+        let mut bounded_obj = TiVec::new();
+        let mut unbounded_obj = U::default();
+
+        for obj in scene_objs {
+            if let Some(bb) = obj.bounding_box() {
+                let idx = bounded_obj.push_and_get_key(obj);
+                flat_tree.push(BVHNode {
+                    bounding_box: bb,
+                    child: Leaf(idx),
+                })
+            } else {
+                unbounded_obj.extend(Some(obj));
+            }
+        }
+
+        recursive_build_tree_entry(&mut flat_tree);
+        (BVHTree::new(flat_tree), bounded_obj, unbounded_obj)
+    }
+}
+
+fn recursive_build_tree_entry<I>(arr: &mut Vec<BVHNode<I>>) {
     let len = arr.len();
     if len > 1 {
         recursive_build_tree(arr, 0, len);
     }
 }
 
-fn recursive_build_tree(arr: &mut Vec<BVHNode>, start: usize, end: usize) -> usize {
+fn recursive_build_tree<I>(arr: &mut Vec<BVHNode<I>>, start: usize, end: usize) -> usize {
     let slice = &mut arr[start..end];
     if slice.len() == 1 {
         return start;
     }
-    // TODO: Generalize sorting
-    slice.sort_unstable_by(|node_a, node_b| {
-        node_a
-            .bounding_box
-            .min()
-            .partial_cmp(&node_b.bounding_box.max())
-            .unwrap_or(std::cmp::Ordering::Less)
-    });
+
+    // split at median of centroid along the axis where centroids spread the most
+    let centroid =
+        |node: &BVHNode<I>| nalgebra::center(node.bounding_box.min(), node.bounding_box.max());
+    let first = centroid(&slice[0]);
+    let (lo, hi) = slice
+        .iter()
+        .map(centroid)
+        .fold((first, first), |(lo, hi), c| (lo.inf(&c), hi.sup(&c)));
+    let axis = (hi - lo).imax();
 
     let mid = (start + end) / 2;
+    slice.select_nth_unstable_by_key(mid - start, |node| OrderedFloat(centroid(node)[axis]));
+
     let left = recursive_build_tree(arr, start, mid);
     let right = recursive_build_tree(arr, mid, end);
 
@@ -190,7 +264,7 @@ fn recursive_build_tree(arr: &mut Vec<BVHNode>, start: usize, end: usize) -> usi
 }
 
 // calculate a upper bounded of capacity of buffer required to perform dfs on flat tree
-fn calc_dfs_required_capacity(flat_tree: &[BVHNode]) -> usize {
+fn calc_dfs_required_capacity<I>(flat_tree: &[BVHNode<I>]) -> usize {
     if flat_tree.is_empty() {
         return 0;
     }
@@ -236,3 +310,43 @@ mod tests {
         recursive_build_tree_entry(&mut d);
     }
 }*/
+
+#[cfg(test)]
+mod bvh_tests {
+    use super::*;
+    use crate::rtracer::stats::RenderStats;
+
+    #[test]
+    fn closest_hit_match_brute_force() {
+        // unit cubes along x axis, at x = 0, 2, 4, ...
+        let boxes: Vec<AABB> = (0..37)
+            .map(|i| {
+                let x = 2.0 * i as f32;
+                AABB::from_floats(x, 0.0, 0.0, x + 1.0, 1.0, 1.0)
+            })
+            .collect();
+        let tree = BVHTree::generate(boxes.iter().cloned().enumerate());
+        assert_eq!(tree.node_count(), 2 * boxes.len() - 1);
+
+        let origin = Point3::new(100.0, 0.5, 0.5);
+        let dir = -Vector3::x_axis();
+        let hit_leaf = |&i: &usize| {
+            boxes[i]
+                .calc_ray_hit_span(origin, dir, &(0.0..))
+                .map(|[t0, _]| (t0, i))
+        };
+
+        let brute_force = (0..boxes.len())
+            .filter_map(|i| hit_leaf(&i))
+            .min_by_key(|(t, _)| OrderedFloat(*t));
+        let mut stats = RenderStats::default();
+        stats.collect_nested_traversal();
+        assert_eq!(tree.closest_hit(origin, dir, hit_leaf), brute_force);
+        assert_eq!(brute_force.map(|(_, i)| i), Some(36));
+
+        // the traversal is counted for the thread's render stats
+        stats.collect_nested_traversal();
+        assert!(stats.primitive_tests >= 1 && stats.primitive_tests <= boxes.len() as u64);
+        assert!(stats.bvh_nodes_visited > stats.primitive_tests);
+    }
+}
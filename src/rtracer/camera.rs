@@ -10,6 +10,9 @@ pub struct Camera {
     forward: Vector3<f32>,
     right: Vector3<f32>,
     up: Vector3<f32>,
+    // world space distance per unit time, for motion blur
+    #[serde(default = "Vector3::zeros")]
+    pub velocity: Vector3<f32>,
 }
 
 impl Camera {
@@ -19,8 +22,32 @@ impl Camera {
             forward: rot * Vector3::new(1.0, 0.0, 0.0),
             right: rot * Vector3::new(0.0, 1.0, 0.0),
             up: rot * Vector3::new(0.0, 0.0, 1.0),
+            velocity: Vector3::zeros(),
         }
     }
+    /// camera with given unit axes, they don't have to form a rotation (eg. mirrored)
+    pub fn from_axes(
+        pos: Point3<f32>,
+        forward: Vector3<f32>,
+        right: Vector3<f32>,
+        up: Vector3<f32>,
+    ) -> Camera {
+        Camera {
+            pos,
+            forward,
+            right,
+            up,
+            velocity: Vector3::zeros(),
+        }
+    }
+    pub fn set_rotation(&mut self, rot: Rotation3<f32>) {
+        self.forward = rot * Vector3::new(1.0, 0.0, 0.0);
+        self.right = rot * Vector3::new(0.0, 1.0, 0.0);
+        self.up = rot * Vector3::new(0.0, 0.0, 1.0);
+    }
+    pub fn pos_at(&self, time: f32) -> Point3<f32> {
+        self.pos + self.velocity * time
+    }
     pub fn ray_at_pixel_position(
         &self,
         px: u32,
@@ -0,0 +1,444 @@
+use std::fmt::{Debug, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use custom_error::custom_error;
+use nalgebra::{Point2, Point3, Unit, Vector3};
+use serde::{Deserialize, Serialize};
+
+use super::bvh::BVHTree;
+use super::helper::perpendicular;
+use super::shape::Shape;
+use super::validation::{check_non_negative, nested_problems};
+use super::HitInfo;
+use crate::utils::aabb::AABB;
+
+custom_error! { pub CurveError
+    IOError {source: std::io::Error} = "Unable to read curve file",
+    InvalidNumber {line: usize, token: String} = "Invalid number '{token}' at line {line}",
+    InvalidCount {line: usize, count: usize} = "Line {line} contain {count} numbers, expected 3k+1 control points followed by root and tip width",
+    EmptyCurves {path: String} = "Curve file {path} contain no curve"
+}
+
+/// Cross section of the curve
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CurveKind {
+    // round tube
+    Cylinder,
+    // flat strip that always face the ray
+    Ribbon,
+}
+
+impl Default for CurveKind {
+    fn default() -> Self {
+        CurveKind::Cylinder
+    }
+}
+
+fn eval_bezier(cp: &[Vector3<f32>; 4], u: f32) -> Vector3<f32> {
+    let a = cp[0].lerp(&cp[1], u);
+    let b = cp[1].lerp(&cp[2], u);
+    let c = cp[2].lerp(&cp[3], u);
+    let d = a.lerp(&b, u);
+    let e = b.lerp(&c, u);
+    d.lerp(&e, u)
+}
+
+fn bezier_derivative(cp: &[Vector3<f32>; 4], u: f32) -> Vector3<f32> {
+    let a = (cp[1] - cp[0]).lerp(&(cp[2] - cp[1]), u);
+    let b = (cp[2] - cp[1]).lerp(&(cp[3] - cp[2]), u);
+    a.lerp(&b, u) * 3.0
+}
+
+/// de Casteljau subdivision at u = 0.5
+fn split_bezier(cp: &[Vector3<f32>; 4]) -> ([Vector3<f32>; 4], [Vector3<f32>; 4]) {
+    let a = (cp[0] + cp[1]) / 2.0;
+    let b = (cp[1] + cp[2]) / 2.0;
+    let c = (cp[2] + cp[3]) / 2.0;
+    let d = (a + b) / 2.0;
+    let e = (b + c) / 2.0;
+    let mid = (d + e) / 2.0;
+    ([cp[0], a, d, mid], [mid, e, c, cp[3]])
+}
+
+/// Cubic Bézier curve with width varying linearly from start to end
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Curve {
+    pub points: [Point3<f32>; 4],
+    // (start, end)
+    pub width: (f32, f32),
+    #[serde(default)]
+    pub kind: CurveKind,
+}
+
+impl Curve {
+    fn control_points(&self) -> [Vector3<f32>; 4] {
+        self.points.map(|p| p.coords)
+    }
+
+    pub fn eval(&self, u: f32) -> Point3<f32> {
+        Point3::from(eval_bezier(&self.control_points(), u))
+    }
+
+    pub fn derivative(&self, u: f32) -> Vector3<f32> {
+        bezier_derivative(&self.control_points(), u)
+    }
+
+    fn width_at(&self, u: f32) -> f32 {
+        self.width.0 + (self.width.1 - self.width.0) * u
+    }
+
+    /// Find closest hit of ray space curve, ray space put the ray origin at 0 and the direction along z.
+    ///
+    /// Recursively subdivide the curve and approximate each piece as line segment,
+    /// `best` = (distance, u) of the closest hit so far
+    fn intersect_ray_space(
+        &self,
+        full: &[Vector3<f32>; 4],
+        piece: &[Vector3<f32>; 4],
+        u_range: (f32, f32),
+        depth: u32,
+        best: &mut Option<(f32, f32)>,
+    ) {
+        let (u0, u1) = u_range;
+        let half_width = 0.5 * self.width_at(u0).max(self.width_at(u1));
+
+        // bounding box of control points (convex hull property) must contain the ray
+        let min = piece
+            .iter()
+            .fold(Vector3::repeat(f32::INFINITY), |m, p| m.inf(p));
+        let max = piece
+            .iter()
+            .fold(Vector3::repeat(f32::NEG_INFINITY), |m, p| m.sup(p));
+        let best_dist = best.map_or(f32::INFINITY, |b| b.0);
+        if min.x > half_width
+            || max.x < -half_width
+            || min.y > half_width
+            || max.y < -half_width
+            || max.z + half_width < 0.0
+            || min.z - half_width > best_dist
+        {
+            return;
+        }
+
+        if depth > 0 {
+            let u_mid = (u0 + u1) / 2.0;
+            let (a, b) = split_bezier(piece);
+            self.intersect_ray_space(full, &a, (u0, u_mid), depth - 1, best);
+            self.intersect_ray_space(full, &b, (u_mid, u1), depth - 1, best);
+            return;
+        }
+
+        // closest point between the ray (origin in xy) and the piece as a line segment
+        let start = piece[0].xy();
+        let segment = piece[3].xy() - start;
+        let length_sq = segment.norm_squared();
+        let w = if length_sq > 0.0 {
+            (-start.dot(&segment) / length_sq).max(0.0).min(1.0)
+        } else {
+            0.0
+        };
+        let u = u0 + (u1 - u0) * w;
+
+        let center = eval_bezier(full, u);
+        let half_width = 0.5 * self.width_at(u);
+        let offset_sq = center.xy().norm_squared();
+        if offset_sq > half_width * half_width {
+            return;
+        }
+
+        let dist = match self.kind {
+            CurveKind::Ribbon => center.z,
+            // front of the tube
+            CurveKind::Cylinder => center.z - (half_width * half_width - offset_sq).sqrt(),
+        };
+        if dist > 1e-6 && dist < best_dist {
+            *best = Some((dist, u));
+        }
+    }
+
+    pub fn bounds(&self) -> AABB {
+        let half = 0.5 * self.width.0.max(self.width.1);
+        let padding = Vector3::repeat(half);
+        let bb = self.points[1..].iter().fold(
+            AABB::new_uncheck(self.points[0], self.points[0]),
+            |bb, p| bb.union(&AABB::new_uncheck(*p, *p)),
+        );
+        AABB::new_uncheck(bb.min() - padding, bb.max() + padding)
+    }
+}
+
+impl Shape for Curve {
+    fn intersect(&self, origin: Point3<f32>, dir: Unit<Vector3<f32>>) -> Option<HitInfo> {
+        let u_axis = perpendicular(&dir);
+        let v_axis = dir.cross(&u_axis);
+        let ray_space = self.points.map(|p| {
+            let d = p - origin;
+            Vector3::new(d.dot(&u_axis), d.dot(&v_axis), d.dot(&dir))
+        });
+
+        // subdivision depth from curvature, like pbrt's curve
+        let curvature = (0..2)
+            .map(|i| (ray_space[i] - ray_space[i + 1] * 2.0 + ray_space[i + 2]).norm())
+            .fold(0.0, f32::max);
+        let tolerance = 0.05 * self.width.0.max(self.width.1);
+        let depth = if curvature > 0.0 && tolerance > 0.0 {
+            let r = (std::f32::consts::SQRT_2 * 6.0 * curvature / (8.0 * tolerance)).log2() / 2.0;
+            r.round().max(0.0).min(10.0) as u32
+        } else {
+            0
+        };
+
+        let mut best = None;
+        self.intersect_ray_space(&ray_space, &ray_space, (0.0, 1.0), depth, &mut best);
+        let (dist, u) = best?;
+
+        let tangent = Unit::try_new(self.derivative(u), 1e-12)
+            .unwrap_or_else(|| Unit::new_normalize(self.points[3] - self.points[0]));
+        let intersection = origin + dir.scale(dist);
+
+        // normal of the ribbon facing the ray, bent around the tangent for cylinder
+        let side =
+            Unit::try_new(tangent.cross(&dir), 1e-6).unwrap_or_else(|| perpendicular(&tangent));
+        let flat = tangent.cross(&side);
+        let half_width = 0.5 * self.width_at(u);
+        let v = if half_width > 0.0 {
+            ((intersection - self.eval(u)).dot(&side) / half_width)
+                .max(-1.0)
+                .min(1.0)
+        } else {
+            0.0
+        };
+        let normal = match self.kind {
+            CurveKind::Ribbon => Unit::new_normalize(flat),
+            CurveKind::Cylinder => Unit::new_normalize(flat * (1.0 - v * v).sqrt() + side.scale(v)),
+        };
+
+        Some(HitInfo {
+            incoming_dir: dir,
+            dist,
+            intersection,
+            normal,
+            uv: Point2::new(u, (v + 1.0) / 2.0),
+            tangent: Some(tangent),
+            color: None,
+            back_face: false,
+        })
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.bounds())
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        check_non_negative(&mut problems, "width.0", self.width.0);
+        check_non_negative(&mut problems, "width.1", self.width.1);
+        if self.points.iter().any(|p| p.iter().any(|x| !x.is_finite())) {
+            problems.push("`points` aren't finite".to_owned());
+        }
+        problems
+    }
+}
+
+/// Curves from curve list file with their own acceleration structure
+pub struct CurveList {
+    pub curves: Vec<Curve>,
+    bounds: AABB,
+    bvh: BVHTree<usize>,
+}
+
+impl CurveList {
+    pub fn new(curves: Vec<Curve>) -> Option<Self> {
+        let first = curves.first()?.bounds();
+        let bounds = curves.iter().fold(first, |bb, c| bb.union(&c.bounds()));
+        let bvh = BVHTree::generate(curves.iter().map(Curve::bounds).enumerate());
+        Some(CurveList {
+            curves,
+            bounds,
+            bvh,
+        })
+    }
+
+    /// Parse curve list, one strand per line: `x y z` of 3k+1 control points
+    /// (k Bézier segments joined end to end), then root and tip width. `#` start a comment
+    pub fn parse(text: &str, kind: CurveKind) -> Result<Vec<Curve>, CurveError> {
+        let mut curves = Vec::new();
+        for (line_index, line) in text.lines().enumerate() {
+            let line_number = line_index + 1;
+            let content = line.split('#').next().unwrap_or("");
+            let numbers = content
+                .split_whitespace()
+                .map(|token| {
+                    token.parse::<f32>().map_err(|_| CurveError::InvalidNumber {
+                        line: line_number,
+                        token: token.to_owned(),
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if numbers.is_empty() {
+                continue;
+            }
+
+            let count = numbers.len();
+            let point_count = count.saturating_sub(2) / 3;
+            if count < 14 || (count - 2) % 3 != 0 || (point_count - 1) % 3 != 0 {
+                return Err(CurveError::InvalidCount {
+                    line: line_number,
+                    count,
+                });
+            }
+
+            let points = numbers[..3 * point_count]
+                .chunks_exact(3)
+                .map(|p| Point3::new(p[0], p[1], p[2]))
+                .collect::<Vec<_>>();
+            let (root_width, tip_width) = (numbers[count - 2], numbers[count - 1]);
+            let segments = (point_count - 1) / 3;
+            let width_at =
+                |i: usize| root_width + (tip_width - root_width) * i as f32 / segments as f32;
+
+            curves.extend((0..segments).map(|i| Curve {
+                points: [
+                    points[3 * i],
+                    points[3 * i + 1],
+                    points[3 * i + 2],
+                    points[3 * i + 3],
+                ],
+                width: (width_at(i), width_at(i + 1)),
+                kind,
+            }));
+        }
+        Ok(curves)
+    }
+
+    pub fn load(path: impl AsRef<Path>, kind: CurveKind) -> Result<Self, CurveError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        CurveList::new(CurveList::parse(&text, kind)?).ok_or_else(|| CurveError::EmptyCurves {
+            path: path.display().to_string(),
+        })
+    }
+}
+
+impl Debug for CurveList {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "CurveList({} curves)", self.curves.len())
+    }
+}
+
+/// Many curves (hair, fur, grass) loaded from curve list file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(
+    try_from = "crate::utils::proxy_serialize::CurvesProxy",
+    into = "crate::utils::proxy_serialize::CurvesProxy"
+)]
+pub struct Curves {
+    // as written in the scene file, loaded relative to it
+    pub file: PathBuf,
+    pub kind: CurveKind,
+    pub data: Arc<CurveList>,
+}
+
+impl Curves {
+    pub fn load(file: PathBuf, kind: CurveKind) -> Result<Self, CurveError> {
+        let data = Arc::new(CurveList::load(&file, kind)?);
+        Ok(Curves { file, kind, data })
+    }
+}
+
+impl Shape for Curves {
+    fn intersect(&self, origin: Point3<f32>, dir: Unit<Vector3<f32>>) -> Option<HitInfo> {
+        let curves = &self.data.curves;
+        self.data
+            .bvh
+            .closest_hit(origin, dir, |&i| {
+                curves[i].intersect(origin, dir).map(|hit| (hit.dist, hit))
+            })
+            .map(|(_, hit)| hit)
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.data.bounds.clone())
+    }
+
+    // hair file can have thousands of curves, only the first bad one is described
+    fn problems(&self) -> Vec<String> {
+        let mut invalid = self
+            .data
+            .curves
+            .iter()
+            .enumerate()
+            .map(|(i, curve)| (i, curve.problems()))
+            .filter(|(_, problems)| !problems.is_empty());
+        let (i, first) = match invalid.next() {
+            Some(invalid) => invalid,
+            None => return Vec::new(),
+        };
+        let mut problems = Vec::new();
+        nested_problems(&mut problems, &format!("curve {}", i), first);
+        let more = invalid.count();
+        if more > 0 {
+            problems.push(format!("{} more curves have problems", more));
+        }
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn arc(kind: CurveKind) -> Curve {
+        Curve {
+            points: [
+                Point3::new(0.0, -1.0, 0.0),
+                Point3::new(0.0, -0.5, 1.0),
+                Point3::new(0.0, 0.5, 1.0),
+                Point3::new(0.0, 1.0, 0.0),
+            ],
+            width: (0.2, 0.1),
+            kind,
+        }
+    }
+
+    #[test]
+    fn curve_hit() {
+        // top of the arc is at z = 0.75
+        let origin = Point3::new(0.0, 0.0, 5.0);
+        let down = -Vector3::z_axis();
+
+        let ribbon = arc(CurveKind::Ribbon).intersect(origin, down).unwrap();
+        assert_approx_eq!(ribbon.dist, 4.25, 1e-3);
+        assert_approx_eq!(ribbon.uv.x, 0.5, 1e-3);
+        assert_approx_eq!(ribbon.tangent.unwrap().y, 1.0, 1e-3);
+        assert_approx_eq!(ribbon.normal.z, 1.0, 1e-3);
+
+        // tube surface is half width (0.075 at the middle) closer
+        let tube = arc(CurveKind::Cylinder).intersect(origin, down).unwrap();
+        assert_approx_eq!(tube.dist, 4.25 - 0.075, 1e-3);
+
+        // pass beside the curve
+        assert!(arc(CurveKind::Cylinder)
+            .intersect(Point3::new(0.2, 0.0, 5.0), down)
+            .is_none());
+    }
+
+    #[test]
+    fn parse_curve_list() {
+        let text = "
+            # two segments strand
+            0 0 0  0 0 1  0 0 2  0 0 3  0 0 4  0 0 5  0 0 6  0.2 0.0
+            1 0 0  1 0 1  1 0 2  1 0 3  0.1 0.1
+        ";
+        let curves = CurveList::parse(text, CurveKind::Ribbon).unwrap();
+        assert_eq!(curves.len(), 3);
+        assert_eq!(curves[0].width, (0.2, 0.1));
+        assert_eq!(curves[1].width, (0.1, 0.0));
+        assert_eq!(curves[1].points[0], Point3::new(0.0, 0.0, 3.0));
+
+        assert!(CurveList::parse("0 0 0 1 1 1 0.1 0.1", CurveKind::Ribbon).is_err());
+        assert!(CurveList::parse("0 0 0 a", CurveKind::Ribbon).is_err());
+    }
+}
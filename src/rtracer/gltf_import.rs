@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use custom_error::custom_error;
+use gltf::khr_lights_punctual::Kind;
+use nalgebra::{Matrix4, Point3, Unit, Vector3};
+
+use super::light::{DirectionalLight, PointLight};
+use super::material::Named;
+use super::mesh::{Mesh, TriangleMesh};
+use super::obj_import::{ImportReport, MtlMaterial};
+use super::scene::SceneBuilder;
+use super::texture::TextureError;
+use super::transform::Transform;
+use super::{Camera, Color3, Materials, SceneObject};
+
+custom_error! { pub GltfImportError
+    GltfError {source: gltf::Error} = "Encounter error while loading glTF file",
+    TextureError {source: TextureError} = "Encounter error while loading material texture"
+}
+
+/// Content of a glTF file, converted to this renderer's objects
+pub struct GltfScene {
+    pub scene: SceneBuilder,
+    // first camera in the node hierarchy
+    pub camera: Option<Camera>,
+    // viewport size (at distance 1) matching the camera's vertical field of view
+    pub viewport_size: Option<f32>,
+    pub report: ImportReport,
+}
+
+// glTF is y up, -z forward. Rotate so that y up become z up
+fn y_up_to_z_up() -> Matrix4<f32> {
+    Matrix4::new(
+        1.0, 0.0, 0.0, 0.0, //
+        0.0, 0.0, -1.0, 0.0, //
+        0.0, 1.0, 0.0, 0.0, //
+        0.0, 0.0, 0.0, 1.0,
+    )
+}
+
+fn convert_material(
+    material: &gltf::Material,
+    base_dir: &Path,
+    source: &str,
+    report: &mut ImportReport,
+) -> Result<Materials, TextureError> {
+    let pbr = material.pbr_metallic_roughness();
+    let [r, g, b, alpha] = pbr.base_color_factor();
+    let base = Color3::new(r, g, b);
+    let metallic = pbr.metallic_factor();
+    let roughness = pbr.roughness_factor().max(1e-3);
+
+    let mut mtl = MtlMaterial::new(material.name().unwrap_or_default().to_owned());
+    mtl.diffuse = base * (1.0 - metallic);
+    mtl.specular = base * metallic;
+    // inverse of the obj import's roughness = sqrt(2 / (Ns + 2))
+    mtl.shininess = 2.0 / (roughness * roughness) - 2.0;
+    mtl.emission = Color3::from(material.emissive_factor());
+    if material.alpha_mode() == gltf::material::AlphaMode::Blend {
+        mtl.dissolve = alpha;
+    }
+    if let Some(info) = pbr.base_color_texture() {
+        match info.texture().source().source() {
+            gltf::image::Source::Uri { uri, .. } => mtl.diffuse_map = Some(base_dir.join(uri)),
+            gltf::image::Source::View { .. } => report.warn_file(
+                source,
+                format!(
+                    "embedded texture of material `{}` isn't supported",
+                    mtl.name
+                ),
+            ),
+        }
+    }
+    mtl.to_material()
+}
+
+struct Importer<'a> {
+    file: PathBuf,
+    base_dir: PathBuf,
+    source: String,
+    buffers: &'a [gltf::buffer::Data],
+    // global index of the first primitive of each mesh, this is the model index of `Mesh`
+    primitive_offsets: Vec<usize>,
+    meshes: HashMap<usize, Arc<TriangleMesh>>,
+    // converted materials by index, None is glTF's default material.
+    // They're in the scene's materials, every primitive using one share it
+    materials: HashMap<Option<usize>, Named>,
+    result: GltfScene,
+}
+
+impl<'a> Importer<'a> {
+    fn visit(&mut self, node: gltf::Node, parent: &Matrix4<f32>) -> Result<(), GltfImportError> {
+        let world = parent * Matrix4::from(node.transform().matrix());
+
+        if let Some(mesh) = node.mesh() {
+            self.add_mesh(&mesh, &world)?;
+        }
+        if let Some(camera) = node.camera() {
+            self.set_camera(&camera, &world);
+        }
+        if let Some(light) = node.light() {
+            self.add_light(&light, &world);
+        }
+        for child in node.children() {
+            self.visit(child, &world)?;
+        }
+        Ok(())
+    }
+
+    fn add_mesh(&mut self, mesh: &gltf::Mesh, world: &Matrix4<f32>) -> Result<(), GltfImportError> {
+        let transform = match Transform::from_matrix(*world) {
+            Ok(transform) => transform,
+            Err(e) => {
+                let name = mesh.name().unwrap_or_default();
+                self.result
+                    .report
+                    .warn_file(&self.source, format!("mesh `{}` skipped, {}", name, e));
+                return Ok(());
+            }
+        };
+
+        for (i, primitive) in mesh.primitives().enumerate() {
+            let model = self.primitive_offsets[mesh.index()] + i;
+            let buffers = self.buffers;
+            let data = match self.meshes.get(&model) {
+                Some(data) => data.clone(),
+                None => {
+                    match TriangleMesh::from_gltf_primitives(Some(primitive.clone()), buffers) {
+                        Some(data) => {
+                            let data = Arc::new(data);
+                            self.meshes.insert(model, data.clone());
+                            data
+                        }
+                        None => {
+                            self.result.report.warn_file(
+                                &self.source,
+                                format!("primitive {} has no triangle, skipped", model),
+                            );
+                            continue;
+                        }
+                    }
+                }
+            };
+
+            let material = self.material(&primitive.material())?;
+            let shape = Mesh {
+                file: self.file.clone(),
+                model: Some(model),
+                data,
+            };
+            let mut obj = SceneObject::new(shape, material);
+            obj.transform = Some(transform.clone());
+            self.result.scene.objects.push(obj);
+        }
+        Ok(())
+    }
+
+    fn material(&mut self, material: &gltf::Material) -> Result<Named, TextureError> {
+        if let Some(named) = self.materials.get(&material.index()) {
+            return Ok(named.clone());
+        }
+
+        let converted = convert_material(
+            material,
+            &self.base_dir,
+            &self.source,
+            &mut self.result.report,
+        )?;
+        let library = &mut self.result.scene.materials;
+        // glTF material name can be missing or repeated
+        let mut name: String = match (material.name(), material.index()) {
+            (Some(name), _) if !name.is_empty() => name.to_owned(),
+            (_, Some(index)) => format!("material{}", index),
+            (_, None) => "default".to_owned(),
+        };
+        let base = name.clone();
+        for n in 2.. {
+            if !library.contains_key(&name) {
+                break;
+            }
+            name = format!("{} {}", base, n);
+        }
+        let converted = Arc::new(converted);
+        library.insert(name.clone(), converted.clone());
+
+        let named = Named::resolved(name, converted);
+        self.materials.insert(material.index(), named.clone());
+        Ok(named)
+    }
+
+    fn set_camera(&mut self, camera: &gltf::Camera, world: &Matrix4<f32>) {
+        if self.result.camera.is_some() {
+            return;
+        }
+        let axis = |v: Vector3<f32>| Unit::new_normalize(world.transform_vector(&v)).into_inner();
+        let pos = world.transform_point(&Point3::origin());
+        self.result.camera = Some(Camera::from_axes(
+            pos,
+            axis(-Vector3::z()),
+            axis(Vector3::x()),
+            axis(Vector3::y()),
+        ));
+
+        match camera.projection() {
+            gltf::camera::Projection::Perspective(p) => {
+                self.result.viewport_size = Some(2.0 * (p.yfov() / 2.0).tan())
+            }
+            gltf::camera::Projection::Orthographic(_) => self.result.report.warn_file(
+                &self.source,
+                "orthographic camera is rendered as perspective",
+            ),
+        }
+    }
+
+    fn add_light(&mut self, light: &gltf::khr_lights_punctual::Light, world: &Matrix4<f32>) {
+        let color = Color3::from(light.color()) * light.intensity();
+        let pos = world.transform_point(&Point3::origin());
+        let light = match light.kind() {
+            Kind::Directional => {
+                let dir = Unit::new_normalize(world.transform_vector(&-Vector3::z()));
+                DirectionalLight::new(dir, color).into()
+            }
+            Kind::Point => PointLight::new(pos, color).into(),
+            Kind::Spot { .. } => {
+                self.result
+                    .report
+                    .warn_file(&self.source, "spot light is imported as point light");
+                PointLight::new(pos, color).into()
+            }
+        };
+        self.result.scene.lights.push(light);
+    }
+}
+
+/// Load the default scene (or the first one) of a .gltf/.glb file
+pub fn load_gltf(path: impl AsRef<Path>) -> Result<GltfScene, GltfImportError> {
+    let path = path.as_ref();
+    let (document, buffers, _images) = gltf::import(path)?;
+
+    let primitive_offsets = document
+        .meshes()
+        .scan(0, |offset, mesh| {
+            let start = *offset;
+            *offset += mesh.primitives().len();
+            Some(start)
+        })
+        .collect();
+
+    let mut importer = Importer {
+        // the converted scene refer to it, from wherever it's saved
+        file: path.canonicalize().unwrap_or_else(|_| path.to_owned()),
+        base_dir: path.parent().unwrap_or_else(|| Path::new("")).to_owned(),
+        source: path.display().to_string(),
+        buffers: &buffers,
+        primitive_offsets,
+        meshes: HashMap::new(),
+        materials: HashMap::new(),
+        result: GltfScene {
+            scene: SceneBuilder::default(),
+            camera: None,
+            viewport_size: None,
+            report: ImportReport::default(),
+        },
+    };
+
+    let root = y_up_to_z_up();
+    if let Some(scene) = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+    {
+        for node in scene.nodes() {
+            importer.visit(node, &root)?;
+        }
+    }
+    Ok(importer.result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtracer::light::Light;
+    use crate::rtracer::Shape;
+    use assert_approx_eq::assert_approx_eq;
+    use std::io::Write;
+
+    // one triangle under a translated parent node and again at the root, a camera and a point light
+    const GLTF: &str = r#"{
+        "asset": {"version": "2.0"},
+        "scene": 0,
+        "scenes": [{"nodes": [0, 2, 3, 4]}],
+        "nodes": [
+            {"translation": [0, 1, 0], "children": [1]},
+            {"mesh": 0},
+            {"camera": 0, "translation": [0, 0, 5]},
+            {"translation": [0, 3, 0], "extensions": {"KHR_lights_punctual": {"light": 0}}},
+            {"mesh": 0}
+        ],
+        "meshes": [{"primitives": [{"attributes": {"POSITION": 0}, "material": 0}]}],
+        "materials": [{"pbrMetallicRoughness": {"baseColorFactor": [1, 0, 0, 1], "metallicFactor": 0}}],
+        "cameras": [{"type": "perspective", "perspective": {"yfov": 1.0, "znear": 0.1}}],
+        "extensionsUsed": ["KHR_lights_punctual"],
+        "extensions": {"KHR_lights_punctual": {"lights": [{"type": "point", "intensity": 2}]}},
+        "accessors": [{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                       "min": [-1, 0, 0], "max": [1, 0, 1]}],
+        "bufferViews": [{"buffer": 0, "byteLength": 36}],
+        "buffers": [{"byteLength": 36, "uri": "data:application/octet-stream;base64,AACAvwAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAAAAAAIA/"}]
+    }"#;
+
+    #[test]
+    fn node_hierarchy_and_axes() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("scene.gltf");
+        std::fs::File::create(&path)
+            .unwrap()
+            .write_all(GLTF.as_bytes())
+            .unwrap();
+        let imported = load_gltf(&path).unwrap();
+
+        // triangle lifted by its parent, +y up in glTF is +z up here
+        let obj = &imported.scene.objects[0];
+        let bb = obj.bounding_box().unwrap();
+        assert_approx_eq!(bb.min().z, 1.0, 1e-3);
+        assert_approx_eq!(bb.max().y, 0.0, 1e-3);
+        assert_approx_eq!(bb.min().y, -1.0, 1e-3);
+
+        // both instance refer to the material converted once
+        assert_eq!(imported.scene.objects.len(), 2);
+        assert_eq!(
+            imported.scene.materials.keys().collect::<Vec<_>>(),
+            vec!["material0"]
+        );
+        assert!(matches!(
+            *imported.scene.materials["material0"],
+            Materials::Diffuse(_)
+        ));
+        for obj in &imported.scene.objects {
+            assert!(matches!(&obj.material, Materials::Named(named) if named.name == "material0"));
+        }
+
+        // camera at glTF z = 5 is at y = -5, looking toward +y
+        let camera = imported.camera.unwrap();
+        assert_approx_eq!(camera.pos.y, -5.0);
+        assert_approx_eq!(imported.viewport_size.unwrap(), 2.0 * 0.5_f32.tan());
+
+        let light = &imported.scene.lights[0];
+        assert_approx_eq!(light.intensity().x, 2.0);
+        assert_approx_eq!(light.dir_to_light(Point3::origin()).z, 1.0);
+    }
+}
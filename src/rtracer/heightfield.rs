@@ -0,0 +1,336 @@
+use std::fmt::{Debug, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use custom_error::custom_error;
+use nalgebra::{Point2, Point3, Unit, Vector3};
+use serde::{Deserialize, Serialize};
+
+use super::mesh::ray_triangle;
+use super::shape::Shape;
+use super::validation::check_positive;
+use super::HitInfo;
+use crate::utils::aabb::AABB;
+
+custom_error! { pub HeightfieldError
+    ImageError {source: image::ImageError} = "Encounter error while loading heightfield image",
+    TooSmall {path: String} = "Heightfield image {path} must be at least 2x2 pixels"
+}
+
+/// Grid of heights in [0, 1], x fastest, row 0 at the lowest y
+pub struct HeightGrid {
+    width: usize,
+    height: usize,
+    data: Vec<f32>,
+}
+
+impl HeightGrid {
+    /// load 8 or 16 bit grayscale image, top row of the image is at the highest y
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, HeightfieldError> {
+        let path = path.as_ref();
+        let image = image::open(path)?.into_luma16();
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        if width < 2 || height < 2 {
+            return Err(HeightfieldError::TooSmall {
+                path: path.display().to_string(),
+            });
+        }
+
+        let mut data = Vec::with_capacity(width * height);
+        for row in image.rows().rev() {
+            data.extend(row.map(|p| f32::from(p.0[0]) / f32::from(u16::MAX)));
+        }
+        Ok(HeightGrid {
+            width,
+            height,
+            data,
+        })
+    }
+
+    #[inline]
+    fn at(&self, i: usize, j: usize) -> f32 {
+        self.data[j * self.width + i]
+    }
+}
+
+impl Debug for HeightGrid {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "HeightGrid({}x{})", self.width, self.height)
+    }
+}
+
+/// Terrain over the rectangle `origin` .. `origin + extent` in xy plane, z up
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(
+    try_from = "crate::utils::proxy_serialize::HeightfieldProxy",
+    into = "crate::utils::proxy_serialize::HeightfieldProxy"
+)]
+pub struct Heightfield {
+    // as written in the scene file, loaded relative to it
+    pub file: PathBuf,
+    pub origin: Point3<f32>,
+    pub extent: (f32, f32),
+    pub height_scale: f32,
+    grid: Arc<HeightGrid>,
+    cell_size: (f32, f32),
+    bounds: AABB,
+}
+
+impl Heightfield {
+    pub fn new(
+        file: PathBuf,
+        grid: Arc<HeightGrid>,
+        origin: Point3<f32>,
+        extent: (f32, f32),
+        height_scale: f32,
+    ) -> Self {
+        let cell_size = (
+            extent.0 / (grid.width - 1) as f32,
+            extent.1 / (grid.height - 1) as f32,
+        );
+
+        let (low, high) = grid
+            .data
+            .iter()
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), &h| {
+                (lo.min(h), hi.max(h))
+            });
+        // padding prevent flat terrain from having zero thickness bounding box
+        let bounds = AABB::new(
+            origin + Vector3::new(0.0, 0.0, low * height_scale - 1e-4),
+            origin + Vector3::new(extent.0, extent.1, high * height_scale + 1e-4),
+        );
+
+        Heightfield {
+            file,
+            origin,
+            extent,
+            height_scale,
+            grid,
+            cell_size,
+            bounds,
+        }
+    }
+
+    pub fn load(
+        file: PathBuf,
+        origin: Point3<f32>,
+        extent: (f32, f32),
+        height_scale: f32,
+    ) -> Result<Self, HeightfieldError> {
+        let grid = Arc::new(HeightGrid::load(&file)?);
+        Ok(Heightfield::new(file, grid, origin, extent, height_scale))
+    }
+
+    fn vertex(&self, i: usize, j: usize) -> Point3<f32> {
+        self.origin
+            + Vector3::new(
+                i as f32 * self.cell_size.0,
+                j as f32 * self.cell_size.1,
+                self.grid.at(i, j) * self.height_scale,
+            )
+    }
+
+    /// smooth normal from central difference of neighbour heights
+    fn vertex_normal(&self, i: usize, j: usize) -> Vector3<f32> {
+        let grid = &self.grid;
+        let (i0, i1) = (i.saturating_sub(1), (i + 1).min(grid.width - 1));
+        let (j0, j1) = (j.saturating_sub(1), (j + 1).min(grid.height - 1));
+        let dz_dx = (grid.at(i1, j) - grid.at(i0, j)) * self.height_scale
+            / ((i1 - i0) as f32 * self.cell_size.0);
+        let dz_dy = (grid.at(i, j1) - grid.at(i, j0)) * self.height_scale
+            / ((j1 - j0) as f32 * self.cell_size.1);
+        Vector3::new(-dz_dx, -dz_dy, 1.0)
+    }
+
+    /// test the two triangles of cell (i, j)
+    fn intersect_cell(
+        &self,
+        i: usize,
+        j: usize,
+        origin: Point3<f32>,
+        dir: Unit<Vector3<f32>>,
+    ) -> Option<HitInfo> {
+        let corners = [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)];
+        let nearest = [[0, 1, 2], [0, 2, 3]]
+            .iter()
+            .filter_map(|tri| {
+                let [a, b, c] = tri.map(|k| corners[k]);
+                let (dist, u, v) = ray_triangle(
+                    &self.vertex(a.0, a.1),
+                    &self.vertex(b.0, b.1),
+                    &self.vertex(c.0, c.1),
+                    origin,
+                    dir,
+                )?;
+                Some((dist, u, v, [a, b, c]))
+            })
+            .filter(|hit| hit.0 > 1e-6)
+            .min_by(|a, b| a.0.total_cmp(&b.0))?;
+
+        let (dist, u, v, [a, b, c]) = nearest;
+        let normal = self.vertex_normal(a.0, a.1) * (1.0 - u - v)
+            + self.vertex_normal(b.0, b.1) * u
+            + self.vertex_normal(c.0, c.1) * v;
+        let intersection = origin + dir.scale(dist);
+        let uv = Point2::new(
+            (intersection.x - self.origin.x) / self.extent.0,
+            (intersection.y - self.origin.y) / self.extent.1,
+        );
+
+        Some(HitInfo {
+            incoming_dir: dir,
+            dist,
+            intersection,
+            normal: Unit::new_normalize(normal),
+            uv,
+            tangent: None,
+            color: None,
+            back_face: false,
+        })
+    }
+}
+
+impl Shape for Heightfield {
+    fn intersect(&self, origin: Point3<f32>, dir: Unit<Vector3<f32>>) -> Option<HitInfo> {
+        let [t_start, t_end] = self.bounds.calc_ray_hit_span(origin, dir, &(0.0..))?;
+        let (nx, ny) = (self.grid.width - 1, self.grid.height - 1);
+        let (cell_x, cell_y) = self.cell_size;
+
+        // 2D grid DDA over the cells in xy (Amanatides & Woo)
+        let start = origin + dir.scale(t_start);
+        let cell_of =
+            |x: f32, size: f32, n: usize| ((x / size).floor().max(0.0) as usize).min(n - 1);
+        let mut i = cell_of(start.x - self.origin.x, cell_x, nx);
+        let mut j = cell_of(start.y - self.origin.y, cell_y, ny);
+
+        // distance along the ray to the next cell boundary in x and y, and between boundaries
+        let axis_setup = |d: f32, o: f32, grid_o: f32, size: f32, index: usize| {
+            if d > 0.0 {
+                let next = grid_o + (index + 1) as f32 * size;
+                ((next - o) / d, size / d)
+            } else if d < 0.0 {
+                let next = grid_o + index as f32 * size;
+                ((next - o) / d, -size / d)
+            } else {
+                (f32::INFINITY, f32::INFINITY)
+            }
+        };
+        let (mut t_max_x, t_delta_x) = axis_setup(dir.x, origin.x, self.origin.x, cell_x, i);
+        let (mut t_max_y, t_delta_y) = axis_setup(dir.y, origin.y, self.origin.y, cell_y, j);
+
+        let mut t_enter = t_start;
+        loop {
+            let t_exit = t_max_x.min(t_max_y).min(t_end);
+
+            // skip cell if the ray segment is completely above or below it
+            let grid = &self.grid;
+            let heights = [
+                grid.at(i, j),
+                grid.at(i + 1, j),
+                grid.at(i, j + 1),
+                grid.at(i + 1, j + 1),
+            ];
+            // negative height_scale flip the terrain, and which of the heights is lowest
+            let scaled = heights.map(|h| h * self.height_scale);
+            let cell_low = scaled.iter().cloned().fold(f32::INFINITY, f32::min);
+            let cell_high = scaled.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+            let z_enter = origin.z + dir.z * t_enter - self.origin.z;
+            let z_exit = origin.z + dir.z * t_exit - self.origin.z;
+            let (seg_low, seg_high) = (z_enter.min(z_exit), z_enter.max(z_exit));
+            if seg_high >= cell_low - 1e-4 && seg_low <= cell_high + 1e-4 {
+                if let Some(hit) = self.intersect_cell(i, j, origin, dir) {
+                    return Some(hit);
+                }
+            }
+
+            if t_exit >= t_end {
+                return None;
+            }
+            if t_max_x < t_max_y {
+                if (dir.x > 0.0 && i + 1 >= nx) || (dir.x < 0.0 && i == 0) {
+                    return None;
+                }
+                i = if dir.x > 0.0 { i + 1 } else { i - 1 };
+                t_max_x += t_delta_x;
+            } else {
+                if (dir.y > 0.0 && j + 1 >= ny) || (dir.y < 0.0 && j == 0) {
+                    return None;
+                }
+                j = if dir.y > 0.0 { j + 1 } else { j - 1 };
+                t_max_y += t_delta_y;
+            }
+            t_enter = t_exit;
+        }
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.bounds.clone())
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        check_positive(&mut problems, "extent.0", self.extent.0);
+        check_positive(&mut problems, "extent.1", self.extent.1);
+        // negative scale is a pit, only infinity and NaN are wrong
+        if !self.height_scale.is_finite() {
+            problems.push("`height_scale` isn't finite".to_owned());
+        }
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    // 3x3 grid with a peak in the middle, or a pit if `height_scale` is negative
+    fn pyramid(height_scale: f32) -> Heightfield {
+        let grid = HeightGrid {
+            width: 3,
+            height: 3,
+            data: vec![0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0],
+        };
+        Heightfield::new(
+            PathBuf::new(),
+            Arc::new(grid),
+            Point3::origin(),
+            (2.0, 2.0),
+            height_scale,
+        )
+    }
+
+    #[test]
+    fn grid_traversal() {
+        let terrain = pyramid(1.0);
+
+        // straight down on the peak
+        let hit = terrain
+            .intersect(Point3::new(1.0, 1.0, 5.0), -Vector3::z_axis())
+            .unwrap();
+        assert_approx_eq!(hit.dist, 4.0, 1e-4);
+        assert_approx_eq!(hit.uv.x, 0.5);
+
+        // grazing ray that travel across several cells before hitting the slope
+        let dir = Unit::new_normalize(Vector3::new(1.0, 0.2, -0.1));
+        let origin = Point3::new(-1.0, 0.5, 0.55);
+        let hit = terrain.intersect(origin, dir).unwrap();
+        assert!(hit.intersection.x > 0.0 && hit.intersection.x < 1.0);
+        assert!(hit.normal.x < 0.0 && hit.normal.z > 0.0);
+
+        // above everything
+        assert!(terrain
+            .intersect(Point3::new(-1.0, 1.0, 1.5), Vector3::x_axis())
+            .is_none());
+        // grazing ray into the pit, the cell bounds must not be swapped
+        let pit = pyramid(-1.0);
+        let dir = Unit::new_normalize(Vector3::new(1.0, 0.0, -0.5));
+        let hit = pit.intersect(Point3::new(0.0, 1.0, 0.0), dir).unwrap();
+        assert!(hit.intersection.z < 0.0 && hit.intersection.x > 0.5);
+        let hit = pit
+            .intersect(Point3::new(1.0, 1.0, 5.0), -Vector3::z_axis())
+            .unwrap();
+        assert_approx_eq!(hit.dist, 6.0, 1e-4);
+    }
+}
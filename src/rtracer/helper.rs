@@ -15,6 +15,23 @@ pub fn calculate_reflect_ray(
     debug_normalize(ray)
 }
 
+/// Snell's law, `eta` = ratio of refractive index (incoming side / other side),
+/// normal must face the incoming side. None on total internal reflection
+pub fn calculate_refract_ray(
+    incoming_ray: &Unit<Vector3<f32>>,
+    normal: &Unit<Vector3<f32>>,
+    eta: f32,
+) -> Option<Unit<Vector3<f32>>> {
+    let cos_i = -incoming_ray.dot(normal);
+    let sin_sq_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin_sq_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin_sq_t).sqrt();
+    let ray = eta * incoming_ray.into_inner() + (eta * cos_i - cos_t) * normal.into_inner();
+    Some(Unit::new_normalize(ray))
+}
+
 #[cfg(debug_assertions)]
 pub fn debug_normalize(v: Vector3<f32>) -> Unit<Vector3<f32>> {
     let (unit_vec, magnitude) = Unit::new_and_get(v);
@@ -27,6 +44,159 @@ pub fn debug_normalize(v: Vector3<f32>) -> Unit<Vector3<f32>> {
     Unit::new_unchecked(v)
 }
 
+/// Normalize direction read from a file, (near) zero vector stay zero so validation can report it
+pub fn normalize_or_zero(v: Vector3<f32>) -> Unit<Vector3<f32>> {
+    Unit::try_new(v, 1e-6).unwrap_or_else(|| Unit::new_unchecked(Vector3::zeros()))
+}
+
+/// real roots of a*x^2 + b*x + c = 0 in ascending order (equal roots if there's only one)
+pub fn solve_quadratic(a: f32, b: f32, c: f32) -> Option<(f32, f32)> {
+    if a.abs() < 1e-12 {
+        // degenerate into linear equation
+        if b == 0.0 {
+            return None;
+        }
+        let x = -c / b;
+        return Some((x, x));
+    }
+
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    // numerically stable form, avoid cancellation between -b and sqrt(discriminant)
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    let (x0, x1) = if q == 0.0 { (0.0, 0.0) } else { (q / a, c / q) };
+    Some((x0.min(x1), x0.max(x1)))
+}
+
+/// real roots of monic cubic x^3 + a*x^2 + b*x + c = 0
+fn solve_cubic(a: f64, b: f64, c: f64, roots: &mut [f64; 4]) -> usize {
+    use std::f64::consts::PI;
+
+    // depressed cubic y^3 + p*y + q = 0, x = y - a/3
+    let sq_a = a * a;
+    let p = (3.0 * b - sq_a) / 9.0;
+    let q = (2.0 * a * sq_a - 9.0 * a * b + 27.0 * c) / 54.0;
+    let shift = a / 3.0;
+
+    let p_cube = p * p * p;
+    let discriminant = q * q + p_cube;
+
+    if discriminant.abs() < 1e-14 {
+        if q.abs() < 1e-14 {
+            // one triple root
+            roots[0] = -shift;
+            1
+        } else {
+            // one single and one double root
+            let u = (-q).cbrt();
+            roots[0] = 2.0 * u - shift;
+            roots[1] = -u - shift;
+            2
+        }
+    } else if discriminant < 0.0 {
+        // three real roots
+        let phi = (-q / (-p_cube).sqrt()).max(-1.0).min(1.0).acos() / 3.0;
+        let t = 2.0 * (-p).sqrt();
+        roots[0] = t * phi.cos() - shift;
+        roots[1] = -t * (phi + PI / 3.0).cos() - shift;
+        roots[2] = -t * (phi - PI / 3.0).cos() - shift;
+        3
+    } else {
+        // one real root
+        let sqrt_d = discriminant.sqrt();
+        roots[0] = (sqrt_d - q).cbrt() - (sqrt_d + q).cbrt() - shift;
+        1
+    }
+}
+
+/// Real roots of a*x^4 + b*x^3 + c*x^2 + d*x + e = 0 (Ferrari's method, polished by Newton's method)
+///
+/// return roots buffer and number of roots found, roots are not sorted
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64, e: f64) -> ([f64; 4], usize) {
+    let mut roots = [0.0; 4];
+    if a == 0.0 {
+        return (roots, 0);
+    }
+
+    // monic x^4 + A*x^3 + B*x^2 + C*x + D = 0
+    let (ca, cb, cc, cd) = (b / a, c / a, d / a, e / a);
+
+    // depressed quartic y^4 + p*y^2 + q*y + r = 0, x = y - A/4
+    let sq_a = ca * ca;
+    let p = -3.0 / 8.0 * sq_a + cb;
+    let q = sq_a * ca / 8.0 - ca * cb / 2.0 + cc;
+    let r = -3.0 / 256.0 * sq_a * sq_a + sq_a * cb / 16.0 - ca * cc / 4.0 + cd;
+
+    let mut count = 0;
+    if r.abs() < 1e-14 {
+        // y * (y^3 + p*y + q) = 0
+        count = solve_cubic(0.0, p, q, &mut roots);
+        roots[count] = 0.0;
+        count += 1;
+    } else {
+        // one real root of resolvent cubic
+        let mut cubic_roots = [0.0; 4];
+        solve_cubic(-p / 2.0, -r, r * p / 2.0 - q * q / 8.0, &mut cubic_roots);
+        let z = cubic_roots[0];
+
+        let u = z * z - r;
+        let v = 2.0 * z - p;
+        let u = if u.abs() < 1e-14 {
+            0.0
+        } else if u > 0.0 {
+            u.sqrt()
+        } else {
+            return (roots, 0);
+        };
+        let v = if v.abs() < 1e-14 {
+            0.0
+        } else if v > 0.0 {
+            v.sqrt()
+        } else {
+            return (roots, 0);
+        };
+        let v = if q < 0.0 { -v } else { v };
+
+        for &(lin, constant) in &[(v, z - u), (-v, z + u)] {
+            let discriminant = lin * lin - 4.0 * constant;
+            if discriminant >= 0.0 {
+                let sqrt_d = discriminant.sqrt();
+                roots[count] = (-lin + sqrt_d) / 2.0;
+                roots[count + 1] = (-lin - sqrt_d) / 2.0;
+                count += 2;
+            }
+        }
+    }
+
+    // resubstitute then polish
+    for root in roots.iter_mut().take(count) {
+        let mut x = *root - ca / 4.0;
+        for _ in 0..2 {
+            let f = (((x + ca) * x + cb) * x + cc) * x + cd;
+            let df = ((4.0 * x + 3.0 * ca) * x + 2.0 * cb) * x + cc;
+            if df.abs() > 1e-12 {
+                x -= f / df;
+            }
+        }
+        *root = x;
+    }
+
+    (roots, count)
+}
+
+/// unit vector perpendicular to `v`
+pub fn perpendicular(v: &Unit<Vector3<f32>>) -> Unit<Vector3<f32>> {
+    let other = if v.x.abs() < 0.9 {
+        Vector3::x()
+    } else {
+        Vector3::y()
+    };
+    Unit::new_normalize(other.cross(v))
+}
+
 pub fn map_float<F: Real>(x: F, src: RangeInclusive<F>, dest: RangeInclusive<F>) -> F {
     let dest_size = *dest.start() - *dest.end();
     let src_size = *src.start() - *src.end();
@@ -118,6 +288,23 @@ where   F1: FnMut(&mut UV3, usize) -> Option<UV3>,
 mod tests {
     use super::*;
 
+    #[test]
+    fn refract_test() {
+        let normal = Vector3::z_axis();
+        // straight through
+        let down = -Vector3::z_axis();
+        let refracted = calculate_refract_ray(&down, &normal, 1.0 / 1.5).unwrap();
+        assert_approx_eq!(refracted.z, -1.0);
+
+        // 45 degree into glass bend toward the normal
+        let incoming = Unit::new_normalize(Vector3::new(1.0, 0.0, -1.0));
+        let refracted = calculate_refract_ray(&incoming, &normal, 1.0 / 1.5).unwrap();
+        assert_approx_eq!(refracted.x, 0.5_f32.sqrt() / 1.5);
+
+        // same angle from inside is past the critical angle
+        assert!(calculate_refract_ray(&incoming, &normal, 1.5).is_none());
+    }
+
     #[test]
     fn map_float_test() {
         assert_eq!(map_float(0.0, -1.0..=1.0, 0.0..=1.0), 0.5);
@@ -125,4 +312,32 @@ mod tests {
         assert_eq!(map_float(-1.0, -1.0..=1.0, 0.0..=1.0), 0.0);
         assert_eq!(map_float(0.0, 0.0..=1.0, -1.0..=1.0), -1.0);
     }
+
+    #[test]
+    fn quadratic_roots() {
+        assert_eq!(solve_quadratic(1.0, -3.0, 2.0), Some((1.0, 2.0)));
+        assert_eq!(solve_quadratic(0.0, 2.0, -4.0), Some((2.0, 2.0)));
+        assert_eq!(solve_quadratic(1.0, 0.0, 1.0), None);
+    }
+
+    #[test]
+    fn quartic_roots() {
+        // (x-1)(x-2)(x+3)(x-0.5) = x^4 - 0.5x^3 - 7x^2 + 9.5x - 3
+        let (roots, count) = solve_quartic(1.0, -0.5, -7.0, 9.5, -3.0);
+        let mut roots = roots[..count].to_vec();
+        roots.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(count, 4);
+        for (root, expected) in roots.iter().zip(&[-3.0, 0.5, 1.0, 2.0]) {
+            assert_approx_eq!(root, expected, 1e-9);
+        }
+
+        // x^4 + 1 has no real root
+        assert_eq!(solve_quartic(1.0, 0.0, 0.0, 0.0, 1.0).1, 0);
+
+        // (x^2 - 4)(x^2 + 1) = x^4 - 3x^2 - 4
+        let (roots, count) = solve_quartic(1.0, 0.0, -3.0, 0.0, -4.0);
+        assert_eq!(count, 2);
+        assert_approx_eq!(roots[0].abs(), 2.0, 1e-9);
+        assert_approx_eq!(roots[1].abs(), 2.0, 1e-9);
+    }
 }
@@ -1,4 +1,6 @@
-use nalgebra::{Point3, Unit, Vector3};
+use nalgebra::{Point2, Point3, Unit, Vector3};
+
+use super::Color3;
 
 // TODO: include more info such as material/ objectId, etc..
 pub struct HitInfo {
@@ -6,4 +8,13 @@ pub struct HitInfo {
     pub dist: f32,
     pub intersection: Point3<f32>,
     pub normal: Unit<Vector3<f32>>,
+    // surface parameterization, usually in [0,1]^2
+    pub uv: Point2<f32>,
+    // direction along the surface, only provided by shape that have one (eg. curves)
+    pub tangent: Option<Unit<Vector3<f32>>>,
+    // interpolated vertex color, only provided by mesh that have one. Diffuse materials are tinted by it
+    pub color: Option<Color3>,
+    // ray came from behind a double sided surface whose `normal` was turned toward it,
+    // like the inside of a closed mesh
+    pub back_face: bool,
 }
@@ -8,7 +8,9 @@ use enum_dispatch::enum_dispatch;
 
 use super::{AREALIGHT_FINITEDIFF_LENGTH, AREALIGHT_MONTECARLO_SAMPLE};
 // use super::Color3;
-use super::renderer::raycast;
+use super::renderer::raycast_transmittance;
+use super::transform::Transform;
+use super::validation::{check_color, check_direction};
 use super::Color3;
 use super::Scene;
 use crate::rtracer::geometric::Plane;
@@ -21,9 +23,36 @@ pub trait Light {
         &self,
         pos: Point3<f32>,
         norm: Unit<Vector3<f32>>,
+        time: f32,
         scene: &Scene,
         thread_buffer: &mut ThreadBuffer,
     ) -> Color3;
+
+    // intensity of light arriving at position=pos without normal attenuation (eg. inside a medium)
+    fn incident_light_at(
+        &self,
+        pos: Point3<f32>,
+        time: f32,
+        scene: &Scene,
+        thread_buffer: &mut ThreadBuffer,
+    ) -> Color3;
+
+    // direction from position=pos toward the light (its center for area light)
+    fn dir_to_light(&self, pos: Point3<f32>) -> Unit<Vector3<f32>>;
+
+    // emitted color, used for animating the light
+    fn intensity(&self) -> Color3;
+    fn set_intensity(&mut self, light: Color3);
+
+    // move the light by transform, used when placing an included scene
+    fn transform(&mut self, transform: &Transform);
+
+    // invalid parameter, described for the user. Checked after loading a scene
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        check_color(&mut problems, "light", &self.intensity());
+        problems
+    }
 }
 
 #[enum_dispatch(Light)]
@@ -46,37 +75,37 @@ impl PointLight {
         PointLight { pos, light }
     }
 
-    // calculated reduction of light due to weakening factor (cos(theta) term) and distance(1/r^2 term)
+    // calculated reduction of light due to weakening factor (cos(theta) term), distance(1/r^2 term)
+    // and obstruction (including partial obstruction by volume), no weakening factor if norm is None
     fn _calc_reduction_factor(
         self_pos: Point3<f32>,
         pos: Point3<f32>,
-        norm: Unit<Vector3<f32>>,
+        norm: Option<Unit<Vector3<f32>>>,
+        time: f32,
         scene: &Scene,
         thread_buffer: &mut ThreadBuffer,
     ) -> f32 {
         let (dir_to_obj, dist_to_obj) = Unit::new_and_get(pos - self_pos);
-        let norm_attune = -norm.dot(dir_to_obj.as_ref());
+        let norm_attune = norm.map_or(1.0, |norm| -norm.dot(dir_to_obj.as_ref()));
 
         if norm_attune.is_sign_negative() {
             return 0.0;
         }
-        let unobstructed_factor = || norm_attune / (dist_to_obj * dist_to_obj); // lazy eval
-
-        let hit_info = raycast(scene, self_pos, dir_to_obj, &mut thread_buffer.bvh_buffer);
-
-        match hit_info {
-            None => unobstructed_factor(),
-            Some(hit) => {
-                // check if it hit something before reaching objct?
-                // 1e-4 is for mitigate float unstable comparison
-                if hit.dist + 1e-4 < dist_to_obj {
-                    // hit something before the object (or at least near enough)
-                    0.0
-                } else {
-                    // actually hit object
-                    unobstructed_factor()
-                }
-            }
+
+        // 1e-4 is for mitigate float unstable comparison
+        let transmittance = raycast_transmittance(
+            scene,
+            self_pos,
+            dir_to_obj,
+            dist_to_obj - 1e-4,
+            time,
+            thread_buffer,
+        );
+
+        if transmittance > 0.0 {
+            transmittance * norm_attune / (dist_to_obj * dist_to_obj)
+        } else {
+            0.0
         }
     }
 
@@ -85,12 +114,13 @@ impl PointLight {
         self_light: Color3,
         pos: Point3<f32>,
         norm: Unit<Vector3<f32>>,
+        time: f32,
         scene: &Scene,
         thread_buffer: &mut ThreadBuffer,
     ) -> Color3 {
-        let a = Self::_calc_reduction_factor(self_pos, pos, norm, scene, thread_buffer);
+        let a = Self::_calc_reduction_factor(self_pos, pos, Some(norm), time, scene, thread_buffer);
         //if a != 0.0 {*/
-            self_light * a
+        self_light * a
         /*}
         else {
             Color3::zeros()
@@ -103,10 +133,37 @@ impl Light for PointLight {
         &self,
         pos: Point3<f32>,
         norm: Unit<Vector3<f32>>,
+        time: f32,
         scene: &Scene,
         thread_buffer: &mut ThreadBuffer,
     ) -> Color3 {
-        Self::_light_at(self.pos, self.light, pos, norm, scene, thread_buffer)
+        Self::_light_at(self.pos, self.light, pos, norm, time, scene, thread_buffer)
+    }
+
+    fn incident_light_at(
+        &self,
+        pos: Point3<f32>,
+        time: f32,
+        scene: &Scene,
+        thread_buffer: &mut ThreadBuffer,
+    ) -> Color3 {
+        self.light * Self::_calc_reduction_factor(self.pos, pos, None, time, scene, thread_buffer)
+    }
+
+    fn dir_to_light(&self, pos: Point3<f32>) -> Unit<Vector3<f32>> {
+        Unit::new_normalize(self.pos - pos)
+    }
+
+    fn intensity(&self) -> Color3 {
+        self.light
+    }
+
+    fn set_intensity(&mut self, light: Color3) {
+        self.light = light;
+    }
+
+    fn transform(&mut self, transform: &Transform) {
+        self.pos = transform.point_to_world(&self.pos);
     }
 }
 
@@ -128,6 +185,7 @@ impl Light for DirectionalLight {
         &self,
         pos: Point3<f32>,
         norm: Unit<Vector3<f32>>,
+        time: f32,
         scene: &Scene,
         thread_buffer: &mut ThreadBuffer,
     ) -> Color3 {
@@ -137,18 +195,50 @@ impl Light for DirectionalLight {
             return Color3::zeros();
         }
 
-        let hit_info = raycast(scene, pos, -self.dir, &mut thread_buffer.bvh_buffer);
+        self.incident_light_at(pos, time, scene, thread_buffer) * norm_attune
+    }
 
-        match hit_info {
-            None => self.light * norm_attune,
-            Some(_) => Color3::zeros(),
-        }
+    fn incident_light_at(
+        &self,
+        pos: Point3<f32>,
+        time: f32,
+        scene: &Scene,
+        thread_buffer: &mut ThreadBuffer,
+    ) -> Color3 {
+        self.light
+            * raycast_transmittance(scene, pos, -self.dir, f32::INFINITY, time, thread_buffer)
+    }
+
+    fn dir_to_light(&self, _pos: Point3<f32>) -> Unit<Vector3<f32>> {
+        -self.dir
+    }
+
+    fn intensity(&self) -> Color3 {
+        self.light
+    }
+
+    fn set_intensity(&mut self, light: Color3) {
+        self.light = light;
+    }
+
+    fn transform(&mut self, transform: &Transform) {
+        self.dir = Unit::new_normalize(transform.vector_to_world(&self.dir));
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        check_color(&mut problems, "light", &self.light);
+        check_direction(&mut problems, "dir", &self.dir);
+        problems
     }
 }
 
 // Area Light
-#[derive(Serialize, Deserialize)]
-#[serde(from = "crate::utils::proxy_serialize::AreaLightProxy")]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(
+    from = "crate::utils::proxy_serialize::AreaLightProxy",
+    into = "crate::utils::proxy_serialize::AreaLightProxy"
+)]
 pub struct AreaLight {
     // #[serde(flatten)]
     pub(crate) plane: Plane,
@@ -203,13 +293,73 @@ impl Light for AreaLight {
         &self,
         pos: Point3<f32>,
         norm: Unit<Vector3<f32>>,
+        time: f32,
+        scene: &Scene,
+        thread_buffer: &mut ThreadBuffer,
+    ) -> Color3 {
+        self._light_at(pos, Some(norm), time, scene, thread_buffer)
+    }
+
+    fn incident_light_at(
+        &self,
+        pos: Point3<f32>,
+        time: f32,
+        scene: &Scene,
+        thread_buffer: &mut ThreadBuffer,
+    ) -> Color3 {
+        self._light_at(pos, None, time, scene, thread_buffer)
+    }
+
+    fn dir_to_light(&self, pos: Point3<f32>) -> Unit<Vector3<f32>> {
+        Unit::new_normalize(self.plane.pos - pos)
+    }
+
+    fn intensity(&self) -> Color3 {
+        self.light
+    }
+
+    fn set_intensity(&mut self, light: Color3) {
+        self.light = light;
+    }
+
+    fn transform(&mut self, transform: &Transform) {
+        let plane = &mut self.plane;
+        let (span_dir, span_length) =
+            Unit::new_and_get(transform.vector_to_world(&plane.span_dir.scale(plane.span_length)));
+        let (cospan_dir, cospan_length) = Unit::new_and_get(
+            transform.vector_to_world(&plane.cospan_dir.scale(plane.cospan_length)),
+        );
+        plane.pos = transform.point_to_world(&plane.pos);
+        plane.norm = transform.normal_to_world(&plane.norm);
+        plane.span_dir = span_dir;
+        plane.span_length = span_length;
+        plane.cospan_dir = cospan_dir;
+        plane.cospan_length = cospan_length;
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        check_color(&mut problems, "light", &self.light);
+        check_direction(&mut problems, "plane.norm", &self.plane.norm);
+        check_direction(&mut problems, "plane.span", &self.plane.span_dir);
+        if problems.is_empty() {
+            check_direction(&mut problems, "plane.cospan", &self.plane.cospan_dir);
+        }
+        problems
+    }
+}
+
+impl AreaLight {
+    fn _light_at(
+        &self,
+        pos: Point3<f32>,
+        norm: Option<Unit<Vector3<f32>>>,
+        time: f32,
         scene: &Scene,
         thread_buffer: &mut ThreadBuffer,
     ) -> Color3 {
         const SQRT_RAY_COUNT: u32 = 2 * AREALIGHT_FINITEDIFF_LENGTH + 1;
-        const RAY_COUNT: f32 = (SQRT_RAY_COUNT * SQRT_RAY_COUNT) as f32;
-        const FD_LENGTH: i32 = AREALIGHT_FINITEDIFF_LENGTH as i32;
-        const FD_FLOAT: f32 = FD_LENGTH as f32;
+        const RAY_COUNT: u32 = SQRT_RAY_COUNT * SQRT_RAY_COUNT;
 
         let Plane {
             pos: plane_pos,
@@ -223,25 +373,23 @@ impl Light for AreaLight {
         let span = span_dir.scale(*span_length);
         let cospan = cospan_dir.scale(*cospan_length);
 
-        let d_span = span_dir.scale(span_length / FD_FLOAT);
-        let d_cospan = cospan_dir.scale(cospan_length / FD_FLOAT);
-
-        // dbg!(d_cospan);
+        // simulate area light as a lot of point light, spread by the sampler
+        // over the pixel's samples instead of a fixed grid
+        let series = thread_buffer.sampler.series_2d(RAY_COUNT);
         let mut reduction_factor_sum = 0.0;
-
-        let mut light_point = (plane_pos - (span + cospan));
-        for _ in 0..SQRT_RAY_COUNT {
-            let mut light_point_i = light_point.clone();
-            for _ in 0..SQRT_RAY_COUNT {
-                // simulate area light as a lot of point light
-                // dbg!(light_point_i);
-                reduction_factor_sum += PointLight::_calc_reduction_factor(light_point_i, pos, norm, scene, thread_buffer);
-                light_point_i += &d_cospan;
-            }
-            light_point += &d_span
+        for i in 0..RAY_COUNT {
+            let (u, v) = thread_buffer.get_2d(series, i);
+            let light_point = plane_pos + span.scale(2.0 * u - 1.0) + cospan.scale(2.0 * v - 1.0);
+            reduction_factor_sum += PointLight::_calc_reduction_factor(
+                light_point,
+                pos,
+                norm,
+                time,
+                scene,
+                thread_buffer,
+            );
         }
 
-
-         self.light * reduction_factor_sum / RAY_COUNT
+        self.light * reduction_factor_sum / RAY_COUNT as f32
     }
 }
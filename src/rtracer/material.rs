@@ -1,19 +1,30 @@
 use nalgebra::{Unit, UnitQuaternion, Vector3};
 use rand::Rng;
-
-use rand_distr::{Distribution, UnitSphere};
 use serde::{Deserialize, Serialize};
 
+use custom_error::custom_error;
 use enum_dispatch::enum_dispatch;
 
-use crate::rtracer::renderer::raycast_compute_light;
+use crate::rtracer::geometric::Shapes;
+use crate::rtracer::renderer::{raycast_compute_light, raycast_solid, shade_hit};
+use crate::rtracer::sampler::{ball_point, sphere_point};
+use crate::rtracer::texture::ImageTexture;
 use crate::rtracer::thread_buffer::ThreadBuffer;
+use crate::rtracer::validation::{check_color, check_non_negative};
 use crate::rtracer::{
     helper, light::Light, Color3, HitInfo, RayCastInfo, Scene, SceneObject, INDIRECT_DEPTH_LIMIT,
     REFLECTION_DEPTH_LIMIT,
 };
 use num_traits::One;
+use std::collections::BTreeMap;
 use std::f32::consts::PI;
+use std::sync::Arc;
+
+custom_error! { pub MaterialError
+    UnknownMaterial {name: String} = "Unknown material `{name}`, it isn't in the scene's materials",
+    NestedReference {name: String} = "Material `{name}` in the scene's materials can't be a reference",
+    DuplicateMaterial {name: String} = "Material `{name}` is defined by more than one scene file"
+}
 
 #[enum_dispatch]
 pub trait Material {
@@ -25,6 +36,11 @@ pub trait Material {
         hit_object: &SceneObject,
         raycase_info: RayCastInfo,
     ) -> Color3;
+
+    /// invalid parameter, described for the user. Checked after loading a scene
+    fn problems(&self) -> Vec<String> {
+        Vec::new()
+    }
 }
 
 #[enum_dispatch(Material)]
@@ -36,6 +52,10 @@ pub enum Materials {
     Reflective,
     PerfectReflective,
     Emission, // PBRReflective
+    Medium,
+    Hair,
+    Dielectric,
+    Named,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -64,11 +84,26 @@ impl Material for NormalDebug {
 pub struct Diffuse {
     color: Color3,
     albedo: f32,
+    // multiplied with color
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    texture: Option<ImageTexture>,
 }
 
 impl Diffuse {
     pub fn new(color: Color3, albedo: f32) -> Self {
-        Diffuse { color, albedo }
+        Diffuse {
+            color,
+            albedo,
+            texture: None,
+        }
+    }
+
+    pub fn with_texture(color: Color3, albedo: f32, texture: ImageTexture) -> Self {
+        Diffuse {
+            color,
+            albedo,
+            texture: Some(texture),
+        }
     }
 }
 
@@ -79,19 +114,42 @@ impl Material for Diffuse {
         thread_buffer: &mut ThreadBuffer,
         hit_info: &HitInfo,
         _hit_object: &SceneObject,
-        _: RayCastInfo,
+        raycast_info: RayCastInfo,
     ) -> Color3 {
-        let dl = scene.direct_light_at(hit_info.intersection, hit_info.normal, thread_buffer);
+        let dl = scene.direct_light_at(
+            hit_info.intersection,
+            hit_info.normal,
+            raycast_info.time(),
+            thread_buffer,
+        );
 
         let combined_light = self.albedo * scene.get_skylight() + (1.0 - self.albedo) * dl;
 
-        combined_light.component_mul(&self.color) // factor in material's color
+        let mut color = match &self.texture {
+            Some(texture) => self.color.component_mul(&texture.sample(&hit_info.uv)),
+            None => self.color,
+        };
+        if let Some(vertex_color) = &hit_info.color {
+            color.component_mul_assign(vertex_color);
+        }
+        combined_light.component_mul(&color) // factor in material's color
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        check_color(&mut problems, "color", &self.color);
+        problems
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(from="crate::utils::proxy_serialize::PBRDiffuseProxy")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(
+    from = "crate::utils::proxy_serialize::PBRDiffuseProxy",
+    into = "crate::utils::proxy_serialize::PBRDiffuseProxy"
+)]
 pub struct PBRDiffuse {
+    pub color: Color3,
+    pub albedo: f32,
     // albedo * color
     pub color_albedo: Color3,
     pub iteration: usize,
@@ -106,20 +164,24 @@ impl Material for PBRDiffuse {
         _hit_object: &SceneObject,
         raycast_info: RayCastInfo,
     ) -> Color3 {
-        let direct_light =
-            scene.direct_light_at(hit_info.intersection, hit_info.normal, thread_buffer)
-                / std::f32::consts::PI;
+        let direct_light = scene.direct_light_at(
+            hit_info.intersection,
+            hit_info.normal,
+            raycast_info.time(),
+            thread_buffer,
+        ) / std::f32::consts::PI;
 
         let total_light = if raycast_info.ray_depth() <= INDIRECT_DEPTH_LIMIT {
+            let series = thread_buffer.sampler.series_2d(self.iteration as u32);
             let indirect_light = (0..self.iteration)
-                .map(|_| {
+                .map(|i| {
                     let reflect_dir = {
                         // this generate random vector with pdf ~ cos(theta)
                         // notice that it generate a vector from surface of sphere with center on the tip of normal vector
                         // It's the fact that a probability of getting a vector that lie sphere surface cross section is the same
                         // for all cross section
                         // so a pdf of vector is proportional to the length of the cross section, which is a circle with radius cos(theta)
-                        let offset = Vector3::from(UnitSphere.sample(&mut thread_buffer.rng));
+                        let offset = sphere_point(thread_buffer.get_2d(series, i as u32));
                         let random_vector = hit_info.normal.into_owned() + offset;
                         Unit::new_normalize(random_vector)
                     };
@@ -143,7 +205,21 @@ impl Material for PBRDiffuse {
             direct_light
         };
 
-        total_light.component_mul(&self.color_albedo)
+        let color = match &hit_info.color {
+            Some(vertex_color) => self.color_albedo.component_mul(vertex_color),
+            None => self.color_albedo,
+        };
+        total_light.component_mul(&color)
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        check_color(&mut problems, "color", &self.color);
+        check_non_negative(&mut problems, "albedo", self.albedo);
+        if self.iteration == 0 {
+            problems.push("`iteration` is 0, it must be at least 1".to_owned());
+        }
+        problems
     }
 }
 
@@ -208,8 +284,6 @@ impl Reflective {
         _hit_object: &SceneObject,
         raycast_info: RayCastInfo,
     ) -> Color3 {
-        use rand_distr::UnitBall;
-
         let r = self.roughness;
         let r_sq = r * r;
         let pdf_denominator = (4.0 * std::f32::consts::PI) * (r * r - (2.0 / 3.0));
@@ -217,7 +291,8 @@ impl Reflective {
         let mean_light = (0..self.iteration)
             .map(|_| {
                 let (reflect_dir, weakening_factor, cos_angle) = loop {
-                    let reflect_noise = r * Vector3::from(UnitBall.sample(&mut thread_buffer.rng));
+                    let reflect_noise =
+                        r * ball_point(thread_buffer.next_2d(), thread_buffer.next_1d());
                     let perfect_reflection =
                         helper::calculate_reflect_ray(&hit_info.incoming_dir, &hit_info.normal);
                     let reflect_dir =
@@ -322,8 +397,12 @@ impl Material for Reflective {
         hit_object: &SceneObject,
         raycast_info: RayCastInfo,
     ) -> Color3 {
-        let direct_light =
-            scene.direct_light_at(hit_info.intersection, hit_info.normal, thread_buffer);
+        let direct_light = scene.direct_light_at(
+            hit_info.intersection,
+            hit_info.normal,
+            raycast_info.time(),
+            thread_buffer,
+        );
 
         if raycast_info.ray_depth() > REFLECTION_DEPTH_LIMIT {
             return direct_light;
@@ -334,6 +413,15 @@ impl Material for Reflective {
 
         0.5 * (direct_light + indirect_light)
     }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        check_color(&mut problems, "color", &self.color);
+        if self.iteration == 0 {
+            problems.push("`iteration` is 0, it must be at least 1".to_owned());
+        }
+        problems
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -358,7 +446,12 @@ impl Material for PerfectReflective {
     ) -> Color3 {
         if raycast_info.ray_depth() > REFLECTION_DEPTH_LIMIT {
             return scene
-                .direct_light_at(hit_info.intersection, hit_info.normal, thread_buffer)
+                .direct_light_at(
+                    hit_info.intersection,
+                    hit_info.normal,
+                    raycast_info.time(),
+                    thread_buffer,
+                )
                 .component_mul(&self.color);
         }
 
@@ -374,6 +467,12 @@ impl Material for PerfectReflective {
 
         reflection_light.component_mul(&self.color)
     }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        check_color(&mut problems, "color", &self.color);
+        problems
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -388,6 +487,12 @@ pub struct Emission {
     light: Color3,
 }
 
+impl Emission {
+    pub fn new(light: Color3) -> Self {
+        Emission { light }
+    }
+}
+
 impl Material for Emission {
     fn compute_light(
         &self,
@@ -399,4 +504,353 @@ impl Material for Emission {
     ) -> Color3 {
         self.light
     }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        check_color(&mut problems, "light", &self.light);
+        problems
+    }
+}
+
+// isotropic phase function
+const PHASE_ISOTROPIC: f32 = 1.0 / (4.0 * PI);
+
+/// Scattering medium, only meaningful on `Volume` shape, which provide the density
+/// on any other shape it behave like an invisible boundary
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Medium {
+    // single scattering albedo (sigma_s / sigma_t)
+    albedo: Color3,
+}
+
+impl Material for Medium {
+    fn compute_light(
+        &self,
+        scene: &Scene,
+        thread_buffer: &mut ThreadBuffer,
+        hit_info: &HitInfo,
+        hit_object: &SceneObject,
+        raycast_info: RayCastInfo,
+    ) -> Color3 {
+        let origin = hit_info.intersection;
+        let dir = hit_info.incoming_dir;
+
+        let volume = match &hit_object.shape {
+            Shapes::Volume(volume) => volume,
+            _ => return raycast_compute_light(scene, thread_buffer, origin, dir, raycast_info),
+        };
+
+        // tracking is done in the volume's space
+        let time = raycast_info.time();
+        let (local_origin, local_dir, dist_scale) = hit_object.local_ray(origin, dir, time);
+        let exit = volume.exit_distance(local_origin, local_dir);
+        // an object inside the volume end the tracking there
+        let solid = raycast_solid(scene, origin, dir, time, thread_buffer)
+            .filter(|(hit, _)| hit.dist * dist_scale < exit);
+        let exit = solid
+            .as_ref()
+            .map_or(exit, |(hit, _)| hit.dist * dist_scale);
+        // the number of steps vary, so only the first take a sampler dimension
+        let first = thread_buffer.next_2d();
+        let collision =
+            volume.delta_tracking(local_origin, local_dir, exit, first, &mut thread_buffer.rng);
+
+        let scatter_dist = match (collision, solid) {
+            (Some(dist), _) => dist / dist_scale,
+            // pass through the medium to the object
+            (None, Some((hit, obj))) => {
+                return shade_hit(scene, thread_buffer, &hit, obj, raycast_info)
+            }
+            // pass through the volume without interacting
+            (None, None) => {
+                return raycast_compute_light(
+                    scene,
+                    thread_buffer,
+                    origin + dir.scale(exit / dist_scale),
+                    dir,
+                    raycast_info,
+                )
+            }
+        };
+
+        let scatter_point = origin + dir.scale(scatter_dist);
+        let direct_light =
+            scene.incident_light_at(scatter_point, raycast_info.time(), thread_buffer)
+                * PHASE_ISOTROPIC;
+
+        let total_light = if raycast_info.ray_depth() <= INDIRECT_DEPTH_LIMIT {
+            // sampled with pdf = phase function, so no weighting needed
+            let scatter_dir = Unit::new_normalize(sphere_point(thread_buffer.next_2d()));
+            let indirect_light = raycast_compute_light(
+                scene,
+                thread_buffer,
+                scatter_point,
+                scatter_dir,
+                raycast_info,
+            );
+            direct_light + indirect_light
+        } else {
+            direct_light
+        };
+
+        total_light.component_mul(&self.albedo)
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        check_color(&mut problems, "albedo", &self.albedo);
+        problems
+    }
+}
+
+/// Kajiya-Kay hair shading, lit along the tangent of the surface rather than the normal.
+/// Meant for `Curve`, shape without tangent use an arbitrary direction perpendicular to the normal
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Hair {
+    color: Color3,
+    specular: Color3,
+    // sharpness of the specular highlight
+    #[serde(default = "default_hair_exponent")]
+    exponent: f32,
+}
+
+fn default_hair_exponent() -> f32 {
+    40.0
+}
+
+impl Material for Hair {
+    fn compute_light(
+        &self,
+        scene: &Scene,
+        thread_buffer: &mut ThreadBuffer,
+        hit_info: &HitInfo,
+        _hit_object: &SceneObject,
+        raycast_info: RayCastInfo,
+    ) -> Color3 {
+        let pos = hit_info.intersection;
+        let tangent = hit_info
+            .tangent
+            .unwrap_or_else(|| helper::perpendicular(&hit_info.normal));
+        let to_eye = -hit_info.incoming_dir;
+
+        scene
+            .lights()
+            .iter()
+            .map(|light| {
+                let incident =
+                    light.incident_light_at(pos, raycast_info.time(), scene, thread_buffer);
+                if incident == Color3::zeros() {
+                    return incident;
+                }
+
+                let to_light = light.dir_to_light(pos);
+                let cos_tl = tangent.dot(&to_light);
+                let diffuse = (1.0 - cos_tl * cos_tl).max(0.0).sqrt() / PI;
+
+                let half_vector = Unit::new_normalize(to_light.into_inner() + to_eye.into_inner());
+                let cos_th = tangent.dot(&half_vector);
+                let specular = (1.0 - cos_th * cos_th).max(0.0).sqrt().powf(self.exponent);
+
+                incident.component_mul(&(self.color * diffuse + self.specular * specular))
+            })
+            .sum()
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        check_color(&mut problems, "color", &self.color);
+        check_color(&mut problems, "specular", &self.specular);
+        problems
+    }
+}
+
+/// Smooth transparent surface (glass, water), reflect and refract by fresnel
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Dielectric {
+    // index of refraction
+    ior: f32,
+    // tint of refracted light
+    #[serde(default = "white")]
+    color: Color3,
+}
+
+fn white() -> Color3 {
+    Color3::repeat(1.0)
+}
+
+impl Dielectric {
+    pub fn new(ior: f32, color: Color3) -> Self {
+        Dielectric { ior, color }
+    }
+}
+
+impl Material for Dielectric {
+    fn compute_light(
+        &self,
+        scene: &Scene,
+        thread_buffer: &mut ThreadBuffer,
+        hit_info: &HitInfo,
+        _hit_object: &SceneObject,
+        raycast_info: RayCastInfo,
+    ) -> Color3 {
+        if raycast_info.ray_depth() > REFLECTION_DEPTH_LIMIT {
+            return scene
+                .direct_light_at(
+                    hit_info.intersection,
+                    hit_info.normal,
+                    raycast_info.time(),
+                    thread_buffer,
+                )
+                .component_mul(&self.color);
+        }
+
+        // outward normal facing the ray = entering, a mesh turn its normal toward the ray
+        // and tell when it did
+        let incoming = &hit_info.incoming_dir;
+        let facing = incoming.dot(&hit_info.normal) < 0.0;
+        let entering = facing && !hit_info.back_face;
+        let normal = if facing {
+            hit_info.normal
+        } else {
+            -hit_info.normal
+        };
+        let eta = if entering { 1.0 / self.ior } else { self.ior };
+
+        let reflect_dir = helper::calculate_reflect_ray(incoming, &normal);
+        let mut cast = |dir| {
+            raycast_compute_light(
+                scene,
+                thread_buffer,
+                hit_info.intersection,
+                dir,
+                raycast_info,
+            )
+        };
+
+        match helper::calculate_refract_ray(incoming, &normal, eta) {
+            Some(refract_dir) => {
+                // schlick approximation, with the angle on the less dense side
+                let cos = if entering {
+                    -incoming.dot(&normal)
+                } else {
+                    -refract_dir.dot(&normal)
+                };
+                let r0 = ((1.0 - self.ior) / (1.0 + self.ior)).powi(2);
+                let fresnel = r0 + (1.0 - r0) * (1.0 - cos).powi(5);
+
+                fresnel * cast(reflect_dir)
+                    + (1.0 - fresnel) * cast(refract_dir).component_mul(&self.color)
+            }
+            // total internal reflection
+            None => cast(reflect_dir),
+        }
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        check_color(&mut problems, "color", &self.color);
+        problems
+    }
+}
+
+/// Scene's material library, shared by every object referring to it by name
+pub type MaterialLibrary = BTreeMap<String, Arc<Materials>>;
+
+/// Reference to a material of the scene's material library, written as just the name.
+/// Resolved with the library when the scene is built
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(from = "String", into = "String")]
+pub struct Named {
+    pub name: String,
+    material: Option<Arc<Materials>>,
+}
+
+impl Named {
+    /// reference already pointing to `material`, which is in the library as `name`
+    pub fn resolved(name: String, material: Arc<Materials>) -> Self {
+        Named {
+            name,
+            material: Some(material),
+        }
+    }
+
+    pub fn resolve(&mut self, library: &MaterialLibrary) -> Result<(), MaterialError> {
+        let material = library
+            .get(&self.name)
+            .ok_or_else(|| MaterialError::UnknownMaterial {
+                name: self.name.clone(),
+            })?;
+        self.material = Some(material.clone());
+        Ok(())
+    }
+}
+
+impl From<String> for Named {
+    fn from(name: String) -> Self {
+        Named {
+            name,
+            material: None,
+        }
+    }
+}
+
+impl From<Named> for String {
+    fn from(named: Named) -> Self {
+        named.name
+    }
+}
+
+impl Material for Named {
+    fn compute_light(
+        &self,
+        scene: &Scene,
+        thread_buffer: &mut ThreadBuffer,
+        hit_info: &HitInfo,
+        hit_object: &SceneObject,
+        raycast_info: RayCastInfo,
+    ) -> Color3 {
+        // `SceneBuilder::build` fail on a reference it can't resolve, a built scene has none
+        match &self.material {
+            Some(material) => {
+                material.compute_light(scene, thread_buffer, hit_info, hit_object, raycast_info)
+            }
+            None => Color3::zeros(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtracer::scene::SceneBuilder;
+    use crate::rtracer::validation::parse_ron;
+
+    #[test]
+    fn resolve_named_material() {
+        let mut library = MaterialLibrary::new();
+        library.insert(
+            "lamp".to_owned(),
+            Arc::new(Emission::new(Color3::repeat(2.0)).into()),
+        );
+
+        let mut named = Named::from("lamp".to_owned());
+        named.resolve(&library).unwrap();
+        assert!(Arc::ptr_eq(
+            named.material.as_ref().unwrap(),
+            &library["lamp"]
+        ));
+
+        let mut unknown = Named::from("wall".to_owned());
+        let error = unknown.resolve(&library).unwrap_err().to_string();
+        assert!(error.contains("`wall`"));
+
+        // building the scene resolve them, or fail
+        let text = "(materials: {\"lamp\": Emission((light: [1, 1, 1]))},
+            objects: [(material: Named(\"wall\"), shape: Sphere((pos: [0, 0, 0], radius: 1)))])";
+        let scene: SceneBuilder = parse_ron(text, "test.ron").unwrap();
+        assert!(matches!(
+            scene.build(),
+            Err(MaterialError::UnknownMaterial { .. })
+        ));
+    }
 }
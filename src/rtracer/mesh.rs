@@ -0,0 +1,445 @@
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+
+use custom_error::custom_error;
+use nalgebra::{Point2, Point3, Unit, Vector3};
+use serde::{Deserialize, Serialize};
+
+use super::bvh::BVHTree;
+use super::shape::Shape;
+use super::{Color3, HitInfo};
+use crate::utils::aabb::AABB;
+
+mod ply;
+mod stl;
+
+custom_error! { pub MeshError
+    ObjError {source: tobj::LoadError} = "Encounter error while loading obj file",
+    GltfError {source: gltf::Error} = "Encounter error while loading glTF file",
+    IOError {source: std::io::Error} = "Encounter error while reading mesh file",
+    InvalidFile {path: String, message: String} = "Invalid mesh file {path}: {message}",
+    EmptyMesh {path: String} = "Mesh file {path} contain no triangle"
+}
+
+pub(crate) fn obj_load_options() -> tobj::LoadOptions {
+    tobj::LoadOptions {
+        single_index: true,
+        triangulate: true,
+        ..Default::default()
+    }
+}
+
+/// Möller–Trumbore intersection, return distance and barycentric coordinate (u, v) of p1 and p2
+pub(crate) fn ray_triangle(
+    p0: &Point3<f32>,
+    p1: &Point3<f32>,
+    p2: &Point3<f32>,
+    origin: Point3<f32>,
+    dir: Unit<Vector3<f32>>,
+) -> Option<(f32, f32, f32)> {
+    let edge1 = p1 - p0;
+    let edge2 = p2 - p0;
+
+    let h = dir.cross(&edge2);
+    let det = edge1.dot(&h);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = det.recip();
+
+    let s = origin - p0;
+    let u = inv_det * s.dot(&h);
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let q = s.cross(&edge1);
+    let v = inv_det * dir.dot(&q);
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let dist = inv_det * edge2.dot(&q);
+    if dist <= 0.0 {
+        return None;
+    }
+    Some((dist, u, v))
+}
+
+/// Indexed triangle mesh in object space
+pub struct TriangleMesh {
+    pub positions: Vec<Point3<f32>>,
+    // per vertex normal, empty if the mesh doesn't provide one
+    pub normals: Vec<Vector3<f32>>,
+    // per vertex texture coordinate, empty if the mesh doesn't provide one
+    pub texcoords: Vec<Point2<f32>>,
+    // per vertex color, empty if the mesh doesn't provide one
+    pub colors: Vec<Color3>,
+    pub triangles: Vec<[usize; 3]>,
+    bounds: AABB,
+    // bottom level acceleration structure, leaf = index of triangle
+    bvh: BVHTree<usize>,
+}
+
+impl TriangleMesh {
+    pub fn new(
+        positions: Vec<Point3<f32>>,
+        normals: Vec<Vector3<f32>>,
+        texcoords: Vec<Point2<f32>>,
+        triangles: Vec<[usize; 3]>,
+    ) -> Option<Self> {
+        let first = *positions.first()?;
+        if triangles.is_empty() {
+            return None;
+        }
+
+        // padding prevent flat mesh from having zero thickness bounding box
+        let padding = Vector3::new(1e-4, 1e-4, 1e-4);
+        let bounds = positions
+            .iter()
+            .fold(AABB::new_uncheck(first, first), |bb, p| {
+                bb.union(&AABB::new_uncheck(*p, *p))
+            });
+        let bounds = AABB::new_uncheck(bounds.min() - padding, bounds.max() + padding);
+
+        let bvh = BVHTree::generate(triangles.iter().enumerate().map(|(i, &[a, b, c])| {
+            let bb =
+                AABB::new(positions[a], positions[b]).union(&AABB::new(positions[c], positions[c]));
+            (i, AABB::new_uncheck(bb.min() - padding, bb.max() + padding))
+        }));
+
+        Some(TriangleMesh {
+            positions,
+            normals,
+            texcoords,
+            colors: Vec::new(),
+            triangles,
+            bounds,
+            bvh,
+        })
+    }
+
+    /// give every vertex a color, `colors` is empty or has one per vertex
+    pub fn with_colors(self, colors: Vec<Color3>) -> Result<Self, String> {
+        if !colors.is_empty() && colors.len() != self.positions.len() {
+            return Err(format!(
+                "{} vertex colors for {} vertices",
+                colors.len(),
+                self.positions.len()
+            ));
+        }
+        Ok(TriangleMesh { colors, ..self })
+    }
+
+    /// load every model of an obj file into a single mesh, or only the `model`-th one
+    pub fn load_obj(path: impl AsRef<Path>, model: Option<usize>) -> Result<Self, MeshError> {
+        let path = path.as_ref();
+        let (models, _materials) = tobj::load_obj(path, &obj_load_options())?;
+
+        let models = models
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| model.map_or(true, |m| m == *i))
+            .map(|(_, model)| model.mesh);
+        TriangleMesh::from_obj_meshes(models).ok_or_else(|| MeshError::EmptyMesh {
+            path: path.display().to_string(),
+        })
+    }
+
+    /// merge meshes loaded by tobj with `obj_load_options`
+    pub(crate) fn from_obj_meshes(meshes: impl IntoIterator<Item = tobj::Mesh>) -> Option<Self> {
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut texcoords = Vec::new();
+        let mut triangles = Vec::new();
+
+        for mesh in meshes {
+            let offset = positions.len();
+            let vertex_count = mesh.positions.len() / 3;
+            let has_normal = mesh.normals.len() == 3 * vertex_count;
+            let has_texcoord = mesh.texcoords.len() == 2 * vertex_count;
+
+            positions.extend(
+                mesh.positions
+                    .chunks_exact(3)
+                    .map(|p| Point3::new(p[0], p[1], p[2])),
+            );
+            // either every vertex have a normal, or none of them do
+            if has_normal && normals.len() == offset {
+                normals.extend(
+                    mesh.normals
+                        .chunks_exact(3)
+                        .map(|n| Vector3::new(n[0], n[1], n[2])),
+                );
+            } else {
+                normals.clear();
+            }
+            if has_texcoord && texcoords.len() == offset {
+                texcoords.extend(
+                    mesh.texcoords
+                        .chunks_exact(2)
+                        .map(|t| Point2::new(t[0], t[1])),
+                );
+            } else {
+                texcoords.clear();
+            }
+            triangles.extend(mesh.indices.chunks_exact(3).map(|t| {
+                [
+                    offset + t[0] as usize,
+                    offset + t[1] as usize,
+                    offset + t[2] as usize,
+                ]
+            }));
+        }
+
+        TriangleMesh::new(positions, normals, texcoords, triangles)
+    }
+
+    /// load every triangle primitive of a glTF file into a single mesh, in their mesh's space,
+    /// or only the `model`-th primitive (counted through every mesh in order)
+    pub fn load_gltf(path: impl AsRef<Path>, model: Option<usize>) -> Result<Self, MeshError> {
+        let path = path.as_ref();
+        let (document, buffers, _images) = gltf::import(path)?;
+
+        let primitives = document
+            .meshes()
+            .flat_map(|mesh| mesh.primitives())
+            .enumerate()
+            .filter(|(i, _)| model.map_or(true, |m| m == *i))
+            .map(|(_, primitive)| primitive);
+        TriangleMesh::from_gltf_primitives(primitives, &buffers).ok_or_else(|| {
+            MeshError::EmptyMesh {
+                path: path.display().to_string(),
+            }
+        })
+    }
+
+    /// merge the triangles primitives, other primitive (points, lines, strips) are skipped.
+    /// None if there's no triangle or an index is out of range
+    pub(crate) fn from_gltf_primitives<'a>(
+        primitives: impl IntoIterator<Item = gltf::Primitive<'a>>,
+        buffers: &[gltf::buffer::Data],
+    ) -> Option<Self> {
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut texcoords = Vec::new();
+        let mut triangles = Vec::new();
+
+        for primitive in primitives {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                continue;
+            }
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let offset = positions.len();
+            positions.extend(reader.read_positions()?.map(Point3::from));
+            let vertex_count = positions.len() - offset;
+
+            // either every vertex have a normal, or none of them do
+            match reader.read_normals() {
+                Some(read) if normals.len() == offset => normals.extend(read.map(Vector3::from)),
+                _ => normals.clear(),
+            }
+            // glTF's v go downward
+            match reader.read_tex_coords(0) {
+                Some(read) if texcoords.len() == offset => {
+                    texcoords.extend(read.into_f32().map(|[u, v]| Point2::new(u, 1.0 - v)))
+                }
+                _ => texcoords.clear(),
+            }
+            let indices = match reader.read_indices() {
+                Some(read) => read.into_u32().map(|i| i as usize).collect(),
+                None => (0..vertex_count).collect::<Vec<_>>(),
+            };
+            if indices.iter().any(|&i| i >= vertex_count) {
+                return None;
+            }
+            triangles.extend(
+                indices
+                    .chunks_exact(3)
+                    .map(|t| [offset + t[0], offset + t[1], offset + t[2]]),
+            );
+        }
+
+        TriangleMesh::new(positions, normals, texcoords, triangles)
+    }
+
+    /// load PLY (ascii or binary little endian) or STL (ascii or binary) file
+    fn load_with(
+        path: &Path,
+        parse: fn(&[u8]) -> Result<Option<Self>, String>,
+    ) -> Result<Self, MeshError> {
+        let data = std::fs::read(path)?;
+        let path = path.display().to_string();
+        match parse(&data) {
+            Ok(Some(mesh)) => Ok(mesh),
+            Ok(None) => Err(MeshError::EmptyMesh { path }),
+            Err(message) => Err(MeshError::InvalidFile { path, message }),
+        }
+    }
+
+    /// load mesh file, format is chosen by extension.
+    /// `model` select a model of obj or a primitive of glTF, other formats have only one
+    pub fn load(path: impl AsRef<Path>, model: Option<usize>) -> Result<Self, MeshError> {
+        let path = path.as_ref();
+        let extension = path.extension().and_then(|e| e.to_str());
+        match extension.map(str::to_ascii_lowercase).as_deref() {
+            Some("gltf") | Some("glb") => TriangleMesh::load_gltf(path, model),
+            Some("ply") => TriangleMesh::load_with(path, ply::parse),
+            Some("stl") => TriangleMesh::load_with(path, stl::parse),
+            _ => TriangleMesh::load_obj(path, model),
+        }
+    }
+
+    /// Load mesh file, sharing the data with every other mesh loaded from the same path and model
+    pub fn load_shared(
+        path: impl AsRef<Path>,
+        model: Option<usize>,
+    ) -> Result<Arc<Self>, MeshError> {
+        let path = path.as_ref();
+        TriangleMesh::shared_with(path, model, || TriangleMesh::load(path, model))
+    }
+
+    /// Mesh of the path and model from the cache `load_shared` use, `load` is only called when
+    /// it isn't there. For importer that already parsed the file
+    pub fn shared_with<E>(
+        path: &Path,
+        model: Option<usize>,
+        load: impl FnOnce() -> Result<Self, E>,
+    ) -> Result<Arc<Self>, E> {
+        type Cache = HashMap<(PathBuf, Option<usize>), Weak<TriangleMesh>>;
+        static CACHE: Mutex<Option<Cache>> = Mutex::new(None);
+
+        let key = (
+            path.canonicalize().unwrap_or_else(|_| path.to_owned()),
+            model,
+        );
+
+        let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+        let cache = cache.get_or_insert_with(HashMap::new);
+        if let Some(mesh) = cache.get(&key).and_then(Weak::upgrade) {
+            return Ok(mesh);
+        }
+
+        let mesh = Arc::new(load()?);
+        cache.insert(key, Arc::downgrade(&mesh));
+        Ok(mesh)
+    }
+
+    pub fn bounds(&self) -> &AABB {
+        &self.bounds
+    }
+
+    pub fn intersect_triangle(
+        &self,
+        index: usize,
+        origin: Point3<f32>,
+        dir: Unit<Vector3<f32>>,
+    ) -> Option<HitInfo> {
+        let [i0, i1, i2] = self.triangles[index];
+        let (p0, p1, p2) = (self.positions[i0], self.positions[i1], self.positions[i2]);
+        let (dist, u, v) = ray_triangle(&p0, &p1, &p2, origin, dir)?;
+
+        let normal = if self.normals.is_empty() {
+            (p1 - p0).cross(&(p2 - p0))
+        } else {
+            self.normals[i0] * (1.0 - u - v) + self.normals[i1] * u + self.normals[i2] * v
+        };
+        let uv = if self.texcoords.is_empty() {
+            Point2::new(u, v)
+        } else {
+            let (t0, t1, t2) = (self.texcoords[i0], self.texcoords[i1], self.texcoords[i2]);
+            Point2::from(t0.coords * (1.0 - u - v) + t1.coords * u + t2.coords * v)
+        };
+
+        let color = if self.colors.is_empty() {
+            None
+        } else {
+            Some(self.colors[i0] * (1.0 - u - v) + self.colors[i1] * u + self.colors[i2] * v)
+        };
+
+        // triangles are double sided, normal always face toward the ray
+        let back_face = normal.dot(&dir) > 0.0;
+        let normal = Unit::new_normalize(if back_face { -normal } else { normal });
+
+        Some(HitInfo {
+            incoming_dir: dir,
+            dist,
+            intersection: origin + dir.scale(dist),
+            normal,
+            uv,
+            tangent: None,
+            color,
+            back_face,
+        })
+    }
+}
+
+impl Debug for TriangleMesh {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "TriangleMesh({} vertices, {} triangles)",
+            self.positions.len(),
+            self.triangles.len()
+        )
+    }
+}
+
+/// Triangle mesh loaded from file, same file is loaded only once and shared between meshes
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(
+    try_from = "crate::utils::proxy_serialize::MeshProxy",
+    into = "crate::utils::proxy_serialize::MeshProxy"
+)]
+pub struct Mesh {
+    // as written in the scene file, loaded relative to it
+    pub file: PathBuf,
+    // index of the only model to load, all models if none
+    pub model: Option<usize>,
+    pub data: Arc<TriangleMesh>,
+}
+
+impl Mesh {
+    pub fn load(file: PathBuf, model: Option<usize>) -> Result<Self, MeshError> {
+        let data = TriangleMesh::load_shared(&file, model)?;
+        Ok(Mesh { file, model, data })
+    }
+}
+
+impl Shape for Mesh {
+    fn intersect(&self, origin: Point3<f32>, dir: Unit<Vector3<f32>>) -> Option<HitInfo> {
+        let data = &self.data;
+        data.bvh
+            .closest_hit(origin, dir, |&i| {
+                data.intersect_triangle(i, origin, dir)
+                    .filter(|hit| hit.dist > 1e-6)
+                    .map(|hit| (hit.dist, hit))
+            })
+            .map(|(_, hit)| hit)
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.data.bounds().clone())
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let data = &self.data;
+        let positions = data
+            .positions
+            .iter()
+            .filter(|p| p.iter().any(|x| !x.is_finite()));
+        let count = positions.count();
+        if count > 0 {
+            problems.push(format!("{} vertex positions aren't finite", count));
+        }
+        // normals are interpolated, a zero one give NaN shading
+        let count = data.normals.iter().filter(|n| !(n.norm() > 1e-6)).count();
+        if count > 0 {
+            problems.push(format!("{} vertex normals have zero length", count));
+        }
+        problems
+    }
+}
@@ -0,0 +1,399 @@
+use std::convert::TryInto;
+
+use nalgebra::{Point2, Point3, Vector3};
+
+use super::TriangleMesh;
+use crate::rtracer::Color3;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+}
+
+#[derive(Clone, Copy)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Result<Self, String> {
+        Ok(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return Err(format!("unknown property type `{}`", name)),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    fn is_integer(self) -> bool {
+        !matches!(self, Scalar::F32 | Scalar::F64)
+    }
+}
+
+enum Property {
+    Scalar(Scalar),
+    // count type, item type
+    List(Scalar, Scalar),
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<(String, Property)>,
+}
+
+impl Element {
+    fn index_of(&self, name: &str) -> Option<usize> {
+        self.properties.iter().position(|(n, _)| n == name)
+    }
+}
+
+/// One row of an element, list property is flattened with its count in front
+#[derive(Default)]
+struct Row {
+    values: Vec<f64>,
+    // where each property start in `values`
+    starts: Vec<usize>,
+}
+
+impl Row {
+    fn scalar(&self, property: usize) -> f64 {
+        self.values[self.starts[property]]
+    }
+
+    fn list(&self, property: usize) -> &[f64] {
+        let start = self.starts[property];
+        let count = self.values[start] as usize;
+        &self.values[start + 1..start + 1 + count]
+    }
+}
+
+/// Value source of the body, either whitespace separated text or packed binary
+enum Body<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary(&'a [u8]),
+}
+
+impl<'a> Body<'a> {
+    fn read(&mut self, ty: Scalar) -> Result<f64, String> {
+        match self {
+            Body::Ascii(tokens) => {
+                let token = tokens.next().ok_or("unexpected end of data")?;
+                token
+                    .parse()
+                    .map_err(|_| format!("invalid number `{}`", token))
+            }
+            Body::Binary(bytes) => {
+                let size = ty.size();
+                if bytes.len() < size {
+                    return Err("unexpected end of data".to_owned());
+                }
+                let (value, rest) = bytes.split_at(size);
+                *bytes = rest;
+                Ok(match ty {
+                    Scalar::I8 => f64::from(value[0] as i8),
+                    Scalar::U8 => f64::from(value[0]),
+                    Scalar::I16 => f64::from(i16::from_le_bytes(value.try_into().unwrap())),
+                    Scalar::U16 => f64::from(u16::from_le_bytes(value.try_into().unwrap())),
+                    Scalar::I32 => f64::from(i32::from_le_bytes(value.try_into().unwrap())),
+                    Scalar::U32 => f64::from(u32::from_le_bytes(value.try_into().unwrap())),
+                    Scalar::F32 => f64::from(f32::from_le_bytes(value.try_into().unwrap())),
+                    Scalar::F64 => f64::from_le_bytes(value.try_into().unwrap()),
+                })
+            }
+        }
+    }
+
+    fn read_row(&mut self, element: &Element, row: &mut Row) -> Result<(), String> {
+        row.values.clear();
+        row.starts.clear();
+        for (_, property) in &element.properties {
+            row.starts.push(row.values.len());
+            match *property {
+                Property::Scalar(ty) => row.values.push(self.read(ty)?),
+                Property::List(count_ty, item_ty) => {
+                    let count = self.read(count_ty)?;
+                    row.values.push(count);
+                    for _ in 0..count as usize {
+                        row.values.push(self.read(item_ty)?);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_header(header: &str) -> Result<(Format, Vec<Element>), String> {
+    let mut lines = header.lines();
+    if lines.next().map(str::trim) != Some("ply") {
+        return Err("missing `ply` magic number".to_owned());
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in lines {
+        let tokens = line.split_whitespace().collect::<Vec<_>>();
+        match tokens.as_slice() {
+            ["format", "ascii", _] => format = Some(Format::Ascii),
+            ["format", "binary_little_endian", _] => format = Some(Format::BinaryLittleEndian),
+            ["format", other, _] => return Err(format!("unsupported format `{}`", other)),
+            ["element", name, count] => elements.push(Element {
+                name: (*name).to_owned(),
+                count: count
+                    .parse()
+                    .map_err(|_| format!("invalid element count `{}`", count))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count_ty, item_ty, name] => {
+                let element = elements.last_mut().ok_or("property before any element")?;
+                let property = Property::List(Scalar::parse(count_ty)?, Scalar::parse(item_ty)?);
+                element.properties.push(((*name).to_owned(), property));
+            }
+            ["property", ty, name] => {
+                let element = elements.last_mut().ok_or("property before any element")?;
+                let property = Property::Scalar(Scalar::parse(ty)?);
+                element.properties.push(((*name).to_owned(), property));
+            }
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => return Err(format!("invalid header line `{}`", line)),
+        }
+    }
+    Ok((format.ok_or("missing format")?, elements))
+}
+
+/// Parse PLY file content, polygon faces are triangulated as fan
+pub(super) fn parse(data: &[u8]) -> Result<Option<TriangleMesh>, String> {
+    const END_HEADER: &[u8] = b"end_header";
+    let header_end = data
+        .windows(END_HEADER.len())
+        .position(|w| w == END_HEADER)
+        .ok_or("missing end_header")?;
+    let body_start = data[header_end..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(data.len(), |i| header_end + i + 1);
+    let header = std::str::from_utf8(&data[..header_end]).map_err(|_| "header isn't text")?;
+    let (format, elements) = parse_header(header)?;
+
+    let body = &data[body_start..];
+    let mut body = match format {
+        Format::Ascii => Body::Ascii(
+            std::str::from_utf8(body)
+                .map_err(|_| "ascii body isn't text")?
+                .split_ascii_whitespace(),
+        ),
+        Format::BinaryLittleEndian => Body::Binary(body),
+    };
+
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut colors = Vec::new();
+    let mut texcoords = Vec::new();
+    let mut triangles = Vec::new();
+    let mut row = Row::default();
+
+    for element in &elements {
+        match element.name.as_str() {
+            "vertex" => {
+                let find = |names: [&str; 3]| -> Option<[usize; 3]> {
+                    Some([
+                        element.index_of(names[0])?,
+                        element.index_of(names[1])?,
+                        element.index_of(names[2])?,
+                    ])
+                };
+                let position = find(["x", "y", "z"]).ok_or("vertex without position")?;
+                let normal = find(["nx", "ny", "nz"]);
+                let color = find(["red", "green", "blue"]);
+                let texcoord = [("u", "v"), ("s", "t"), ("texture_u", "texture_v")]
+                    .iter()
+                    .find_map(|(u, v)| Some([element.index_of(u)?, element.index_of(v)?]));
+                // 8 bit color is 0..255, float color is 0..1
+                let color_scale = match color.map(|c| &element.properties[c[0]].1) {
+                    Some(Property::Scalar(ty)) if ty.is_integer() => 1.0 / 255.0,
+                    _ => 1.0,
+                };
+
+                for _ in 0..element.count {
+                    body.read_row(element, &mut row)?;
+                    let get = |[a, b, c]: [usize; 3]| {
+                        [
+                            row.scalar(a) as f32,
+                            row.scalar(b) as f32,
+                            row.scalar(c) as f32,
+                        ]
+                    };
+                    positions.push(Point3::from(get(position)));
+                    if let Some(normal) = normal {
+                        normals.push(Vector3::from(get(normal)));
+                    }
+                    if let Some(color) = color {
+                        colors.push(Color3::from(get(color)) * color_scale);
+                    }
+                    if let Some([u, v]) = texcoord {
+                        texcoords.push(Point2::new(row.scalar(u) as f32, row.scalar(v) as f32));
+                    }
+                }
+            }
+            "face" => {
+                let index = element
+                    .index_of("vertex_indices")
+                    .or_else(|| element.index_of("vertex_index"))
+                    .ok_or("face without vertex_indices")?;
+                for _ in 0..element.count {
+                    body.read_row(element, &mut row)?;
+                    let polygon = row.list(index);
+                    for i in 1..polygon.len().saturating_sub(1) {
+                        triangles.push([
+                            polygon[0] as usize,
+                            polygon[i] as usize,
+                            polygon[i + 1] as usize,
+                        ]);
+                    }
+                }
+            }
+            // skip unknown element (edge, material, ...)
+            _ => {
+                for _ in 0..element.count {
+                    body.read_row(element, &mut row)?;
+                }
+            }
+        }
+    }
+
+    if triangles.iter().flatten().any(|&i| i >= positions.len()) {
+        return Err("face index out of range".to_owned());
+    }
+    TriangleMesh::new(positions, normals, texcoords, triangles)
+        .map(|m| m.with_colors(colors))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+    use nalgebra::Unit;
+
+    #[test]
+    fn ascii_and_binary() {
+        let ascii = b"ply
+format ascii 1.0
+comment a quad
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 255 0 0
+1 0 0 0 255 0
+1 1 0 0 0 255
+0 1 0 255 255 255
+4 0 1 2 3
+";
+        let mesh = parse(ascii).unwrap().unwrap();
+        assert_eq!(mesh.triangles, vec![[0, 1, 2], [0, 2, 3]]);
+        assert_approx_eq!(mesh.colors[1].y, 1.0);
+        assert!(mesh.normals.is_empty());
+
+        let mut binary = b"ply
+format binary_little_endian 1.0
+element vertex 3
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+element face 1
+property uchar intensity
+property list uchar uint vertex_indices
+end_header
+"
+        .to_vec();
+        for p in &[[0.0f32, 0.0, 0.0], [2.0, 0.0, 0.0], [0.0, 2.0, 0.0]] {
+            for v in p.iter().chain(&[0.0, 0.0, 1.0]) {
+                binary.extend(&v.to_le_bytes());
+            }
+        }
+        binary.extend(&[7, 3]);
+        for i in 0u32..3 {
+            binary.extend(&i.to_le_bytes());
+        }
+        let mesh = parse(&binary).unwrap().unwrap();
+        assert_eq!(mesh.triangles, vec![[0, 1, 2]]);
+        assert_approx_eq!(mesh.positions[1].x, 2.0);
+        assert_approx_eq!(mesh.normals[2].z, 1.0);
+
+        assert!(parse(b"ply\nformat binary_big_endian 1.0\nend_header\n").is_err());
+    }
+
+    #[test]
+    fn vertex_properties_by_name() {
+        // list before the position, and y before x
+        let ascii = b"ply
+format ascii 1.0
+element vertex 3
+property list uchar float weights
+property float y
+property float x
+property float z
+property float red
+property float green
+property float blue
+element face 1
+property list uchar int vertex_indices
+end_header
+2 0.5 0.5 0 1 0 1 1 1
+0 0 2 0 0 0 0
+1 0.25 2 0 0 0 0 0
+3 0 1 2
+";
+        let mesh = parse(ascii).unwrap().unwrap();
+        assert_approx_eq!(mesh.positions[0].x, 1.0);
+        assert_approx_eq!(mesh.positions[1].x, 2.0);
+        assert_approx_eq!(mesh.positions[2].y, 2.0);
+        assert_approx_eq!(mesh.colors[0].x, 1.0);
+
+        // hit color is interpolated from the vertices
+        let hit = mesh
+            .intersect_triangle(
+                0,
+                Point3::new(1.2, 0.3, 1.0),
+                -Unit::new_normalize(Vector3::z()),
+            )
+            .unwrap();
+        assert!(hit.color.unwrap().x > 0.0);
+
+        let colors = vec![Color3::zeros()];
+        assert!(mesh.with_colors(colors).is_err());
+    }
+}
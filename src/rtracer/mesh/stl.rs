@@ -0,0 +1,107 @@
+use std::convert::TryInto;
+
+use nalgebra::Point3;
+
+use super::TriangleMesh;
+
+// 80 bytes header + triangle count
+const BINARY_HEADER: usize = 84;
+// normal, 3 vertices and attribute
+const BINARY_TRIANGLE: usize = 50;
+
+/// Parse STL file content, facet normals are ignored since they're often missing or wrong
+pub(super) fn parse(data: &[u8]) -> Result<Option<TriangleMesh>, String> {
+    // ascii file also start with "solid", so tell them apart by the size binary file must have
+    let positions = if data.len() >= BINARY_HEADER
+        && data.len()
+            == BINARY_HEADER
+                + BINARY_TRIANGLE * u32::from_le_bytes(data[80..84].try_into().unwrap()) as usize
+    {
+        parse_binary(&data[BINARY_HEADER..])
+    } else {
+        parse_ascii(std::str::from_utf8(data).map_err(|_| "neither binary nor ascii STL")?)?
+    };
+
+    let triangles = (0..positions.len() / 3)
+        .map(|i| [3 * i, 3 * i + 1, 3 * i + 2])
+        .collect();
+    Ok(TriangleMesh::new(
+        positions,
+        Vec::new(),
+        Vec::new(),
+        triangles,
+    ))
+}
+
+fn parse_binary(data: &[u8]) -> Vec<Point3<f32>> {
+    let float = |bytes: &[u8]| f32::from_le_bytes(bytes.try_into().unwrap());
+    data.chunks_exact(BINARY_TRIANGLE)
+        .flat_map(|triangle| {
+            // skip the normal
+            triangle[12..48]
+                .chunks_exact(12)
+                .map(move |v| Point3::new(float(&v[0..4]), float(&v[4..8]), float(&v[8..12])))
+        })
+        .collect()
+}
+
+fn parse_ascii(text: &str) -> Result<Vec<Point3<f32>>, String> {
+    let mut positions = Vec::new();
+    for line in text.lines() {
+        let mut tokens = line.split_whitespace();
+        if tokens.next() != Some("vertex") {
+            continue;
+        }
+        let coords = tokens
+            .map(|t| t.parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .ok()
+            .filter(|c| c.len() == 3)
+            .ok_or_else(|| format!("invalid vertex `{}`", line.trim()))?;
+        positions.push(Point3::new(coords[0], coords[1], coords[2]));
+    }
+    if positions.len() % 3 != 0 {
+        return Err("facet without 3 vertices".to_owned());
+    }
+    Ok(positions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn ascii_and_binary() {
+        let ascii = b"solid tri
+  facet normal 0 0 1
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+      vertex 0 1 0
+    endloop
+  endfacet
+endsolid tri
+";
+        let mesh = parse(ascii).unwrap().unwrap();
+        assert_eq!(mesh.triangles, vec![[0, 1, 2]]);
+        assert_approx_eq!(mesh.positions[2].y, 1.0);
+
+        // binary header may start with "solid" too
+        let mut binary = b"solid but binary".to_vec();
+        binary.resize(80, 0);
+        binary.extend(&2u32.to_le_bytes());
+        for z in &[0.0f32, 5.0] {
+            binary.extend(&[0; 12]);
+            for v in &[[0.0f32, 0.0], [1.0, 0.0], [0.0, 1.0]] {
+                for c in &[v[0], v[1], *z] {
+                    binary.extend(&c.to_le_bytes());
+                }
+            }
+            binary.extend(&[0, 0]);
+        }
+        let mesh = parse(&binary).unwrap().unwrap();
+        assert_eq!(mesh.triangles.len(), 2);
+        assert_approx_eq!(mesh.positions[4].z, 5.0);
+    }
+}
@@ -0,0 +1,436 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use custom_error::custom_error;
+use serde::{Deserialize, Serialize};
+
+use super::material::{Dielectric, Diffuse, Emission, PerfectReflective, Reflective};
+use super::mesh::{obj_load_options, Mesh, MeshError, TriangleMesh};
+use super::texture::{ImageTexture, TextureError};
+use super::transform::Transform;
+use super::{Color3, Materials, SceneObject};
+
+custom_error! { pub ObjImportError
+    ObjError {source: tobj::LoadError} = "Encounter error while loading obj file",
+    IOError {source: io::Error} = "Encounter error while reading obj file",
+    TextureError {source: TextureError} = "Encounter error while loading material texture",
+    MeshError {source: MeshError} = "Encounter error while building mesh"
+}
+
+// Diffuse's share of skylight for imported material
+const IMPORT_DIFFUSE_ALBEDO: f32 = 0.2;
+// sample of glossy reflection
+const IMPORT_GLOSSY_ITERATION: usize = 16;
+// shininess above this is a perfect mirror
+const IMPORT_MIRROR_SHININESS: f32 = 1000.0;
+
+/// Statement of an .mtl file that couldn't be translated, reported to the user as warning
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub warnings: Vec<String>,
+}
+
+impl ImportReport {
+    fn warn(&mut self, source: &str, line: usize, message: impl std::fmt::Display) {
+        self.warnings
+            .push(format!("{}:{}: {}", source, line, message));
+    }
+
+    pub(crate) fn warn_file(&mut self, source: &str, message: impl std::fmt::Display) {
+        self.warnings.push(format!("{}: {}", source, message));
+    }
+}
+
+/// Part of a material in .mtl file this renderer understand, also the common ground for
+/// translating other formats' material
+#[derive(Debug, Clone)]
+pub struct MtlMaterial {
+    pub name: String,
+    // Kd
+    pub diffuse: Color3,
+    // Ks
+    pub specular: Color3,
+    // Ns
+    pub shininess: f32,
+    // Ni
+    pub ior: f32,
+    // d, or 1 - Tr
+    pub dissolve: f32,
+    // Ke
+    pub emission: Color3,
+    // map_Kd, relative to the working directory
+    pub diffuse_map: Option<PathBuf>,
+}
+
+impl MtlMaterial {
+    pub fn new(name: String) -> Self {
+        MtlMaterial {
+            name,
+            diffuse: Color3::repeat(0.8),
+            specular: Color3::zeros(),
+            shininess: 0.0,
+            ior: 1.5,
+            dissolve: 1.0,
+            emission: Color3::zeros(),
+            diffuse_map: None,
+        }
+    }
+
+    /// Pick the renderer material closest to this one.
+    ///
+    /// There's no layered material, so the strongest part win:
+    /// emissive > transparent (dielectric) > specular stronger than diffuse (glossy) > diffuse
+    pub fn to_material(&self) -> Result<Materials, TextureError> {
+        if self.emission.max() > 0.0 {
+            return Ok(Emission::new(self.emission).into());
+        }
+        if self.dissolve < 1.0 {
+            // Kd tint the transmitted light, like a colored glass
+            return Ok(Dielectric::new(self.ior, self.diffuse).into());
+        }
+        if self.specular.max() > self.diffuse.max() {
+            if self.shininess >= IMPORT_MIRROR_SHININESS {
+                return Ok(PerfectReflective::new(self.specular).into());
+            }
+            // same mapping as Blender's principled BSDF import
+            let roughness = (2.0 / (self.shininess.max(0.0) + 2.0)).sqrt();
+            return Ok(Reflective::new(self.specular, roughness, IMPORT_GLOSSY_ITERATION).into());
+        }
+        Ok(match &self.diffuse_map {
+            Some(file) => Diffuse::with_texture(
+                self.diffuse,
+                IMPORT_DIFFUSE_ALBEDO,
+                ImageTexture::load(file.clone())?,
+            ),
+            None => Diffuse::new(self.diffuse, IMPORT_DIFFUSE_ALBEDO),
+        }
+        .into())
+    }
+}
+
+fn parse_floats<T: FromStr>(args: &[&str], count: usize) -> Option<Vec<T>> {
+    if args.len() != count {
+        return None;
+    }
+    args.iter().map(|a| a.parse().ok()).collect()
+}
+
+fn parse_color(args: &[&str]) -> Option<Color3> {
+    // a single value is grey
+    match args.len() {
+        1 => parse_floats(args, 1).map(|v: Vec<f32>| Color3::repeat(v[0])),
+        _ => parse_floats(args, 3).map(|v: Vec<f32>| Color3::new(v[0], v[1], v[2])),
+    }
+}
+
+/// Parse .mtl text, texture paths are resolved against `base_dir`.
+/// `source` name the file in the report
+pub fn parse_mtl(
+    text: &str,
+    base_dir: &Path,
+    source: &str,
+    report: &mut ImportReport,
+) -> Vec<MtlMaterial> {
+    let mut materials: Vec<MtlMaterial> = Vec::new();
+    // unsupported statement are reported once per file
+    let mut reported = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let line_number = i + 1;
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args = tokens.collect::<Vec<_>>();
+
+        if keyword == "newmtl" {
+            materials.push(MtlMaterial::new(args.join(" ")));
+            continue;
+        }
+        let material = match materials.last_mut() {
+            Some(material) => material,
+            None => {
+                report.warn(
+                    source,
+                    line_number,
+                    format!("`{}` before any newmtl", keyword),
+                );
+                continue;
+            }
+        };
+
+        let parsed = match keyword {
+            "Kd" => parse_color(&args).map(|c| material.diffuse = c),
+            "Ks" => parse_color(&args).map(|c| material.specular = c),
+            "Ke" => parse_color(&args).map(|c| material.emission = c),
+            "Ns" => parse_floats(&args, 1).map(|v| material.shininess = v[0]),
+            "Ni" => parse_floats(&args, 1).map(|v| material.ior = v[0]),
+            "d" => parse_floats(&args, 1).map(|v| material.dissolve = v[0]),
+            "Tr" => parse_floats(&args, 1).map(|v: Vec<f32>| material.dissolve = 1.0 - v[0]),
+            // texture option (-s, -o, ...) aren't supported, only the plain file name
+            "map_Kd" if args.len() == 1 => {
+                material.diffuse_map = Some(base_dir.join(args[0]));
+                Some(())
+            }
+            _ => {
+                let statement = if keyword == "map_Kd" {
+                    "map_Kd with options".to_owned()
+                } else {
+                    keyword.to_owned()
+                };
+                if !reported.contains(&statement) {
+                    report.warn(
+                        source,
+                        line_number,
+                        format!("unsupported statement `{}`", statement),
+                    );
+                    reported.push(statement);
+                }
+                Some(())
+            }
+        };
+        if parsed.is_none() {
+            report.warn(source, line_number, format!("invalid `{}` statement", line));
+        }
+    }
+    materials
+}
+
+/// Load every model of an obj file as its own scene object,
+/// with material translated from the obj's mtl libraries
+pub fn load_obj_objects(
+    path: &Path,
+    report: &mut ImportReport,
+) -> Result<Vec<SceneObject>, ObjImportError> {
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    // tobj's material don't keep the statements it doesn't know, parse the libraries ourselves
+    let mut materials = HashMap::new();
+    for line in fs::read_to_string(path)?.lines() {
+        let line = line.trim();
+        if !line.starts_with("mtllib") {
+            continue;
+        }
+        for library in line.split_whitespace().skip(1) {
+            let mtl_path = base_dir.join(library);
+            let source = mtl_path.display().to_string();
+            match fs::read_to_string(&mtl_path) {
+                Ok(text) => {
+                    let mtl_dir = mtl_path.parent().unwrap_or(base_dir);
+                    for material in parse_mtl(&text, mtl_dir, &source, report) {
+                        materials.insert(material.name.clone(), material);
+                    }
+                }
+                Err(e) => report.warn_file(&source, format!("can't read material library ({})", e)),
+            }
+        }
+    }
+
+    let (models, tobj_materials) = tobj::load_obj(path, &obj_load_options())?;
+    let names = tobj_materials
+        .map(|m| m.into_iter().map(|m| m.name).collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    let source = path.display().to_string();
+    models
+        .into_iter()
+        .enumerate()
+        .filter(|(_, model)| !model.mesh.indices.is_empty())
+        .map(|(i, model)| {
+            let name = model.mesh.material_id.and_then(|id| names.get(id)).cloned();
+            let material = match name.as_ref().and_then(|name| materials.get(name)) {
+                Some(material) => material.to_material()?,
+                None => {
+                    if let Some(name) = name {
+                        report.warn_file(&source, format!("unknown material `{}`", name));
+                    }
+                    MtlMaterial::new(String::new()).to_material()?
+                }
+            };
+            // same cache as the mesh shape, the file's model is only kept once
+            let data = TriangleMesh::shared_with(path, Some(i), || {
+                TriangleMesh::from_obj_meshes(Some(model.mesh)).ok_or_else(|| {
+                    MeshError::EmptyMesh {
+                        path: source.clone(),
+                    }
+                })
+            })?;
+            let mesh = Mesh {
+                file: path.to_owned(),
+                model: Some(i),
+                data,
+            };
+            Ok(SceneObject::new(mesh, material))
+        })
+        .collect()
+}
+
+/// Obj file with its materials, in the scene file. Each model become a scene object
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(try_from = "crate::utils::proxy_serialize::ObjAssetProxy")]
+pub struct ObjAsset {
+    // as written in the scene file, loaded relative to it
+    pub file: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transform: Option<Transform>,
+    #[serde(skip)]
+    pub objects: Vec<SceneObject>,
+    // what couldn't be translated, for the loader to show
+    #[serde(skip)]
+    pub report: ImportReport,
+}
+
+impl ObjAsset {
+    /// load the asset, warnings are kept in its `report`
+    pub fn load(file: PathBuf, transform: Option<Transform>) -> Result<Self, ObjImportError> {
+        let mut report = ImportReport::default();
+        let mut objects = load_obj_objects(&file, &mut report)?;
+        for obj in &mut objects {
+            obj.transform = transform.clone();
+        }
+        Ok(ObjAsset {
+            file,
+            transform,
+            objects,
+            report,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtracer::renderer::raycast_compute_light;
+    use crate::rtracer::scene::SceneBuilder;
+    use crate::rtracer::thread_buffer::ThreadBuffer;
+    use crate::rtracer::validation::parse_ron;
+    use crate::rtracer::RayCastInfo;
+    use assert_approx_eq::assert_approx_eq;
+    use nalgebra::{Point3, Unit, Vector3};
+
+    const MTL: &str = "\
+# exported
+newmtl glass
+Kd 0.8 0.8 0.8
+Ni 1.45
+d 0.2
+illum 4
+
+newmtl lamp
+Ke 4 4 3
+
+newmtl metal
+Kd 0.1
+Ks 0.9 0.9 0.9
+Ns 200
+illum 2
+map_Bump -bm 0.5 bump.png
+
+newmtl wood
+Kd 0.6 0.4 0.2
+Kd oops
+";
+
+    #[test]
+    fn parse_mtl_statements() {
+        let mut report = ImportReport::default();
+        let materials = parse_mtl(MTL, Path::new("assets"), "test.mtl", &mut report);
+        assert_eq!(materials.len(), 4);
+        assert_approx_eq!(materials[0].ior, 1.45);
+        assert_approx_eq!(materials[0].dissolve, 0.2);
+        assert_approx_eq!(materials[2].diffuse.y, 0.1);
+        assert_approx_eq!(materials[3].diffuse.x, 0.6);
+
+        // illum reported only once, bad Kd reported with its line
+        assert_eq!(
+            report.warnings,
+            vec![
+                "test.mtl:6: unsupported statement `illum`",
+                "test.mtl:16: unsupported statement `map_Bump`",
+                "test.mtl:20: invalid `Kd oops` statement",
+            ]
+        );
+    }
+
+    #[test]
+    fn material_mapping() {
+        let mut report = ImportReport::default();
+        let materials = parse_mtl(MTL, Path::new(""), "test.mtl", &mut report);
+        let kinds = materials
+            .iter()
+            .map(|m| m.to_material().unwrap())
+            .collect::<Vec<_>>();
+        assert!(matches!(kinds[0], Materials::Dielectric(_)));
+        assert!(matches!(kinds[1], Materials::Emission(_)));
+        assert!(matches!(kinds[2], Materials::Reflective(_)));
+        assert!(matches!(kinds[3], Materials::Diffuse(_)));
+    }
+
+    #[test]
+    fn ray_through_glass_mesh() {
+        // closed cube of glass, wound outward, ray must refract back out parallel to itself
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        fs::write(
+            dir.join("glass.mtl"),
+            "newmtl glass\nKd 1 1 1\nNi 1.5\nd 0\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("cube.obj"),
+            "\
+mtllib glass.mtl
+v -1 -1 -1
+v 1 -1 -1
+v 1 1 -1
+v -1 1 -1
+v -1 -1 1
+v 1 -1 1
+v 1 1 1
+v -1 1 1
+usemtl glass
+f 1 4 3
+f 1 3 2
+f 5 6 7
+f 5 7 8
+f 1 2 6
+f 1 6 5
+f 4 8 7
+f 4 7 3
+f 1 5 8
+f 1 8 4
+f 2 3 7
+f 2 7 6
+",
+        )
+        .unwrap();
+
+        let mut report = ImportReport::default();
+        let cube = load_obj_objects(&dir.join("cube.obj"), &mut report).unwrap();
+        assert!(matches!(cube[0].material, Materials::Dielectric(_)));
+
+        // lamp on the ray shifted by the slab, missed when the ray bend the wrong way inside
+        let mut scene: SceneBuilder = parse_ron(
+            "(objects: [(shape: Sphere((pos: [5, 1.59, 0], radius: 0.3)), \
+             material: Emission((light: [1, 1, 1])))])",
+            "test.ron",
+        )
+        .unwrap();
+        scene.objects.extend(cube);
+        let scene = scene.build().unwrap();
+
+        let color = raycast_compute_light(
+            &scene,
+            &mut ThreadBuffer::default(),
+            Point3::new(-5.0, -1.2, 0.0),
+            Unit::new_normalize(Vector3::new(1.0, 0.3, 0.0)),
+            RayCastInfo::at_time(0.0),
+        );
+        assert!(color.x > 0.8, "lamp not reached, got {}", color);
+    }
+}
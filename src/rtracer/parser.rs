@@ -3,20 +3,32 @@ use std::io;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 
+use nalgebra::{Point3, Rotation3};
 use ron::ser::{to_string_pretty, PrettyConfig};
-use serde::{Deserialize, Deserializer, Serialize};
+use serde::{Deserialize, Serialize};
 
 use custom_error::custom_error;
 
+use super::animation::Animation;
+use super::gltf_import::{load_gltf, GltfImportError};
+use super::material::MaterialError;
+use super::obj_import::ImportReport;
+use super::sampler::SamplerKind;
+use super::validation::{
+    parse_ron, parse_ron_with, validate_config, validate_scene, ValidationReport,
+};
 use super::{Camera, Scene};
 use crate::rtracer::scene::SceneBuilder;
+use crate::utils::overrides::Override;
+use crate::utils::proxy_serialize::with_base_dir;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize)]
 pub struct SceneData {
-    #[serde(deserialize_with = "deserialize_scene")]
     pub scene: Scene,
     pub camera: Camera,
     pub config: RenderConfig,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub animation: Option<Animation>,
 }
 
 #[derive(Serialize, Deserialize, Default)]
@@ -27,23 +39,306 @@ pub struct ColorMapConfig {
     pub gamma: Option<f32>,
 }
 
+/// Render in passes accumulated in a float buffer, `samples_per_pixel` is still the most to take
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ProgressiveConfig {
+    // samples per pixel of each pass
+    pub pass_samples: u32,
+    // seconds between writing the image so far to the output file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub write_interval: Option<f32>,
+    // stop after the pass that go over this many seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_budget: Option<f32>,
+    // stop when every pixel's relative variance (see `PixelEstimate`) is below it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variance_threshold: Option<f32>,
+}
+
+impl Default for ProgressiveConfig {
+    fn default() -> Self {
+        ProgressiveConfig {
+            pass_samples: 1,
+            write_interval: None,
+            time_budget: None,
+            variance_threshold: None,
+        }
+    }
+}
+
+/// Samples go where the pixel estimate is noisiest, `samples_per_pixel` becomes the average.
+/// Pixels below the progressive `variance_threshold` get no more samples
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct AdaptiveConfig {
+    // every pixel get them first so its variance can be estimated
+    pub min_samples: u32,
+    // default to 4 times samples_per_pixel
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_samples: Option<u32>,
+    // write the samples each pixel got as a grayscale image next to the output
+    pub heatmap: bool,
+}
+
+impl Default for AdaptiveConfig {
+    fn default() -> Self {
+        AdaptiveConfig {
+            min_samples: 4,
+            max_samples: None,
+            heatmap: false,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct RenderConfig {
+    // image width, and height too unless `image_height` is given
     pub image_size: u32,
-    pub viewport_size: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_height: Option<u32>,
+    pub viewport_size: f32,
     pub output_file: PathBuf,
     #[serde(default)]
     pub color_map: ColorMapConfig,
+    #[serde(default = "default_samples_per_pixel")]
+    pub samples_per_pixel: u32,
+    // time interval (open, close) the camera see, (0, 0) = no motion blur
+    #[serde(default)]
+    pub shutter: (f32, f32),
+    #[serde(default)]
+    pub seed: u64,
+    #[serde(default)]
+    pub sampler: SamplerKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progressive: Option<ProgressiveConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adaptive: Option<AdaptiveConfig>,
+}
+
+impl RenderConfig {
+    pub fn image_dimensions(&self) -> (u32, u32) {
+        (
+            self.image_size,
+            self.image_height.unwrap_or(self.image_size),
+        )
+    }
+
+    // viewport_size is the image width in scene unit
+    pub fn unit_per_pixel(&self) -> f32 {
+        self.viewport_size / self.image_size as f32
+    }
+
+    // samples the sampler spread evenly together, adaptive sampling may stop a pixel after them
+    pub fn stratified_samples(&self) -> u32 {
+        match &self.adaptive {
+            Some(adaptive) => adaptive.min_samples,
+            None => self.samples_per_pixel,
+        }
+    }
+
+    // most samples a pixel can get
+    pub fn max_samples_per_pixel(&self) -> u32 {
+        match &self.adaptive {
+            Some(adaptive) => adaptive.max_samples.unwrap_or(4 * self.samples_per_pixel),
+            None => self.samples_per_pixel,
+        }
+    }
+}
+
+fn default_samples_per_pixel() -> u32 {
+    1
+}
+
+// scene file as written, before includes and material references are resolved
+#[derive(Deserialize)]
+struct SceneFile {
+    scene: SceneBuilder,
+    camera: Camera,
+    config: RenderConfig,
+    #[serde(default)]
+    animation: Option<Animation>,
 }
 
 custom_error! { pub SceneParserError
     RonSerdeError {source: ron::error::Error} = "Encounter error while (de)serializing scene data",
-    IOError {source: io::Error} = "Encounter error while opening file"
+    IOError {source: io::Error} = "Encounter error while opening file",
+    GltfImportError {source: GltfImportError} = "Encounter error while importing glTF file",
+    MaterialError {source: MaterialError} = "Encounter error while resolving material reference",
+    InvalidScene {report: ValidationReport} = "{report}"
 }
 
+/// load ron scene file, or .gltf/.glb with default render config
 pub fn load_scene_data(path: impl AsRef<Path>) -> Result<SceneData, SceneParserError> {
-    let scene_data = ron::de::from_reader(fs::File::open(path)?)?;
-    Ok(scene_data)
+    load_scene_data_with(path, &[])
+}
+
+/// `load_scene_data` with the value at some paths of the scene file replaced,
+/// see [`crate::utils::overrides`]
+pub fn load_scene_data_with(
+    path: impl AsRef<Path>,
+    overrides: &[Override],
+) -> Result<SceneData, SceneParserError> {
+    let path = path.as_ref();
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("gltf") | Some("glb") => {
+            let data = load_gltf_scene_data(path)?;
+            if overrides.is_empty() {
+                return Ok(data);
+            }
+            // there is no file to override, go through its ron form instead
+            let text = to_string_pretty(&data, PrettyConfig::default())?;
+            scene_data_from_ron(&text, path, overrides)
+        }
+        _ => scene_data_from_ron(&fs::read_to_string(path)?, path, overrides),
+    }
+}
+
+/// Load a ron scene file with its includes, checking every file.
+/// Problem that still allow the rest to be checked is collected, they're all reported together
+fn scene_data_from_ron(
+    text: &str,
+    path: &Path,
+    overrides: &[Override],
+) -> Result<SceneData, SceneParserError> {
+    let source = path.display().to_string();
+    let mut report = ValidationReport::default();
+    let key = path.canonicalize()?;
+    let dir = key.parent().unwrap_or_else(|| Path::new(""));
+    let file: SceneFile = match with_base_dir(dir, || parse_ron_with(text, &source, overrides)) {
+        Ok(file) => file,
+        Err(problem) => {
+            report.problems.push(problem);
+            return Err(SceneParserError::InvalidScene { report });
+        }
+    };
+
+    let mut scene = file.scene;
+    print_asset_warnings(&scene);
+    validate_scene(&scene, &source, &mut report);
+    validate_config(&file.config, &source, &mut report);
+    let mut stack = vec![key.clone()];
+    load_includes(&mut scene, dir, &mut stack, &mut report);
+
+    if !report.is_empty() {
+        return Err(SceneParserError::InvalidScene { report });
+    }
+    let scene = match scene.build() {
+        Ok(scene) => scene,
+        Err(e) => {
+            report.problem(&source, e);
+            return Err(SceneParserError::InvalidScene { report });
+        }
+    };
+    if let Some(Err(e)) = file.animation.as_ref().map(|a| a.check(&scene)) {
+        report.problem(&source, format_args!("animation: {}", e));
+        return Err(SceneParserError::InvalidScene { report });
+    }
+    Ok(SceneData {
+        scene,
+        camera: file.camera,
+        config: file.config,
+        animation: file.animation,
+    })
+}
+
+/// Merge the scene's included files into it, recursively, checking each of them.
+/// Include path is relative to `dir`, the including file's directory,
+/// like every other file path in that file.
+/// `stack` is the chain of files currently being included, used to detect cycle
+fn load_includes(
+    scene: &mut SceneBuilder,
+    dir: &Path,
+    stack: &mut Vec<PathBuf>,
+    report: &mut ValidationReport,
+) {
+    let including = stack
+        .last()
+        .map(|p| p.display().to_string())
+        .unwrap_or_default();
+    for include in std::mem::take(&mut scene.include) {
+        let path = dir.join(&include.file);
+        let source = path.display().to_string();
+
+        let text = match path
+            .canonicalize()
+            .and_then(|key| Ok((key, fs::read_to_string(&path)?)))
+        {
+            Ok((key, _)) if stack.contains(&key) => {
+                let chain = stack
+                    .iter()
+                    .chain(Some(&key))
+                    .map(|p| p.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(" -> ");
+                report.problem(&including, format_args!("include cycle: {}", chain));
+                continue;
+            }
+            Ok((key, text)) => {
+                stack.push(key);
+                text
+            }
+            Err(e) => {
+                report.problem(&including, format_args!("can't include {} ({})", source, e));
+                continue;
+            }
+        };
+
+        let key = stack.last().cloned().unwrap_or_default();
+        let dir = key.parent().unwrap_or_else(|| Path::new(""));
+        match with_base_dir(dir, || parse_ron::<SceneBuilder>(&text, &source)) {
+            Ok(mut included) => {
+                print_asset_warnings(&included);
+                validate_scene(&included, &source, report);
+                load_includes(&mut included, dir, stack, report);
+                if let Err(e) = scene.merge(included, &key, include.transform.as_ref()) {
+                    report.problem(&including, e);
+                }
+            }
+            Err(problem) => report.problems.push(problem),
+        }
+        stack.pop();
+    }
+}
+
+// importer's warnings don't stop the scene from loading, they're only shown
+fn print_warnings(report: &ImportReport) {
+    for warning in &report.warnings {
+        eprintln!("warning: {}", warning);
+    }
+}
+
+fn print_asset_warnings(scene: &SceneBuilder) {
+    for asset in &scene.assets {
+        print_warnings(&asset.report);
+    }
+}
+
+fn load_gltf_scene_data(path: &Path) -> Result<SceneData, SceneParserError> {
+    let imported = load_gltf(path)?;
+    print_warnings(&imported.report);
+
+    let camera = imported
+        .camera
+        .unwrap_or_else(|| Camera::new(Point3::origin(), Rotation3::identity()));
+    Ok(SceneData {
+        scene: imported.scene.build()?,
+        camera,
+        config: RenderConfig {
+            image_size: 512,
+            image_height: None,
+            viewport_size: imported.viewport_size.unwrap_or(1.0),
+            output_file: path.with_extension("png"),
+            color_map: ColorMapConfig::default(),
+            samples_per_pixel: default_samples_per_pixel(),
+            shutter: (0.0, 0.0),
+            seed: 0,
+            sampler: SamplerKind::default(),
+            progressive: None,
+            adaptive: None,
+        },
+        animation: None,
+    })
 }
 
 pub fn save_scene_data(path: impl AsRef<Path>, scene: &SceneData) -> Result<(), SceneParserError> {
@@ -52,13 +347,6 @@ pub fn save_scene_data(path: impl AsRef<Path>, scene: &SceneData) -> Result<(),
     Ok(())
 }
 
-fn deserialize_scene<'de, D>(deserializer: D) -> Result<Scene, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    SceneBuilder::deserialize(deserializer).map(|b| b.build())
-}
-
 pub mod serde_interface {
     use nalgebra::{Point3, Rotation3};
     use serde::{Deserialize, Serialize};
@@ -89,3 +377,314 @@ pub mod serde_interface {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtracer::helper::perpendicular;
+    use crate::rtracer::light::Light;
+    use crate::rtracer::Shape;
+    use crate::utils::fuzzing::unit_vector_strategy;
+    use assert_approx_eq::assert_approx_eq;
+    use nalgebra::{Similarity3, Vector3};
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use proptest::test_runner::{Config, TestRunner};
+
+    const MAIN: &str = r#"(
+        scene: (
+            include: ["rig/lights.ron", (file: "rig/lights.ron", transform: Some(TRS(translation: [0, 0, 2])))],
+            objects: [(material: Named("lamp"), shape: Sphere((pos: [2, 0, 0], radius: 0.5)))],
+            skylight: [0, 0, 0],
+        ),
+        camera: (pos: [0, 0, 0], forward: [1, 0, 0], right: [0, 1, 0], up: [0, 0, 1]),
+        config: (image_size: 8, viewport_size: 1, output_file: "out.png"),
+    )"#;
+
+    // material library shared by including it, relative include back to the main file is a cycle.
+    // Mesh path is relative to this file
+    const LIGHTS: &str = r#"(
+        materials: {"lamp": Emission((light: [1, 1, 1]))},
+        objects: [(material: Named("lamp"), shape: Mesh((file: "tri.ply")))],
+        lights: [PointLight((pos: [1, 0, 0], light: [1, 1, 1]))],
+    )"#;
+
+    const TRIANGLE: &str = "ply
+format ascii 1.0
+element vertex 3
+property float x
+property float y
+property float z
+element face 1
+property list uchar int vertex_indices
+end_header
+0 5 0
+1 5 0
+0 6 0
+3 0 1 2
+";
+
+    #[test]
+    fn include_transform_and_cycle() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        fs::create_dir(dir.join("rig")).unwrap();
+        fs::write(dir.join("main.ron"), MAIN).unwrap();
+        fs::write(dir.join("rig/lights.ron"), LIGHTS).unwrap();
+        fs::write(dir.join("rig/tri.ply"), TRIANGLE).unwrap();
+
+        let data = load_scene_data(dir.join("main.ron")).unwrap();
+        let lights = data.scene.lights();
+        assert_eq!(lights.len(), 2);
+        assert_approx_eq!(lights[0].dir_to_light(Point3::origin()).x, 1.0);
+        assert_approx_eq!(lights[1].dir_to_light(Point3::new(1.0, 0.0, 0.0)).z, 1.0);
+
+        // main file's object keep its index, included ones follow
+        assert_eq!(data.scene.object_count(), 3);
+        let min = |i| *data.scene.object(i).bounding_box().unwrap().min();
+        assert_approx_eq!(min(0).x, 1.5, 1e-3);
+        assert_approx_eq!(min(1).y, 5.0, 1e-3);
+        assert_approx_eq!(min(2).z, 2.0, 1e-3);
+
+        // the same name in two files is ambiguous
+        let lamp = "scene: (\n            materials: {\"lamp\": Emission((light: [2, 2, 2]))},";
+        fs::write(dir.join("main.ron"), MAIN.replacen("scene: (", lamp, 1)).unwrap();
+        match load_scene_data(dir.join("main.ron")) {
+            Err(SceneParserError::InvalidScene { report }) => {
+                assert!(report
+                    .problems
+                    .iter()
+                    .any(|p| p.contains("more than one scene file")));
+            }
+            _ => panic!("material defined twice isn't reported"),
+        }
+
+        fs::write(dir.join("rig/lights.ron"), r#"(include: ["../main.ron"])"#).unwrap();
+        fs::write(
+            dir.join("main.ron"),
+            MAIN.replacen("\"rig/lights.ron\", ", "", 1),
+        )
+        .unwrap();
+        match load_scene_data(dir.join("main.ron")) {
+            Err(SceneParserError::InvalidScene { report }) => {
+                assert!(report.problems.iter().any(|p| p.contains("include cycle")));
+            }
+            _ => panic!("include cycle isn't detected"),
+        }
+    }
+
+    // generated scene is written in the form `save_scene_data` write, so the saved file must match it
+
+    fn point() -> impl Strategy<Value = [f32; 3]> {
+        [-10.0f32..10.0, -10.0f32..10.0, -10.0f32..10.0]
+    }
+
+    fn color() -> impl Strategy<Value = [f32; 3]> {
+        [0.0f32..4.0, 0.0f32..4.0, 0.0f32..4.0]
+    }
+
+    fn unit() -> impl Strategy<Value = [f32; 3]> {
+        unit_vector_strategy().prop_map(|v| [v.x, v.y, v.z])
+    }
+
+    fn plane() -> impl Strategy<Value = String> {
+        (point(), unit_vector_strategy(), 0.1f32..5.0, 0.1f32..5.0).prop_map(|(pos, norm, a, b)| {
+            let span = perpendicular(&norm);
+            let cospan = norm.cross(&span).scale(b);
+            let span = span.scale(a);
+            format!(
+                "(pos: {:?}, norm: {:?}, span: {:?}, cospan: Some({:?}))",
+                pos,
+                [norm.x, norm.y, norm.z],
+                [span.x, span.y, span.z],
+                [cospan.x, cospan.y, cospan.z]
+            )
+        })
+    }
+
+    fn sphere() -> impl Strategy<Value = String> {
+        (point(), 0.01f32..5.0)
+            .prop_map(|(pos, r)| format!("Sphere((pos: {:?}, radius: {:?}))", pos, r))
+    }
+
+    fn shape(mesh: &Path) -> impl Strategy<Value = String> {
+        let mesh = format!("Mesh((file: {:?}))", mesh);
+        let flat = prop_oneof![
+            sphere(),
+            plane().prop_map(|p| format!("Plane({})", p)),
+            (point(), unit(), 0.1f32..5.0).prop_map(|(pos, n, r)| {
+                format!("Disc((pos: {:?}, norm: {:?}, radius: {:?}))", pos, n, r)
+            }),
+            // rotation vector shorter than pi, longer one is saved as the equivalent short one
+            (
+                point(),
+                [0.1f32..5.0, 0.1f32..5.0, 0.1f32..5.0],
+                [-1.5f32..1.5, -1.5f32..1.5, -1.5f32..1.5]
+            )
+                .prop_map(|(c, size, r)| {
+                    format!(
+                        "Cuboid((center: {:?}, size: {:?}, rotation: {:?}))",
+                        c, size, r
+                    )
+                }),
+            Just(mesh),
+        ];
+        let solid = prop_oneof![
+            (point(), point(), 0.1f32..2.0, any::<bool>()).prop_map(|(s, e, r, capped)| format!(
+                "Cylinder((start: {:?}, end: {:?}, radius: {:?}, capped: {}))",
+                s, e, r, capped
+            )),
+            (point(), point(), 0.1f32..2.0, 0.0f32..2.0, any::<bool>()).prop_map(
+                |(s, e, r0, r1, capped)| format!(
+                    "Cone((start: {:?}, end: {:?}, start_radius: {:?}, end_radius: {:?}, capped: {}))",
+                    s, e, r0, r1, capped
+                )
+            ),
+            (point(), point(), 0.1f32..2.0).prop_map(|(s, e, r)| {
+                format!("Capsule((start: {:?}, end: {:?}, radius: {:?}))", s, e, r)
+            }),
+            (point(), unit(), 1.0f32..3.0, 0.1f32..1.0).prop_map(|(c, axis, major, minor)| format!(
+                "Torus((center: {:?}, axis: {:?}, major_radius: {:?}, minor_radius: {:?}))",
+                c, axis, major, minor
+            )),
+            (sphere(), sphere()).prop_map(|(a, b)| {
+                format!("Csg((operation: Difference, left: {}, right: {}))", a, b)
+            }),
+            (point(), 0.1f32..2.0, 0.0f32..1.0).prop_map(|(c, r, k)| format!(
+                "Sdf((min: [-1, -1, -1], max: [1, 1, 1], node: SmoothUnion(a: Sphere(center: {:?}, radius: {:?}), \
+                 b: Box(center: [0, 0, 0], size: [1, 1, 1], rounding: 0), k: {:?}), max_steps: 256, epsilon: 0.0001))",
+                c, r, k
+            )),
+        ];
+        prop_oneof![flat, solid]
+    }
+
+    fn material() -> impl Strategy<Value = String> {
+        prop_oneof![
+            Just(r#"Named("white")"#.to_owned()),
+            color().prop_map(|c| format!("Emission((light: {:?}))", c)),
+            (color(), 0.0f32..1.0, 1usize..20).prop_map(|(c, albedo, iteration)| format!(
+                "PBRDiffuse((color: {:?}, albedo: {:?}, iteration: {}))",
+                c, albedo, iteration
+            )),
+            (color(), 0.0f32..1.0)
+                .prop_map(|(c, albedo)| format!("Diffuse((color: {:?}, albedo: {:?}))", c, albedo)),
+            (color(), 0.0f32..1.0, 1usize..20).prop_map(|(c, roughness, iteration)| format!(
+                "Reflective((color: {:?}, roughness: {:?}, iteration: {}))",
+                c, roughness, iteration
+            )),
+            color().prop_map(|c| format!("PerfectReflective((color: {:?}))", c)),
+            (1.0f32..2.5, color())
+                .prop_map(|(ior, c)| format!("Dielectric((ior: {:?}, color: {:?}))", ior, c)),
+        ]
+    }
+
+    // transform is saved as its matrix
+    fn transform() -> impl Strategy<Value = String> {
+        prop_oneof![
+            Just(String::new()),
+            (point(), point(), 0.1f32..3.0).prop_map(|(t, r, s)| {
+                let m = Similarity3::new(Vector3::from(t), Vector3::from(r), s).to_homogeneous();
+                let rows = (0..4)
+                    .map(|i| {
+                        format!(
+                            "({:?}, {:?}, {:?}, {:?})",
+                            m[(i, 0)],
+                            m[(i, 1)],
+                            m[(i, 2)],
+                            m[(i, 3)]
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                format!("transform: Some(Matrix(({}))),", rows.join(", "))
+            }),
+        ]
+    }
+
+    fn object(mesh: &Path) -> impl Strategy<Value = String> {
+        (material(), shape(mesh), transform()).prop_map(|(material, shape, transform)| {
+            format!("(material: {}, shape: {}, {})", material, shape, transform)
+        })
+    }
+
+    fn light() -> impl Strategy<Value = String> {
+        prop_oneof![
+            (point(), color())
+                .prop_map(|(pos, c)| format!("PointLight((pos: {:?}, light: {:?}))", pos, c)),
+            (unit(), color())
+                .prop_map(|(d, c)| format!("DirectionalLight((dir: {:?}, light: {:?}))", d, c)),
+            (plane(), color())
+                .prop_map(|(p, c)| format!("AreaLight((plane: {}, light: {:?}))", p, c)),
+        ]
+    }
+
+    // ron text equal up to float rounding
+    fn assert_same_ron(a: &str, b: &str) {
+        let tokens = |s: &str| {
+            s.split(|c: char| c.is_whitespace() || "()[]{},:".contains(c))
+                .filter(|t| !t.is_empty())
+                .map(str::to_owned)
+                .collect::<Vec<_>>()
+        };
+        let (a, b) = (tokens(a), tokens(b));
+        assert_eq!(a.len(), b.len(), "{:?} != {:?}", a, b);
+        for (x, y) in a.iter().zip(&b) {
+            match (x.parse::<f32>(), y.parse::<f32>()) {
+                (Ok(x), Ok(y)) => {
+                    assert!((x - y).abs() <= 1e-4 * (1.0 + x.abs()), "{} != {}", x, y)
+                }
+                _ => assert_eq!(x, y),
+            }
+        }
+    }
+
+    #[test]
+    fn save_load_round_trip() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        fs::create_dir(dir.join("meshes")).unwrap();
+        fs::write(dir.join("meshes/tri.ply"), TRIANGLE).unwrap();
+        let mut runner = TestRunner::new(Config::with_cases(64));
+        // relative path must be saved as written, not resolved against the scene file
+        let mesh = prop_oneof![
+            object(&dir.join("meshes/tri.ply")),
+            object(Path::new("meshes/tri.ply")),
+        ];
+        let scene = (vec(mesh, 1..8), vec(light(), 0..4));
+        runner
+            .run(&scene, |(objects, lights)| {
+                let text = format!(
+                    r#"(
+                        scene: (
+                            materials: {{"white": Diffuse((color: [1, 1, 1], albedo: 0.5))}},
+                            objects: [{}],
+                            lights: [{}],
+                            skylight: [0.1, 0.2, 0.3],
+                        ),
+                        camera: (pos: [0, 0, 0], forward: [1, 0, 0], right: [0, 1, 0], up: [0, 0, 1], velocity: [0, 0, 0]),
+                        config: (
+                            image_size: 8, viewport_size: 1, output_file: "out.png",
+                            color_map: (v_min: None, v_max: None, gamma: None),
+                            samples_per_pixel: 1, shutter: (0, 0), seed: 0, sampler: Independent,
+                        ),
+                    )"#,
+                    objects.join(", "),
+                    lights.join(", ")
+                );
+                fs::write(dir.join("scene.ron"), &text).unwrap();
+                let data = load_scene_data(dir.join("scene.ron")).unwrap();
+                assert_eq!(data.scene.object_count(), objects.len());
+
+                // nothing lost or changed by loading then saving
+                save_scene_data(dir.join("saved.ron"), &data).unwrap();
+                let saved = fs::read_to_string(dir.join("saved.ron")).unwrap();
+                assert_same_ron(&text, &saved);
+
+                let reloaded = load_scene_data(dir.join("saved.ron")).unwrap();
+                save_scene_data(dir.join("resaved.ron"), &reloaded).unwrap();
+                assert_same_ron(&saved, &fs::read_to_string(dir.join("resaved.ron")).unwrap());
+                Ok(())
+            })
+            .unwrap();
+    }
+}
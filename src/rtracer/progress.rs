@@ -0,0 +1,258 @@
+use std::io::{stdout, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use custom_error::custom_error;
+use serde::Serialize;
+
+use super::stats::RenderStats;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ProgressMode {
+    // updating line with percentage, rays/s and ETA
+    Human,
+    // one json object per line on stdout, nothing else is printed there
+    Json,
+    Quiet,
+}
+
+custom_error! { pub ProgressModeError
+    Unknown {mode: String} = "Unknown progress mode `{mode}`, expected human, json or quiet"
+}
+
+impl FromStr for ProgressMode {
+    type Err = ProgressModeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(ProgressMode::Human),
+            "json" => Ok(ProgressMode::Json),
+            "quiet" => Ok(ProgressMode::Quiet),
+            _ => Err(ProgressModeError::Unknown { mode: s.to_owned() }),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event<'a> {
+    Progress {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        frame: Option<u32>,
+        percent: f32,
+        rays_per_second: f32,
+        elapsed: f32,
+        eta: f32,
+    },
+    Done {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        frame: Option<u32>,
+        rays_per_second: f32,
+        elapsed: f32,
+    },
+    Saved {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        frame: Option<u32>,
+        file: &'a Path,
+    },
+    Stats {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        frame: Option<u32>,
+        #[serde(flatten)]
+        stats: &'a RenderStats,
+    },
+}
+
+/// Progress of one render, updated from the render threads.
+/// A report is printed at most every `interval` ms by whichever thread advance after it
+pub struct Progress {
+    mode: ProgressMode,
+    frame: Option<u32>,
+    // pixel samples
+    total: u64,
+    done: AtomicU64,
+    rays: AtomicU64,
+    // ms since start when the next report is due
+    next_report: AtomicU64,
+    interval: u64,
+    start: Instant,
+    // seconds the render is allowed to run, caps the ETA
+    budget: Option<f32>,
+}
+
+impl Progress {
+    /// `total` is the number of pixel samples, the render may stop earlier
+    pub fn new(mode: ProgressMode, total: u64) -> Self {
+        let interval = if mode == ProgressMode::Json {
+            1000
+        } else {
+            250
+        };
+        Progress {
+            mode,
+            frame: None,
+            total: total.max(1),
+            done: AtomicU64::new(0),
+            rays: AtomicU64::new(0),
+            // rate is meaningless right at the start
+            next_report: AtomicU64::new(interval),
+            interval,
+            start: Instant::now(),
+            budget: None,
+        }
+    }
+
+    pub fn with_budget(mut self, budget: Option<f32>) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    pub fn with_frame(mut self, frame: u32) -> Self {
+        self.frame = Some(frame);
+        self
+    }
+
+    /// `rays` is every ray cast for the `samples` rendered
+    pub fn advance(&self, samples: u64, rays: u64) {
+        if self.mode == ProgressMode::Quiet {
+            return;
+        }
+        self.rays.fetch_add(rays, Ordering::Relaxed);
+        let done = self.done.fetch_add(samples, Ordering::Relaxed) + samples;
+        let elapsed = self.start.elapsed().as_millis() as u64;
+        let due = self.next_report.load(Ordering::Relaxed);
+        if elapsed >= due
+            && self
+                .next_report
+                .compare_exchange(
+                    due,
+                    elapsed + self.interval,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+        {
+            self.report(done);
+        }
+    }
+
+    fn rays_per_second(&self) -> f32 {
+        let elapsed = self.start.elapsed().as_secs_f32().max(1e-3);
+        self.rays.load(Ordering::Relaxed) as f32 / elapsed
+    }
+
+    fn report(&self, done: u64) {
+        let fraction = done as f32 / self.total as f32;
+        let elapsed = self.start.elapsed().as_secs_f32();
+        let eta = if done > 0 {
+            elapsed / fraction - elapsed
+        } else {
+            0.0
+        };
+        let eta = self
+            .budget
+            .map_or(eta, |budget| eta.min(budget - elapsed).max(0.0));
+        let rays_per_second = self.rays_per_second();
+        match self.mode {
+            ProgressMode::Human => {
+                print!(
+                    "\r{:5.1}% | {} rays/s | ETA {}   ",
+                    fraction * 100.0,
+                    human_count(rays_per_second),
+                    human_duration(eta)
+                );
+                stdout().flush().unwrap();
+            }
+            ProgressMode::Json => print_event(&Event::Progress {
+                frame: self.frame,
+                percent: fraction * 100.0,
+                rays_per_second,
+                elapsed,
+                eta,
+            }),
+            ProgressMode::Quiet => {}
+        }
+    }
+
+    pub fn finish(&self) {
+        let elapsed = self.start.elapsed().as_secs_f32();
+        let rays_per_second = self.rays_per_second();
+        match self.mode {
+            ProgressMode::Human => println!(
+                "\rRendering Finish In {:.2}s ({} rays/s)          ",
+                elapsed,
+                human_count(rays_per_second)
+            ),
+            ProgressMode::Json => print_event(&Event::Done {
+                frame: self.frame,
+                rays_per_second,
+                elapsed,
+            }),
+            ProgressMode::Quiet => {}
+        }
+    }
+
+    pub fn stats(&self, stats: &RenderStats) {
+        match self.mode {
+            ProgressMode::Human => println!("{}", stats),
+            ProgressMode::Json => print_event(&Event::Stats {
+                frame: self.frame,
+                stats,
+            }),
+            ProgressMode::Quiet => {}
+        }
+    }
+
+    pub fn saved(&self, file: &Path) {
+        match self.mode {
+            ProgressMode::Human => println!("Saving to {}", file.display()),
+            ProgressMode::Json => print_event(&Event::Saved {
+                frame: self.frame,
+                file,
+            }),
+            ProgressMode::Quiet => {}
+        }
+    }
+}
+
+fn print_event(event: &Event) {
+    let mut out = stdout();
+    serde_json::to_writer(&mut out, event).unwrap();
+    writeln!(out).unwrap();
+    out.flush().unwrap();
+}
+
+fn human_count(x: f32) -> String {
+    match x {
+        x if x >= 1e9 => format!("{:.1}G", x / 1e9),
+        x if x >= 1e6 => format!("{:.1}M", x / 1e6),
+        x if x >= 1e3 => format!("{:.1}k", x / 1e3),
+        x => format!("{:.0}", x),
+    }
+}
+
+fn human_duration(secs: f32) -> String {
+    let secs = secs.round() as u64;
+    match secs {
+        s if s >= 3600 => format!("{}h{:02}m", s / 3600, s / 60 % 60),
+        s if s >= 60 => format!("{}m{:02}s", s / 60, s % 60),
+        s => format!("{}s", s),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_count_and_duration() {
+        assert_eq!(human_count(950.0), "950");
+        assert_eq!(human_count(2_500_000.0), "2.5M");
+        assert_eq!(human_duration(42.4), "42s");
+        assert_eq!(human_duration(3725.0), "1h02m");
+        assert_eq!("json".parse::<ProgressMode>().unwrap(), ProgressMode::Json);
+        assert!("loud".parse::<ProgressMode>().is_err());
+    }
+}
@@ -1,11 +1,20 @@
 #[derive(Copy, Clone)]
 pub struct RayCastInfo {
     ray_number: usize,
+    // time the ray is cast, shared by every secondary ray of the same camera ray
+    time: f32,
 }
 
 impl RayCastInfo {
     pub fn new() -> Self {
-        RayCastInfo { ray_number: 0 }
+        RayCastInfo::at_time(0.0)
+    }
+
+    pub fn at_time(time: f32) -> Self {
+        RayCastInfo {
+            ray_number: 0,
+            time,
+        }
     }
 
     pub fn increment_ray_number(&mut self) {
@@ -15,4 +24,8 @@ impl RayCastInfo {
     pub fn ray_depth(&self) -> usize {
         self.ray_number
     }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
 }
@@ -6,7 +6,6 @@ use image::Rgb;
 use itertools::Itertools;
 use itertools::MinMaxResult::*;
 use nalgebra::{Point3, Unit, Vector3};
-use rand::prelude::Rng;
 use rand::SeedableRng;
 
 use crate::rtracer::{material::Material, RayCastInfo, SceneObject};
@@ -16,54 +15,173 @@ use super::Color3;
 use super::HitInfo;
 
 use super::scene::Scene;
-use super::shape::Shape;
 
+use crate::rtracer::accumulator::{Accumulator, RenderBuffer};
 use crate::rtracer::geometric::Shapes;
-use crate::rtracer::parser::ColorMapConfig;
+use crate::rtracer::parser::{ProgressiveConfig, RenderConfig};
+use crate::rtracer::progress::Progress;
+use crate::rtracer::stats::RenderStats;
 use crate::rtracer::thread_buffer::ThreadBuffer;
 use ordered_float::OrderedFloat;
 use rayon::prelude::*;
+use std::cell::Cell;
 use std::ops::RangeBounds;
+use std::time::Instant;
 
 pub type RenderImage = ImageBuffer<Rgb<u8>, Vec<u8>>;
-type RenderBuffer = ImageBuffer<Rgb<f32>, Vec<f32>>;
 
-// TODO: extract per-ray render part for improving usability
 pub fn render(
     scene: &Scene,
     camera: &Camera,
-    image_size: u32,
-    unit_per_pixel: f32,
-    color_map_config: &ColorMapConfig,
-) -> RenderImage {
-    let mut img: RenderBuffer = ImageBuffer::new(image_size, image_size);
-    let raycast_info = RayCastInfo::new();
-
-    let half_width = img.width() / 2;
-    let half_height = img.height() / 2;
-
-    img.enumerate_pixels_mut().par_bridge().for_each_with(
-        ThreadBuffer::default(),
-        |thread_buffer, (px, py, pixel)| {
-            // get ray from camera
-            let ray_dir =
-                camera.ray_at_pixel_position(px, py, unit_per_pixel, half_width, half_height);
-
-            // raycast!
-            let light =
-                raycast_compute_light(scene, thread_buffer, camera.pos, ray_dir, raycast_info);
-            *pixel = Rgb([light[0], light[1], light[2]]);
+    config: &RenderConfig,
+    progress: &Progress,
+) -> (RenderImage, RenderStats) {
+    let (accumulator, stats) = render_progressive(scene, camera, config, progress, |_| ());
+    (to_image(&accumulator, config), stats)
+}
+
+/// Render in passes of samples added to a float buffer, until one of the stop condition of
+/// `config.progressive` is met, `intermediate` is given the image so far every `write_interval`.
+/// Without progressive config it's a single pass of every sample.
+/// With `config.adaptive`, after `min_samples` each pass split its samples between pixels by
+/// their variance, until there's `samples_per_pixel` on average or no pixel need more
+pub fn render_progressive(
+    scene: &Scene,
+    camera: &Camera,
+    config: &RenderConfig,
+    progress: &Progress,
+    mut intermediate: impl FnMut(&RenderImage),
+) -> (Accumulator, RenderStats) {
+    let (width, height) = config.image_dimensions();
+    let adaptive = config.adaptive.as_ref();
+    let progressive = config.progressive.clone().unwrap_or(ProgressiveConfig {
+        // adaptive sampling need passes to look at the variance in between
+        pass_samples: if adaptive.is_some() {
+            1
+        } else {
+            config.samples_per_pixel
         },
-    );
+        ..ProgressiveConfig::default()
+    });
+    let mut accumulator = Accumulator::new(width, height);
+    let pixel_count = width as u64 * height as u64;
+    let budget = pixel_count * config.samples_per_pixel as u64;
+
+    let start_time = Instant::now();
+    let mut last_write = start_time;
+    let mut stats = RenderStats::default();
+    let mut taken = 0;
+    while taken < budget {
+        let remaining = budget - taken;
+        let pass = progressive.pass_samples.max(1) as u64;
+        let counts = match adaptive {
+            Some(_) if taken > 0 => accumulator.allocate(
+                remaining.min(pass * pixel_count),
+                config.max_samples_per_pixel(),
+                progressive.variance_threshold,
+            ),
+            Some(adaptive) => vec![adaptive.min_samples; pixel_count as usize],
+            None => vec![pass.min(remaining / pixel_count) as u32; pixel_count as usize],
+        };
+        let pass_taken: u64 = counts.iter().map(|&c| c as u64).sum();
+        if pass_taken == 0 {
+            break;
+        }
+        let pass_stats = render_pass(scene, camera, config, &mut accumulator, &counts, progress);
+        stats.merge(&pass_stats);
+        taken += pass_taken;
+
+        let out_of_time = progressive
+            .time_budget
+            .map_or(false, |budget| start_time.elapsed().as_secs_f32() >= budget);
+        let converged = progressive
+            .variance_threshold
+            .map_or(false, |threshold| accumulator.converged(threshold));
+        if out_of_time || converged {
+            break;
+        }
+        if let Some(interval) = progressive.write_interval {
+            if taken < budget && last_write.elapsed().as_secs_f32() >= interval {
+                intermediate(&to_image(&accumulator, config));
+                last_write = Instant::now();
+            }
+        }
+    }
+    stats.render_time = start_time.elapsed().as_secs_f32();
+    progress.finish();
+
+    (accumulator, stats)
+}
 
-    // map from f32 image to u8 image
+// render the next `counts[i]` samples of each pixel, sample index decide its random numbers so
+// the result is the same however the samples are split into passes
+fn render_pass(
+    scene: &Scene,
+    camera: &Camera,
+    config: &RenderConfig,
+    accumulator: &mut Accumulator,
+    counts: &[u32],
+    progress: &Progress,
+) -> RenderStats {
+    let (width, height) = accumulator.dimensions();
+    let unit_per_pixel = config.unit_per_pixel();
+    let (open, close) = scene.shutter();
+
+    let half_width = width / 2;
+    let half_height = height / 2;
+
+    // every thread's buffer is kept to merge their stats
+    accumulator
+        .pixels_mut()
+        .par_iter_mut()
+        .zip(counts)
+        .enumerate()
+        .filter(|(_, (_, &count))| count > 0)
+        .fold(
+            || ThreadBuffer::with_sampler(config.sampler, config.stratified_samples()),
+            |mut thread_buffer, (i, (pixel, &count))| {
+                let (px, py) = (i as u32 % width, i as u32 / width);
+                // get ray from camera
+                let ray_dir =
+                    camera.ray_at_pixel_position(px, py, unit_per_pixel, half_width, half_height);
+                let rays_before = thread_buffer.stats.rays();
+
+                // raycast! each sample see the scene at random time in the shutter interval
+                let first = pixel.samples();
+                for sample in first..first + count {
+                    thread_buffer.seed_sample(config.seed, px, py, sample);
+                    let time = if open < close {
+                        open + (close - open) * thread_buffer.next_1d()
+                    } else {
+                        open
+                    };
+                    let origin = camera.pos_at(time);
+                    let raycast_info = RayCastInfo::at_time(time);
+                    pixel.add(raycast_compute_light(
+                        scene,
+                        &mut thread_buffer,
+                        origin,
+                        ray_dir,
+                        raycast_info,
+                    ));
+                }
+                progress.advance(count as u64, thread_buffer.stats.rays() - rays_before);
+                thread_buffer
+            },
+        )
+        .map(|thread_buffer| thread_buffer.stats)
+        .reduce(RenderStats::default, RenderStats::merged)
+}
+
+// map from f32 image to u8 image
+pub fn to_image(accumulator: &Accumulator, config: &RenderConfig) -> RenderImage {
+    let color_map_config = &config.color_map;
     color_map(
-        img,
+        accumulator.image(),
         color_map_config.v_min,
         color_map_config.v_max,
         color_map_config.gamma,
     )
-    // color_map(img, None, None)
     // TODO: post process with dither and blur
 }
 
@@ -74,20 +192,36 @@ pub fn raycast_compute_light(
     dir: Unit<Vector3<f32>>,
     info: RayCastInfo,
 ) -> Color3 {
-    let mut info = info.clone();
+    if info.ray_depth() == 0 {
+        thread_buffer.stats.camera_rays += 1;
+    } else {
+        thread_buffer.stats.indirect_rays += 1;
+    }
 
-    if let Some((hit, obj_ref)) =
-        raycast_return_ref(scene, origin, dir, &mut thread_buffer.bvh_buffer)
+    if let Some((hit, obj_ref)) = raycast_return_ref(scene, origin, dir, info.time(), thread_buffer)
     {
-        info.increment_ray_number();
-        obj_ref
-            .material
-            .compute_light(scene, thread_buffer, &hit, &obj_ref, info)
+        shade_hit(scene, thread_buffer, &hit, obj_ref, info)
     } else {
         scene.get_skylight()
     }
 }
 
+/// light leaving the hit back along the ray, `info` is the ray's
+pub fn shade_hit(
+    scene: &Scene,
+    thread_buffer: &mut ThreadBuffer,
+    hit: &HitInfo,
+    obj_ref: &SceneObject,
+    mut info: RayCastInfo,
+) -> Color3 {
+    info.increment_ray_number();
+    let stats = &mut thread_buffer.stats;
+    stats.max_depth = stats.max_depth.max(info.ray_depth());
+    obj_ref
+        .material
+        .compute_light(scene, thread_buffer, hit, obj_ref, info)
+}
+
 // TODO: maybe change to input array of Color3?
 fn color_map(
     buf: RenderBuffer,
@@ -151,10 +285,11 @@ fn color_map(
 fn raycast_shapes<'a>(
     origin: Point3<f32>,
     dir: Unit<Vector3<f32>>,
-    shapes: impl Iterator<Item = &'a Shapes>,
+    time: f32,
+    scene_objects: impl Iterator<Item = &'a SceneObject>,
 ) -> Option<HitInfo> {
-    shapes
-        .filter_map(|shape| shape.intersect(origin, dir))
+    scene_objects
+        .filter_map(|obj| obj.intersect_at(origin, dir, time))
         .filter(|x| x.dist > 1e-6)
         .min_by_key(|a| OrderedFloat(a.dist))
 }
@@ -162,19 +297,24 @@ fn raycast_shapes<'a>(
 fn raycast_shapes_return_ref<'a>(
     origin: Point3<f32>,
     dir: Unit<Vector3<f32>>,
+    time: f32,
     scene_objects: impl Iterator<Item = &'a SceneObject>,
+    stats: &mut RenderStats,
 ) -> Option<(HitInfo, &'a SceneObject)> {
-    scene_objects
-        .map(|obj| (&obj.shape, obj))
-        .filter_map(|(shape, obj)| Some((shape.intersect(origin, dir)?, obj)))
+    let hit = scene_objects
+        .inspect(|_| stats.primitive_tests += 1)
+        .filter_map(|obj| Some((obj.intersect_at(origin, dir, time)?, obj)))
         .filter(|(x, _)| x.dist > 1e-6)
-        .min_by_key(|(a, _)| OrderedFloat(a.dist))
+        .min_by_key(|(a, _)| OrderedFloat(a.dist));
+    stats.collect_nested_traversal();
+    hit
 }
 
 fn raycast_bounded(
     scene: &Scene,
     origin: Point3<f32>,
     dir: Unit<Vector3<f32>>,
+    time: f32,
     t_span: &impl RangeBounds<f32>,
     bvh_buffer: &mut Vec<usize>,
 ) -> Option<HitInfo> {
@@ -187,45 +327,58 @@ fn raycast_bounded(
         )
         // safe because bounded is monotonically increasing vector
         // TODO: type encode monotonically incresing vector
-        .map(|index| &unsafe { bounded.get_unchecked(index) }.shape);
-    raycast_shapes(origin, dir, valid_obj)
+        .map(|index| unsafe { bounded.get_unchecked(index) });
+    raycast_shapes(origin, dir, time, valid_obj)
 }
 
 fn raycast_bounded_return_ref<'a>(
     scene: &'a Scene,
     origin: Point3<f32>,
     dir: Unit<Vector3<f32>>,
+    time: f32,
     t_span: &impl RangeBounds<f32>,
-    bvh_buffer: &mut Vec<usize>,
+    keep: impl Fn(&&'a SceneObject) -> bool,
+    thread_buffer: &mut ThreadBuffer,
 ) -> Option<(HitInfo, &'a SceneObject)> {
+    let ThreadBuffer {
+        bvh_buffer, stats, ..
+    } = thread_buffer;
     let bounded = scene.bounded();
+    let visited = Cell::new(0);
     let valid_obj = scene
         .bvh()
         .query_leaf(
-            |node| !node.bounding_box.does_ray_hit(origin, dir, t_span),
+            |node| {
+                visited.set(visited.get() + 1);
+                !node.bounding_box.does_ray_hit(origin, dir, t_span)
+            },
             bvh_buffer,
         )
         // safe because bounded is monotonically increasing vector
-        .map(|index| unsafe { bounded.get_unchecked(index) });
-    raycast_shapes_return_ref(origin, dir, valid_obj)
+        .map(|index| unsafe { bounded.get_unchecked(index) })
+        .filter(keep);
+    let hit = raycast_shapes_return_ref(origin, dir, time, valid_obj, stats);
+    stats.bvh_nodes_visited += visited.get();
+    hit
 }
 
 pub fn raycast(
     scene: &Scene,
     origin: Point3<f32>,
     dir: Unit<Vector3<f32>>,
+    time: f32,
     bvh_buffer: &mut Vec<usize>,
 ) -> Option<HitInfo> {
     let unbounded_hit: Option<HitInfo> =
-        raycast_shapes(origin, dir, scene.unbounded().iter().map(|obj| &obj.shape));
+        raycast_shapes(origin, dir, time, scene.unbounded().iter());
 
     // TODO: unchecked index
     let _bounded = scene.bounded();
     let _bvh = scene.bvh();
     if let Some(hit) = unbounded_hit {
-        raycast_bounded(scene, origin, dir, &(0.0..hit.dist), bvh_buffer).or(Some(hit))
+        raycast_bounded(scene, origin, dir, time, &(0.0..hit.dist), bvh_buffer).or(Some(hit))
     } else {
-        raycast_bounded(scene, origin, dir, &(..), bvh_buffer)
+        raycast_bounded(scene, origin, dir, time, &(..), bvh_buffer)
     }
 }
 
@@ -233,14 +386,268 @@ pub fn raycast_return_ref<'a>(
     scene: &'a Scene,
     origin: Point3<f32>,
     dir: Unit<Vector3<f32>>,
-    bvh_buffer: &mut Vec<usize>,
+    time: f32,
+    thread_buffer: &mut ThreadBuffer,
 ) -> Option<(HitInfo, &'a SceneObject)> {
-    let unbounded_hit = raycast_shapes_return_ref(origin, dir, scene.unbounded().iter());
+    raycast_filtered(scene, origin, dir, time, |_| true, thread_buffer)
+}
+
+/// Nearest hit that isn't a volume, a medium's tracking stop at an object inside it
+pub fn raycast_solid<'a>(
+    scene: &'a Scene,
+    origin: Point3<f32>,
+    dir: Unit<Vector3<f32>>,
+    time: f32,
+    thread_buffer: &mut ThreadBuffer,
+) -> Option<(HitInfo, &'a SceneObject)> {
+    let solid = |obj: &&SceneObject| !matches!(obj.shape, Shapes::Volume(_));
+    raycast_filtered(scene, origin, dir, time, solid, thread_buffer)
+}
+
+fn raycast_filtered<'a>(
+    scene: &'a Scene,
+    origin: Point3<f32>,
+    dir: Unit<Vector3<f32>>,
+    time: f32,
+    keep: impl Fn(&&'a SceneObject) -> bool + Copy,
+    thread_buffer: &mut ThreadBuffer,
+) -> Option<(HitInfo, &'a SceneObject)> {
+    let unbounded_hit = raycast_shapes_return_ref(
+        origin,
+        dir,
+        time,
+        scene.unbounded().iter().filter(keep),
+        &mut thread_buffer.stats,
+    );
 
     // TODO: unchecked index
     if let Some(hit) = unbounded_hit {
-        raycast_bounded_return_ref(scene, origin, dir, &(0.0..hit.0.dist), bvh_buffer).or(Some(hit))
+        raycast_bounded_return_ref(
+            scene,
+            origin,
+            dir,
+            time,
+            &(0.0..hit.0.dist),
+            keep,
+            thread_buffer,
+        )
+        .or(Some(hit))
     } else {
-        raycast_bounded_return_ref(scene, origin, dir, &(..), bvh_buffer)
+        raycast_bounded_return_ref(scene, origin, dir, time, &(..), keep, thread_buffer)
+    }
+}
+
+/// Fraction of light that travel from `origin` to distance `max_dist` along `dir`
+///
+/// opaque object block the light completely, while volumes attenuate it (estimated by ratio tracking)
+pub fn raycast_transmittance(
+    scene: &Scene,
+    mut origin: Point3<f32>,
+    dir: Unit<Vector3<f32>>,
+    mut max_dist: f32,
+    time: f32,
+    thread_buffer: &mut ThreadBuffer,
+) -> f32 {
+    let mut transmittance = 1.0;
+    thread_buffer.stats.shadow_rays += 1;
+
+    while let Some((hit, obj_ref)) = raycast_return_ref(scene, origin, dir, time, thread_buffer) {
+        if hit.dist >= max_dist {
+            break;
+        }
+
+        match &obj_ref.shape {
+            Shapes::Volume(volume) => {
+                // tracking is done in the volume's space
+                let (local_origin, local_dir, dist_scale) =
+                    obj_ref.local_ray(hit.intersection, dir, time);
+                let remaining = max_dist - hit.dist;
+                let exit = volume
+                    .exit_distance(local_origin, local_dir)
+                    .min(remaining * dist_scale);
+                // an object inside the volume block the light like anywhere else
+                let solid = raycast_solid(scene, hit.intersection, dir, time, thread_buffer);
+                if solid.map_or(false, |(solid, _)| solid.dist * dist_scale < exit) {
+                    return 0.0;
+                }
+                let first = thread_buffer.next_1d();
+                transmittance *= volume.ratio_tracking(
+                    local_origin,
+                    local_dir,
+                    exit,
+                    first,
+                    &mut thread_buffer.rng,
+                );
+                if transmittance <= 0.0 {
+                    return 0.0;
+                }
+
+                // continue tracing from where the ray leave the volume
+                let exit = exit / dist_scale;
+                origin = hit.intersection + dir.scale(exit);
+                max_dist = remaining - exit;
+            }
+            _ => return 0.0,
+        }
+    }
+
+    transmittance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtracer::parser::{AdaptiveConfig, ColorMapConfig};
+    use crate::rtracer::progress::ProgressMode;
+    use crate::rtracer::sampler::SamplerKind;
+    use crate::rtracer::scene::SceneBuilder;
+    use crate::rtracer::validation::parse_ron;
+    use nalgebra::Rotation3;
+
+    fn test_scene() -> (Scene, Camera, RenderConfig) {
+        let scene: SceneBuilder = parse_ron(
+            r#"(
+                objects: [
+                    (shape: Sphere((pos: [3, 0, 0], radius: 0.5)),
+                     material: PBRDiffuse((color: [1, 0.5, 0.5], albedo: 0.8, iteration: 2))),
+                    (shape: Sphere((pos: [3, 0, -100.5], radius: 100)),
+                     material: PBRDiffuse((color: [0.5, 0.5, 1], albedo: 0.8, iteration: 2))),
+                ],
+                lights: [AreaLight((plane: (pos: [2, 0, 2], norm: [0, 0, -1], span: [0.5, 0, 0]), light: [4, 4, 4]))],
+            )"#,
+            "test.ron",
+        )
+        .unwrap();
+        let camera = Camera::new(Point3::origin(), Rotation3::identity());
+        let config = RenderConfig {
+            image_size: 12,
+            image_height: None,
+            viewport_size: 1.0,
+            output_file: "out.png".into(),
+            color_map: ColorMapConfig {
+                v_min: Some(0.0),
+                v_max: Some(1.0),
+                gamma: None,
+            },
+            samples_per_pixel: 3,
+            shutter: (0.0, 0.0),
+            seed: 7,
+            sampler: SamplerKind::Sobol,
+            progressive: None,
+            adaptive: None,
+        };
+        (scene.build().unwrap(), camera, config)
+    }
+
+    #[test]
+    fn object_inside_volume() {
+        // empty medium around a sphere, which must still be seen and cast shadow
+        let scene: SceneBuilder = parse_ron(
+            r#"(
+                objects: [
+                    (shape: Volume((min: [1, -2, -2], max: [5, 2, 2], sigma_t: 0, density: Noise(()))),
+                     material: Medium((albedo: [1, 1, 1]))),
+                    (shape: Sphere((pos: [3, 0, 0], radius: 0.5)),
+                     material: Emission((light: [1, 0, 0]))),
+                ],
+            )"#,
+            "test.ron",
+        )
+        .unwrap();
+        let scene = scene.build().unwrap();
+        let mut thread_buffer = ThreadBuffer::default();
+        let info = RayCastInfo::at_time(0.0);
+        let color = raycast_compute_light(
+            &scene,
+            &mut thread_buffer,
+            Point3::origin(),
+            Vector3::x_axis(),
+            info,
+        );
+        assert_eq!(color, Color3::new(1.0, 0.0, 0.0));
+
+        let mut transmittance = |origin| {
+            raycast_transmittance(
+                &scene,
+                origin,
+                Vector3::x_axis(),
+                6.0,
+                0.0,
+                &mut thread_buffer,
+            )
+        };
+        assert_eq!(transmittance(Point3::origin()), 0.0);
+        assert_eq!(transmittance(Point3::new(0.0, 1.5, 0.0)), 1.0);
+    }
+
+    fn quiet() -> Progress {
+        Progress::new(ProgressMode::Quiet, 0)
+    }
+
+    #[test]
+    fn same_image_with_any_thread_count() {
+        let (scene, camera, config) = test_scene();
+        let render_with = |threads| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            pool.install(|| render(&scene, &camera, &config, &quiet()))
+        };
+
+        let (one, stats) = render_with(1);
+        let (four, _) = render_with(4);
+        assert!(stats.indirect_rays > 0);
+        assert_eq!(one.into_raw(), four.into_raw());
+    }
+
+    #[test]
+    fn progressive_passes_and_stopping() {
+        let (scene, camera, mut config) = test_scene();
+        let (single, _) = render(&scene, &camera, &config, &quiet());
+
+        let mut writes = 0;
+        config.progressive = Some(ProgressiveConfig {
+            pass_samples: 1,
+            write_interval: Some(1e-9),
+            ..ProgressiveConfig::default()
+        });
+        let (passes, stats) =
+            render_progressive(&scene, &camera, &config, &quiet(), |_| writes += 1);
+        assert_eq!(single.into_raw(), to_image(&passes, &config).into_raw());
+        assert_eq!(stats.camera_rays, 12 * 12 * 3);
+        // no write after the last pass, it's the final image
+        assert_eq!(writes, 2);
+
+        config.samples_per_pixel = 1000;
+        config.progressive = Some(ProgressiveConfig {
+            pass_samples: 2,
+            variance_threshold: Some(f32::INFINITY),
+            ..ProgressiveConfig::default()
+        });
+        let (_, stats) = render(&scene, &camera, &config, &quiet());
+        assert_eq!(stats.camera_rays, 12 * 12 * 2);
+    }
+
+    #[test]
+    fn adaptive_samples_between_min_and_max() {
+        let (scene, camera, mut config) = test_scene();
+        config.samples_per_pixel = 6;
+        config.adaptive = Some(AdaptiveConfig {
+            min_samples: 2,
+            max_samples: Some(30),
+            heatmap: true,
+        });
+        let (accumulator, stats) = render_progressive(&scene, &camera, &config, &quiet(), |_| ());
+        let samples: Vec<u32> = accumulator.pixels().iter().map(|p| p.samples()).collect();
+        let total: u32 = samples.iter().sum();
+        assert!(samples.iter().all(|&n| (2..=30).contains(&n)));
+        assert!(samples.iter().min() < samples.iter().max());
+        assert_eq!(stats.camera_rays, total as u64);
+        // rounding may go a bit over the average
+        assert!(total >= 12 * 12 * 6 && total < 12 * 12 * 7);
+
+        let heatmap = accumulator.heatmap();
+        assert_eq!(heatmap.iter().max(), Some(&255));
     }
 }
@@ -0,0 +1,493 @@
+use std::f32::consts::PI;
+use std::sync::OnceLock;
+
+use nalgebra::Vector3;
+use rand::{Rng, SeedableRng};
+use rand_xoshiro::Xoroshiro128Plus;
+use serde::{Deserialize, Serialize};
+
+/// How the random numbers of a pixel sample are drawn.
+///
+/// Every request for a number (or a pair) use the next dimension, and the samples of one pixel
+/// are spread evenly over each dimension, except with `Independent`.
+/// `Halton`, `Sobol` and `BlueNoise` are also even over the first samples only
+/// (a power of two for the last two), `Stratified` over each batch of `samples_per_pixel`
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum SamplerKind {
+    Independent,
+    // jittered grid, cells shuffled differently for each pixel and dimension
+    Stratified,
+    // randomly shifted Halton sequence, a prime base per dimension.
+    // Past the 64 primes the dimensions are padded with `Sobol`'s
+    Halton,
+    // Owen-scrambled Sobol (0,2)-sequence, shuffled per pixel and pair of dimensions
+    Sobol,
+    // the same scrambled Sobol sequence in every pixel, xor-shifted by a blue noise texture,
+    // so the error of neighbouring pixels differ and looks like fine grain instead of blotch
+    BlueNoise,
+}
+
+impl Default for SamplerKind {
+    fn default() -> Self {
+        SamplerKind::Independent
+    }
+}
+
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+// side of the blue noise tile is 2^BLUE_NOISE_BITS
+const BLUE_NOISE_BITS: u32 = 6;
+const BLUE_NOISE_SIZE: usize = 1 << BLUE_NOISE_BITS;
+
+/// Several points of one 2D dimension, reserved by `Sampler::series_2d`
+#[derive(Clone, Copy)]
+pub struct Series2D {
+    dimension: u32,
+    count: u32,
+}
+
+#[derive(Clone)]
+pub struct Sampler {
+    kind: SamplerKind,
+    // samples stratified together by `Stratified`, the following ones form the next batch
+    samples_per_pixel: u32,
+    seed: u64,
+    pixel: (u32, u32),
+    // same for every sample of a pixel
+    pixel_key: u64,
+    sample: u32,
+    dimension: u32,
+}
+
+impl Sampler {
+    pub fn new(kind: SamplerKind, samples_per_pixel: u32) -> Self {
+        Sampler {
+            kind,
+            samples_per_pixel: samples_per_pixel.max(1),
+            seed: 0,
+            pixel: (0, 0),
+            pixel_key: 0,
+            sample: 0,
+            dimension: 0,
+        }
+    }
+
+    pub fn start_sample(&mut self, seed: u64, px: u32, py: u32, sample: u32) {
+        self.pixel_key = [px as u64, py as u64]
+            .iter()
+            .fold(mix(seed), |hash, &x| mix(hash ^ x));
+        self.seed = seed;
+        self.pixel = (px, py);
+        self.sample = sample;
+        self.dimension = 0;
+    }
+
+    pub fn next_1d(&mut self, rng: &mut Xoroshiro128Plus) -> f32 {
+        let dimension = self.take_dimensions(1);
+        let key = self.key(dimension);
+        let n = self.samples_per_pixel;
+        match self.kind {
+            SamplerKind::Independent => rng.gen(),
+            SamplerKind::Stratified => {
+                let stratum = permute(self.sample % n, n, key ^ mix32(self.sample / n));
+                (stratum as f32 + rng.gen::<f32>()) / n as f32
+            }
+            SamplerKind::Halton => match PRIMES.get(dimension as usize) {
+                Some(&base) => shift(radical_inverse(self.sample, base), key),
+                None => to_float(sobol_1d(self.sample, key)),
+            },
+            SamplerKind::Sobol => to_float(sobol_1d(self.sample, key)),
+            SamplerKind::BlueNoise => {
+                let key = self.sequence_key(dimension);
+                to_float(sobol_1d(self.sample, key) ^ self.blue_noise(key))
+            }
+        }
+    }
+
+    pub fn next_2d(&mut self, rng: &mut Xoroshiro128Plus) -> (f32, f32) {
+        let series = self.series_2d(1);
+        self.get_2d(series, 0, rng)
+    }
+
+    /// Reserve a dimension for `count` points per pixel sample,
+    /// together the points of every sample of the pixel are well distributed
+    pub fn series_2d(&mut self, count: u32) -> Series2D {
+        Series2D {
+            dimension: self.take_dimensions(2),
+            count: count.max(1),
+        }
+    }
+
+    pub fn get_2d(&self, series: Series2D, i: u32, rng: &mut Xoroshiro128Plus) -> (f32, f32) {
+        let key = self.key(series.dimension);
+        let index = self.sample * series.count + i;
+        match self.kind {
+            SamplerKind::Independent => (rng.gen(), rng.gen()),
+            SamplerKind::Stratified => {
+                let n = self.samples_per_pixel * series.count;
+                let nx = ((n as f32).sqrt() as u32).max(1);
+                let ny = (n + nx - 1) / nx;
+                let cell = permute(index % n, nx * ny, key ^ mix32(index / n));
+                (
+                    ((cell % nx) as f32 + rng.gen::<f32>()) / nx as f32,
+                    ((cell / nx) as f32 + rng.gen::<f32>()) / ny as f32,
+                )
+            }
+            SamplerKind::Halton => {
+                let d = series.dimension as usize;
+                match (PRIMES.get(d), PRIMES.get(d + 1)) {
+                    (Some(&bx), Some(&by)) => (
+                        shift(radical_inverse(index, bx), key),
+                        shift(radical_inverse(index, by), mix32(key)),
+                    ),
+                    _ => {
+                        let (x, y) = sobol_2d(index, key);
+                        (to_float(x), to_float(y))
+                    }
+                }
+            }
+            SamplerKind::Sobol => {
+                let (x, y) = sobol_2d(index, key);
+                (to_float(x), to_float(y))
+            }
+            SamplerKind::BlueNoise => {
+                let key = self.sequence_key(series.dimension);
+                let (x, y) = sobol_2d(index, key);
+                (
+                    to_float(x ^ self.blue_noise(key)),
+                    to_float(y ^ self.blue_noise(mix32(key))),
+                )
+            }
+        }
+    }
+
+    fn take_dimensions(&mut self, n: u32) -> u32 {
+        let dimension = self.dimension;
+        self.dimension += n;
+        dimension
+    }
+
+    fn key(&self, dimension: u32) -> u32 {
+        mix(self.pixel_key ^ dimension as u64) as u32
+    }
+
+    // like `key` but the same for every pixel
+    fn sequence_key(&self, dimension: u32) -> u32 {
+        mix(mix(self.seed) ^ dimension as u64) as u32
+    }
+
+    // pixel's value in the blue noise tile, as the top bits of the xor shift.
+    // `key` offset the tile so each dimension see a different part of it
+    fn blue_noise(&self, key: u32) -> u32 {
+        let mask = BLUE_NOISE_SIZE as u32 - 1;
+        let x = self.pixel.0.wrapping_add(key) & mask;
+        let y = self.pixel.1.wrapping_add(key >> 16) & mask;
+        let rank = blue_noise_tile()[(x + y * BLUE_NOISE_SIZE as u32) as usize];
+        rank << (32 - 2 * BLUE_NOISE_BITS) | key >> (2 * BLUE_NOISE_BITS)
+    }
+}
+
+/// Uniform point on the unit sphere
+pub fn sphere_point((u, v): (f32, f32)) -> Vector3<f32> {
+    let z = 1.0 - 2.0 * u;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * v;
+    Vector3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Uniform point in the unit ball
+pub fn ball_point(uv: (f32, f32), w: f32) -> Vector3<f32> {
+    sphere_point(uv) * w.cbrt()
+}
+
+// splitmix64 finalizer, nearby input give unrelated output
+pub(crate) fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+fn mix32(x: u32) -> u32 {
+    mix(x as u64) as u32
+}
+
+fn to_float(x: u32) -> f32 {
+    // 24 bits so it stays below 1
+    (x >> 8) as f32 / (1 << 24) as f32
+}
+
+fn shift(x: f32, key: u32) -> f32 {
+    let x = x + to_float(key);
+    if x >= 1.0 {
+        x - 1.0
+    } else {
+        x
+    }
+}
+
+fn radical_inverse(mut index: u32, base: u32) -> f32 {
+    let inv_base = 1.0 / base as f64;
+    let mut factor = inv_base;
+    let mut x = 0.0;
+    while index > 0 {
+        x += (index % base) as f64 * factor;
+        index /= base;
+        factor *= inv_base;
+    }
+    (x as f32).min(1.0 - f32::EPSILON)
+}
+
+// Owen-scrambled Sobol point, `key` also shuffle the order of the points
+fn sobol_1d(index: u32, key: u32) -> u32 {
+    let index = nested_uniform_scramble(index, key);
+    nested_uniform_scramble(index.reverse_bits(), mix32(key))
+}
+
+fn sobol_2d(index: u32, key: u32) -> (u32, u32) {
+    let index = nested_uniform_scramble(index, key);
+    let key_x = mix32(key);
+    let key_y = mix32(key_x);
+    (
+        nested_uniform_scramble(index.reverse_bits(), key_x),
+        nested_uniform_scramble(sobol_second(index), key_y),
+    )
+}
+
+// second dimension of Sobol sequence, the first is the bit reversed index
+fn sobol_second(mut index: u32) -> u32 {
+    let mut v = 1 << 31;
+    let mut x = 0;
+    while index != 0 {
+        if index & 1 != 0 {
+            x ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    x
+}
+
+// Owen scrambling by hashing, Burley 2020 "Practical Hash-based Owen Scrambling"
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x ^= x.wrapping_mul(0x3d20_adea);
+    x = x.wrapping_add(seed);
+    x = x.wrapping_mul((seed >> 16) | 1);
+    x ^= x.wrapping_mul(0x0552_6c56);
+    x ^= x.wrapping_mul(0x53a2_2864);
+    x.reverse_bits()
+}
+
+// permutation of 0..len chosen by `key`, Kensler 2013 "Correlated Multi-Jittered Sampling"
+fn permute(mut i: u32, len: u32, key: u32) -> u32 {
+    let mut w = len - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= key;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= key >> 16;
+        i ^= (i & w) >> 4;
+        i ^= key >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= key >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | key >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < len {
+            break;
+        }
+    }
+    (i + key) % len
+}
+
+/// Rank of each pixel of a tileable blue noise texture, made on first use
+fn blue_noise_tile() -> &'static [u32] {
+    static TILE: OnceLock<Vec<u32>> = OnceLock::new();
+    TILE.get_or_init(void_and_cluster)
+}
+
+// Ulichney 1993 "The void-and-cluster method for dither array generation",
+// each rank go to the pixel farthest from the ones with lower rank
+fn void_and_cluster() -> Vec<u32> {
+    const N: usize = BLUE_NOISE_SIZE * BLUE_NOISE_SIZE;
+    let mut set = vec![false; N];
+    let mut energy = vec![0.0; N];
+
+    let mut rng = Xoroshiro128Plus::seed_from_u64(0);
+    let initial = N / 10;
+    let mut count = 0;
+    while count < initial {
+        let p = rng.gen_range(0..N);
+        if !set[p] {
+            set[p] = true;
+            add_energy(&mut energy, p, 1.0);
+            count += 1;
+        }
+    }
+    // spread the initial points, moving the tightest cluster to the largest void until it stays
+    for _ in 0..N {
+        let cluster = tightest_cluster(&energy, &set);
+        set[cluster] = false;
+        add_energy(&mut energy, cluster, -1.0);
+        let void = largest_void(&energy, &set);
+        set[void] = true;
+        add_energy(&mut energy, void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = vec![0; N];
+    // lower ranks by removing initial points, then higher ranks by filling the voids
+    let (mut removed, mut removed_energy) = (set.clone(), energy.clone());
+    for r in (0..initial).rev() {
+        let cluster = tightest_cluster(&removed_energy, &removed);
+        removed[cluster] = false;
+        add_energy(&mut removed_energy, cluster, -1.0);
+        rank[cluster] = r as u32;
+    }
+    for r in initial..N {
+        let void = largest_void(&energy, &set);
+        set[void] = true;
+        add_energy(&mut energy, void, 1.0);
+        rank[void] = r as u32;
+    }
+    rank
+}
+
+// gaussian (sigma 1.5) of the toroidal distance to `p`
+fn add_energy(energy: &mut [f32], p: usize, sign: f32) {
+    let size = BLUE_NOISE_SIZE;
+    let wrap = |d: usize| d.min(size - d) as f32;
+    for (i, e) in energy.iter_mut().enumerate() {
+        let dx = wrap((i % size + size - p % size) % size);
+        let dy = wrap((i / size + size - p / size) % size);
+        *e += sign * (-(dx * dx + dy * dy) / 4.5).exp();
+    }
+}
+
+fn tightest_cluster(energy: &[f32], set: &[bool]) -> usize {
+    (0..energy.len())
+        .filter(|&i| set[i])
+        .max_by(|&a, &b| energy[a].partial_cmp(&energy[b]).unwrap())
+        .unwrap()
+}
+
+fn largest_void(energy: &[f32], set: &[bool]) -> usize {
+    (0..energy.len())
+        .filter(|&i| !set[i])
+        .min_by(|&a, &b| energy[a].partial_cmp(&energy[b]).unwrap())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn one_point_per_stratum() {
+        let mut rng = Xoroshiro128Plus::seed_from_u64(0);
+        for &kind in &[
+            SamplerKind::Stratified,
+            SamplerKind::Sobol,
+            SamplerKind::BlueNoise,
+        ] {
+            for dimension in 0..3 {
+                let mut sampler = Sampler::new(kind, 16);
+                let mut cells_2d = [0; 16];
+                let mut cells_1d = [0; 16];
+                for sample in 0..16 {
+                    sampler.start_sample(5, 3, 4, sample);
+                    sampler.dimension = dimension * 3;
+                    let (u, v) = sampler.next_2d(&mut rng);
+                    let w = sampler.next_1d(&mut rng);
+                    assert!((0.0..1.0).contains(&u) && (0.0..1.0).contains(&v));
+                    cells_2d[(u * 4.0) as usize + 4 * (v * 4.0) as usize] += 1;
+                    cells_1d[(w * 16.0) as usize] += 1;
+                }
+                assert_eq!(cells_2d, [1; 16], "{:?}", kind);
+                assert_eq!(cells_1d, [1; 16], "{:?}", kind);
+            }
+        }
+
+        // the random shift keep halton's base 2 and 3 dimension stratified, but not together
+        let mut sampler = Sampler::new(SamplerKind::Halton, 6);
+        let (mut halves, mut thirds) = ([0; 2], [0; 3]);
+        for sample in 0..6 {
+            sampler.start_sample(5, 3, 4, sample);
+            let (u, v) = sampler.next_2d(&mut rng);
+            halves[(u * 2.0) as usize] += 1;
+            thirds[(v * 3.0) as usize] += 1;
+        }
+        assert_eq!((halves, thirds), ([3; 2], [2; 3]));
+
+        // past the primes halton continue with sobol's dimensions
+        let mut sampler = Sampler::new(SamplerKind::Halton, 16);
+        let mut cells = [0; 16];
+        for sample in 0..16 {
+            sampler.start_sample(5, 3, 4, sample);
+            sampler.dimension = PRIMES.len() as u32;
+            let (u, v) = sampler.next_2d(&mut rng);
+            cells[(u * 4.0) as usize + 4 * (v * 4.0) as usize] += 1;
+        }
+        assert_eq!(cells, [1; 16]);
+
+        // adaptive sampling can stop a pixel after the first batch
+        for &kind in &[
+            SamplerKind::Stratified,
+            SamplerKind::Sobol,
+            SamplerKind::BlueNoise,
+        ] {
+            let mut sampler = Sampler::new(kind, 4);
+            for batch in 0..3 {
+                let mut quarters = [0; 4];
+                let mut cells = [0; 4];
+                for sample in 4 * batch..4 * batch + 4 {
+                    sampler.start_sample(5, 3, 4, sample);
+                    quarters[(sampler.next_1d(&mut rng) * 4.0) as usize] += 1;
+                    let (u, v) = sampler.next_2d(&mut rng);
+                    cells[(u * 2.0) as usize + 2 * (v * 2.0) as usize] += 1;
+                }
+                assert_eq!((quarters, cells), ([1; 4], [1; 4]), "{:?}", kind);
+            }
+        }
+    }
+
+    #[test]
+    fn blue_noise_tile_spread_ranks() {
+        let tile = blue_noise_tile();
+        let mut sorted = tile.to_vec();
+        sorted.sort_unstable();
+        assert!(sorted.iter().enumerate().all(|(i, &r)| i as u32 == r));
+
+        // neighbouring pixels have far apart ranks, white noise would average a third
+        let size = BLUE_NOISE_SIZE;
+        let mean_difference = (0..size * size)
+            .map(|i| {
+                let right = (i + 1) % size + i / size * size;
+                (tile[i] as f32 - tile[right] as f32).abs()
+            })
+            .sum::<f32>()
+            / (size * size * size * size) as f32;
+        assert!(mean_difference > 0.38, "{}", mean_difference);
+    }
+}
@@ -1,31 +1,163 @@
 use crate::rtracer::light::Light;
 
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use std::vec::Vec;
 
 use serde::{Deserialize, Serialize};
 
+use super::material::{MaterialError, MaterialLibrary, Materials};
+use super::obj_import::ObjAsset;
 use super::{light, Color3, SceneObject};
 use nalgebra::{Point3, Unit, Vector3};
 
+/// Other scene file whose content is added to the scene, moved by the transform
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(from = "crate::utils::proxy_serialize::IncludeProxy")]
+pub struct Include {
+    // relative to the including file
+    pub file: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transform: Option<Transform>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct SceneBuilder {
+    // scene files merged into this one, see `parser::load_scene_data`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<Include>,
+    // material shared by name, object refer to them with `Named("name")`
+    #[serde(default, skip_serializing_if = "MaterialLibrary::is_empty")]
+    pub materials: MaterialLibrary,
+    #[serde(default)]
     pub objects: Vec<SceneObject>,
+    // obj files, each of their models is appended to objects
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub assets: Vec<ObjAsset>,
+    #[serde(default)]
     pub lights: Vec<light::Lights>,
+    // only the including scene's skylight is used
+    #[serde(default = "Color3::zeros")]
     pub skylight: Color3,
+    // file each material merged from an included file come from, they're the same material
+    // when a file is included twice
+    #[serde(skip)]
+    material_files: BTreeMap<String, PathBuf>,
 }
 
 impl SceneBuilder {
-    pub fn build(self) -> Scene {
+    /// point every named material reference of the objects to the library's material
+    fn resolve_materials(&mut self) -> Result<(), MaterialError> {
+        if let Some((name, _)) = self
+            .materials
+            .iter()
+            .find(|(_, material)| matches!(***material, Materials::Named(_)))
+        {
+            return Err(MaterialError::NestedReference { name: name.clone() });
+        }
+        for obj in &mut self.objects {
+            if let Materials::Named(named) = &mut obj.material {
+                named.resolve(&self.materials)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the content of `file`, an included scene, moved by `transform`.
+    /// Its objects go after this scene's own objects and assets, so their index in this file
+    /// (for the animation) stay the same. Material name defined by both is an error
+    pub fn merge(
+        &mut self,
+        other: SceneBuilder,
+        file: &Path,
+        transform: Option<&Transform>,
+    ) -> Result<(), MaterialError> {
+        let material_file = |files: &BTreeMap<String, PathBuf>, name: &str| {
+            files.get(name).cloned().unwrap_or_else(|| file.to_owned())
+        };
+        for name in other.materials.keys() {
+            let from = material_file(&other.material_files, name);
+            if self.materials.contains_key(name) && self.material_files.get(name) != Some(&from) {
+                return Err(MaterialError::DuplicateMaterial { name: name.clone() });
+            }
+        }
+        for (name, material) in other.materials {
+            let from = material_file(&other.material_files, &name);
+            self.material_files.insert(name.clone(), from);
+            self.materials.entry(name).or_insert(material);
+        }
+
+        self.flatten_assets();
+        let objects = other
+            .objects
+            .into_iter()
+            .chain(other.assets.into_iter().flat_map(|asset| asset.objects));
+        for mut obj in objects {
+            if let Some(transform) = transform {
+                obj.transform = Some(match &obj.transform {
+                    Some(inner) => inner.then(transform),
+                    None => transform.clone(),
+                });
+            }
+            self.objects.push(obj);
+        }
+
+        for mut light in other.lights {
+            if let Some(transform) = transform {
+                light.transform(transform);
+            }
+            self.lights.push(light);
+        }
+        Ok(())
+    }
+
+    // asset's models are objects placed right after the file's own objects
+    fn flatten_assets(&mut self) {
+        for asset in self.assets.drain(..) {
+            self.objects.extend(asset.objects);
+        }
+    }
+
+    /// Scene ready to render, fail if an object refer to a material the library doesn't have
+    pub fn build(mut self) -> Result<Scene, MaterialError> {
+        self.flatten_assets();
+        self.resolve_materials()?;
+
+        // where each object end up, same split as the tree building
+        let (mut bounded, mut unbounded) = (0, 0);
+        let locations = self
+            .objects
+            .iter()
+            .map(|obj| {
+                if obj.bounding_box().is_some() {
+                    bounded += 1;
+                    ObjectLocation::Bounded(SceneObjectIndex(bounded - 1))
+                } else {
+                    unbounded += 1;
+                    ObjectLocation::Unbounded(unbounded - 1)
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let base_transforms = self
+            .objects
+            .iter()
+            .map(|obj| obj.transform.clone())
+            .collect();
         let (bvh, bounded_objects, unbounded_objects) =
             BVHTree::from_scene_objects::<Vec<SceneObject>, _>(self.objects);
 
-        Scene {
+        Ok(Scene {
+            materials: self.materials,
             bvh,
             bounded_objects: bounded_objects.into_boxed_slice(),
             unbounded_objects: unbounded_objects.into_boxed_slice(),
             lights: self.lights.into_boxed_slice(),
             skylight: self.skylight,
-        }
+            shutter: (0.0, 0.0),
+            locations: locations.into_boxed_slice(),
+            base_transforms,
+        })
     }
 
     pub fn from_maybe_component(
@@ -34,9 +166,13 @@ impl SceneBuilder {
         skylight: Option<Color3>,
     ) -> Self {
         SceneBuilder {
+            include: Vec::new(),
+            materials: MaterialLibrary::new(),
             objects: objs.unwrap_or_else(Vec::new),
+            assets: Vec::new(),
             lights: lights.unwrap_or_else(Vec::new),
             skylight: skylight.unwrap_or_else(|| Color3::new(0.0, 0.0, 0.0)),
+            material_files: BTreeMap::new(),
         }
     }
 }
@@ -44,14 +180,21 @@ impl SceneBuilder {
 impl Default for SceneBuilder {
     fn default() -> Self {
         SceneBuilder {
+            include: Vec::new(),
+            materials: MaterialLibrary::new(),
             objects: Vec::new(),
+            assets: Vec::new(),
             lights: Vec::new(),
             skylight: Color3::new(0.0, 0.0, 0.0),
+            material_files: BTreeMap::new(),
         }
     }
 }
 
+use crate::rtracer::animation::ObjectPose;
 use crate::rtracer::bvh::BVHTree;
+use crate::rtracer::shape::Shape;
+use crate::rtracer::transform::Transform;
 
 use crate::rtracer::thread_buffer::ThreadBuffer;
 use derive_more::{From, Into};
@@ -60,14 +203,48 @@ use typed_index_collections::TiSlice;
 #[derive(Clone, From, Into, Debug)]
 pub struct SceneObjectIndex(usize);
 
-#[derive(Serialize)]
+// position of object in the scene file's object list after splitting
+#[derive(Clone, Debug)]
+enum ObjectLocation {
+    Bounded(SceneObjectIndex),
+    Unbounded(usize),
+}
+
 pub struct Scene {
+    // kept for saving the scene, objects already refer to them directly
+    materials: MaterialLibrary,
     bounded_objects: Box<TiSlice<SceneObjectIndex, SceneObject>>,
-    #[serde(skip)]
     bvh: BVHTree,
     unbounded_objects: Box<[SceneObject]>,
     lights: Box<[light::Lights]>,
     skylight: Color3,
+    // time interval the bounding volumes cover
+    shutter: (f32, f32),
+    locations: Box<[ObjectLocation]>,
+    // transform of each object in the scene file, animation is applied before them
+    base_transforms: Box<[Option<Transform>]>,
+}
+
+// scene saved in the same form as `SceneBuilder`, objects in their original order
+#[derive(Serialize)]
+struct SceneSerialize<'a> {
+    #[serde(skip_serializing_if = "MaterialLibrary::is_empty")]
+    materials: &'a MaterialLibrary,
+    objects: Vec<&'a SceneObject>,
+    lights: &'a [light::Lights],
+    skylight: &'a Color3,
+}
+
+impl Serialize for Scene {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SceneSerialize {
+            materials: &self.materials,
+            objects: (0..self.object_count()).map(|i| self.object(i)).collect(),
+            lights: &self.lights,
+            skylight: &self.skylight,
+        }
+        .serialize(serializer)
+    }
 }
 
 impl Scene {
@@ -75,17 +252,30 @@ impl Scene {
         &self,
         point: Point3<f32>,
         normal: Unit<Vector3<f32>>,
+        time: f32,
         thread_buffer: &mut ThreadBuffer,
     ) -> Color3 {
         let direct_light = self
             .lights
             .iter()
-            .map(|x| x.direct_light_at(point, normal, self, thread_buffer))
+            .map(|x| x.direct_light_at(point, normal, time, self, thread_buffer))
             .sum::<Color3>();
 
         direct_light // + self.skylight
     }
 
+    pub fn incident_light_at(
+        &self,
+        point: Point3<f32>,
+        time: f32,
+        thread_buffer: &mut ThreadBuffer,
+    ) -> Color3 {
+        self.lights
+            .iter()
+            .map(|x| x.incident_light_at(point, time, self, thread_buffer))
+            .sum::<Color3>()
+    }
+
     #[inline]
     pub fn bounded(&self) -> &TiSlice<SceneObjectIndex, SceneObject> {
         self.bounded_objects.as_ref()
@@ -101,11 +291,91 @@ impl Scene {
         &self.bvh
     }
 
+    /// Move a bounded object, only the top level tree is rebuilt
+    /// since every shape's own tree is in its object space
+    pub fn set_transform(&mut self, index: SceneObjectIndex, transform: Option<Transform>) {
+        self.bounded_objects[index].transform = transform;
+        self.rebuild_top_level();
+    }
+
+    /// number of objects, as listed in the scene file
+    #[inline]
+    pub fn object_count(&self) -> usize {
+        self.locations.len()
+    }
+
+    /// object by its index in the scene file
+    pub fn object(&self, index: usize) -> &SceneObject {
+        match &self.locations[index] {
+            ObjectLocation::Bounded(i) => &self.bounded_objects[i.clone()],
+            ObjectLocation::Unbounded(i) => &self.unbounded_objects[*i],
+        }
+    }
+
+    /// Put objects given by their index in the scene file where the animation say,
+    /// the top level tree is rebuilt once and only if a bounded object moved
+    pub fn set_poses(&mut self, poses: impl IntoIterator<Item = (usize, ObjectPose)>) {
+        let mut moved = false;
+        for (index, pose) in poses {
+            let base = &self.base_transforms[index];
+            let (transform, hidden) = match pose {
+                ObjectPose::Transformed(animated) => (
+                    Some(match base {
+                        Some(base) => animated.then(base),
+                        None => animated,
+                    }),
+                    false,
+                ),
+                ObjectPose::Hidden => (base.clone(), true),
+            };
+            let obj = match &self.locations[index] {
+                ObjectLocation::Bounded(i) => {
+                    let obj = &mut self.bounded_objects[i.clone()];
+                    moved |= obj.transform != transform || obj.hidden != hidden;
+                    obj
+                }
+                ObjectLocation::Unbounded(i) => &mut self.unbounded_objects[*i],
+            };
+            obj.transform = transform;
+            obj.hidden = hidden;
+        }
+        if moved {
+            self.rebuild_top_level();
+        }
+    }
+
+    pub fn rebuild_top_level(&mut self) {
+        let shutter = self.shutter;
+        self.bvh = BVHTree::generate(
+            self.bounded_objects
+                .iter_enumerated()
+                .filter_map(|(i, obj)| Some((i, obj.bounding_box_over(shutter)?))),
+        );
+    }
+
+    /// Set time interval of camera rays, bounding volume of moving objects are expanded to cover it
+    pub fn set_shutter(&mut self, shutter: (f32, f32)) {
+        if self.shutter != shutter {
+            self.shutter = shutter;
+            self.rebuild_top_level();
+        }
+    }
+
+    #[inline]
+    pub fn shutter(&self) -> (f32, f32) {
+        self.shutter
+    }
+
     #[inline]
     pub fn lights(&self) -> &[light::Lights] {
         self.lights.as_ref()
     }
 
+    #[inline]
+    pub fn lights_mut(&mut self) -> &mut [light::Lights] {
+        self.lights.as_mut()
+    }
+
     #[inline]
     pub fn skylight(&self) -> &Color3 {
         &self.skylight
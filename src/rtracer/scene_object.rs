@@ -1,12 +1,27 @@
+use nalgebra::{Point3, Unit, Vector3};
 use serde::{Deserialize, Serialize};
 
 use super::shape::geometric::Shapes;
-use super::Materials;
+use super::shape::Shape;
+use super::transform::{Motion, Transform};
+use super::{HitInfo, Materials};
+use crate::utils::aabb::AABB;
+
+// number of interval the shutter is divided into when bounding a moving object
+const MOTION_BOUND_STEPS: usize = 16;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SceneObject {
     pub material: Materials,
     pub shape: Shapes,
+    // object space to world space, shape is in world space if there's none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transform: Option<Transform>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub motion: Option<Motion>,
+    // collapsed by the animation on the current frame, nothing hit it
+    #[serde(skip)]
+    pub hidden: bool,
 }
 
 impl SceneObject {
@@ -14,6 +29,168 @@ impl SceneObject {
         SceneObject {
             material: material.into(),
             shape: shape.into(),
+            transform: None,
+            motion: None,
+            hidden: false,
+        }
+    }
+
+    /// object space to world space transform at time, None if it's the identity
+    pub fn transform_at(&self, time: f32) -> Option<Transform> {
+        let motion = match &self.motion {
+            Some(motion) => motion,
+            None => return self.transform.clone(),
+        };
+
+        let (pivot, base) = match &self.transform {
+            Some(transform) => (
+                transform.point_to_world(&Point3::origin()),
+                transform.clone(),
+            ),
+            None => (Point3::origin(), Transform::identity()),
+        };
+        Some(base.then(&motion.transform_at(time, pivot)))
+    }
+
+    /// Ray in the shape's space at time, with object space distance per world space distance
+    pub fn local_ray(
+        &self,
+        origin: Point3<f32>,
+        dir: Unit<Vector3<f32>>,
+        time: f32,
+    ) -> (Point3<f32>, Unit<Vector3<f32>>, f32) {
+        match self.transform_at(time) {
+            Some(transform) => transform.ray_to_local(origin, dir),
+            None => (origin, dir, 1.0),
+        }
+    }
+
+    /// intersection of the transformed shape in world space, where the object is at time
+    pub fn intersect_at(
+        &self,
+        origin: Point3<f32>,
+        dir: Unit<Vector3<f32>>,
+        time: f32,
+    ) -> Option<HitInfo> {
+        let intersect_with = |transform: &Transform| {
+            let (local_origin, local_dir, dist_scale) = transform.ray_to_local(origin, dir);
+            let hit = self.shape.intersect(local_origin, local_dir)?;
+            Some(transform.hit_to_world(hit, origin, dir, dist_scale))
+        };
+
+        if self.hidden {
+            return None;
+        }
+        match (&self.transform, &self.motion) {
+            (None, None) => self.shape.intersect(origin, dir),
+            (Some(transform), None) => intersect_with(transform),
+            (_, Some(_)) => intersect_with(&self.transform_at(time)?),
+        }
+    }
+
+    /// Bounding box covering every position of the object during time interval `shutter`
+    pub fn bounding_box_over(&self, shutter: (f32, f32)) -> Option<AABB> {
+        if self.hidden {
+            return None;
+        }
+        let motion = match &self.motion {
+            Some(motion) if shutter.0 != shutter.1 => motion,
+            _ => return self.bounding_box(),
+        };
+        let local = self.shape.bounding_box()?;
+        let (open, close) = shutter;
+        let step = (close - open) / MOTION_BOUND_STEPS as f32;
+
+        let bounds_at = |time: f32| match self.transform_at(time) {
+            Some(transform) => transform.aabb_to_world(&local),
+            None => local.clone(),
+        };
+        let swept = (1..=MOTION_BOUND_STEPS)
+            .map(|i| bounds_at(open + step * i as f32))
+            .fold(bounds_at(open), |bb, b| bb.union(&b));
+
+        // rotating point leave the chord between two samples by at most r * (1 - cos(angle / 2))
+        let pivot = match (&motion.pivot, &self.transform) {
+            (Some(pivot), _) => *pivot,
+            (None, Some(transform)) => transform.point_to_world(&Point3::origin()),
+            (None, None) => Point3::origin(),
+        };
+        // with the pivot off-centre the farthest corner can be any of the 8
+        let (min, max) = (swept.min(), swept.max());
+        let radius = (0..8)
+            .map(|i| {
+                let pick = |bit, axis: usize| if i & bit == 0 { min[axis] } else { max[axis] };
+                (Point3::new(pick(1, 0), pick(2, 1), pick(4, 2)) - pivot).norm()
+            })
+            .fold(0.0, f32::max);
+        let sag = radius * (1.0 - (motion.angle_over(step.abs()) / 2.0).cos());
+        let padding = Vector3::repeat(sag);
+        Some(AABB::new_uncheck(
+            swept.min() - padding,
+            swept.max() + padding,
+        ))
+    }
+}
+
+// intersection of the transformed shape at time 0, in world space
+impl Shape for SceneObject {
+    fn intersect(&self, origin: Point3<f32>, dir: Unit<Vector3<f32>>) -> Option<HitInfo> {
+        self.intersect_at(origin, dir, 0.0)
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        let bb = self.shape.bounding_box()?;
+        Some(match self.transform_at(0.0) {
+            Some(transform) => transform.aabb_to_world(&bb),
+            None => bb,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtracer::geometric::Cuboid;
+    use crate::rtracer::material::Emission;
+    use crate::rtracer::Color3;
+    use nalgebra::UnitQuaternion;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn swept_bounds_cover_off_centre_spin() {
+        for i in 0..16 {
+            // pivot off the box's centre, turning the whole setup move the corners
+            // relative to the bound steps
+            let turn = UnitQuaternion::from_axis_angle(&Vector3::z_axis(), i as f32 * PI / 64.0);
+            let pivot = turn * Point3::new(1.5, -1.5, 0.0);
+            let cube = Cuboid::new(Point3::origin(), Vector3::repeat(4.0), turn);
+            let mut obj = SceneObject::new(cube, Emission::new(Color3::zeros()));
+            obj.motion = Some(Motion {
+                velocity: Vector3::zeros(),
+                angular_velocity: Vector3::new(0.0, 1.0, 3.0 * PI),
+                pivot: Some(pivot),
+            });
+
+            let bounds = obj.bounding_box_over((0.0, 1.0)).unwrap();
+            let local = obj.shape.bounding_box().unwrap();
+            for step in 0..=1000 {
+                let bb = obj
+                    .transform_at(step as f32 / 1000.0)
+                    .unwrap()
+                    .aabb_to_world(&local);
+                for axis in 0..3 {
+                    assert!(
+                        bb.min()[axis] >= bounds.min()[axis] - 1e-4,
+                        "pivot {}",
+                        pivot
+                    );
+                    assert!(
+                        bb.max()[axis] <= bounds.max()[axis] + 1e-4,
+                        "pivot {}",
+                        pivot
+                    );
+                }
+            }
         }
     }
 }
@@ -5,23 +5,92 @@ use enum_dispatch::enum_dispatch;
 use super::HitInfo;
 use crate::utils::aabb::AABB;
 
+mod csg;
+mod cuboid;
+mod cylinder;
+mod sdf;
+mod torus;
+
+// limit on crossings found by marching, guard against shape that keep hitting at the same spot
+const MAX_MARCH_CROSSINGS: usize = 64;
+
 #[enum_dispatch]
 pub trait Shape {
     fn intersect(&self, origin: Point3<f32>, dir: Unit<Vector3<f32>>) -> Option<HitInfo>;
     fn bounding_box(&self) -> Option<AABB>;
+
+    /// Call `f` on every crossing of the ray with the surface, in any order.
+    ///
+    /// Crossings behind the origin may also be reported, default implementation
+    /// march along the ray with repeated `intersect`
+    fn crossings(&self, origin: Point3<f32>, dir: Unit<Vector3<f32>>, f: &mut dyn FnMut(HitInfo)) {
+        let mut traveled = 0.0;
+        for _ in 0..MAX_MARCH_CROSSINGS {
+            let start = origin + dir.scale(traveled);
+            match self.intersect(start, dir) {
+                Some(mut hit) => {
+                    traveled += hit.dist;
+                    hit.dist = traveled;
+                    f(hit);
+                    traveled += 1e-4;
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// invalid parameter, described for the user. Checked after loading a scene
+    fn problems(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// closest crossing in front of the ray
+pub(crate) fn nearest_crossing(for_each: impl FnOnce(&mut dyn FnMut(HitInfo))) -> Option<HitInfo> {
+    let mut nearest: Option<HitInfo> = None;
+    for_each(&mut |hit| {
+        if hit.dist > 1e-6 && nearest.as_ref().map_or(true, |n| hit.dist < n.dist) {
+            nearest = Some(hit);
+        }
+    });
+    nearest
+}
+
+/// crossings in front of the ray, sorted by distance
+pub(crate) fn sorted_crossings(for_each: impl FnOnce(&mut dyn FnMut(HitInfo))) -> Vec<HitInfo> {
+    let mut crossings = Vec::new();
+    for_each(&mut |hit| {
+        if hit.dist > 1e-6 {
+            crossings.push(hit)
+        }
+    });
+    crossings.sort_by_key(|hit| ordered_float::OrderedFloat(hit.dist));
+    crossings
 }
 
 pub mod geometric {
-    use nalgebra::{ComplexField, Point3, Unit, Vector3};
+    use nalgebra::{ComplexField, Point2, Point3, Unit, Vector3};
     use serde::{Deserialize, Serialize};
 
     use enum_dispatch::enum_dispatch;
+    use std::f32::consts::PI;
 
     use super::HitInfo;
     use super::Shape;
     use crate::rtracer::helper::debug_normalize;
+    use crate::rtracer::validation::{check_direction, check_non_negative};
     use crate::utils::aabb::AABB;
 
+    pub use super::csg::Csg;
+    pub use super::cuboid::Cuboid;
+    pub use super::cylinder::{Capsule, Cone, Cylinder};
+    pub use super::sdf::{Sdf, SdfNode};
+    pub use super::torus::Torus;
+    pub use crate::rtracer::curve::{Curve, Curves};
+    pub use crate::rtracer::heightfield::Heightfield;
+    pub use crate::rtracer::mesh::Mesh;
+    pub use crate::rtracer::volume::Volume;
+
     #[enum_dispatch(Shape)]
     #[derive(Serialize, Deserialize, Debug)]
     pub enum Shapes {
@@ -29,6 +98,18 @@ pub mod geometric {
         InfinitePlane,
         Disc,
         Plane,
+        Volume,
+        Mesh,
+        Cuboid,
+        Cylinder,
+        Cone,
+        Capsule,
+        Torus,
+        Csg,
+        Sdf,
+        Heightfield,
+        Curve,
+        Curves,
     }
 
     #[derive(Serialize, Deserialize, Debug)]
@@ -47,8 +128,17 @@ pub mod geometric {
         }
     }
 
-    #[derive(Serialize, Deserialize, Debug)]
-    #[serde(from = "SphereProxy")]
+    impl From<Sphere> for SphereProxy {
+        fn from(sphere: Sphere) -> Self {
+            SphereProxy {
+                pos: sphere.pos,
+                radius: sphere.radius,
+            }
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    #[serde(from = "SphereProxy", into = "SphereProxy")]
     pub struct Sphere {
         pub pos: Point3<f32>,
         pub radius: f32,
@@ -75,19 +165,69 @@ pub mod geometric {
             let intersection = origin.clone() + dir.into_inner() * dist;
             let normal = debug_normalize((intersection - self.pos) / self.radius);
 
+            // longitude, latitude
+            let uv = Point2::new(
+                0.5 + normal.y.atan2(normal.x) / (2.0 * PI),
+                normal.z.max(-1.0).min(1.0).acos() / PI,
+            );
+
             Some(HitInfo {
                 incoming_dir: dir,
                 dist,
                 intersection,
                 normal,
+                uv,
+                tangent: None,
+                color: None,
+                back_face: false,
             })
         }
 
+        fn crossings(
+            &self,
+            origin: Point3<f32>,
+            dir: Unit<Vector3<f32>>,
+            f: &mut dyn FnMut(HitInfo),
+        ) {
+            let dac = origin - self.pos;
+            let half_b = dir.dot(&dac);
+            let discriminant = half_b * half_b - (dac.magnitude_squared() - self.radius_squared);
+            if discriminant < 0.0 {
+                return;
+            }
+
+            let sqrt_d = discriminant.sqrt();
+            for &dist in &[-half_b - sqrt_d, -half_b + sqrt_d] {
+                let intersection = origin + dir.into_inner() * dist;
+                let normal = Unit::new_normalize(intersection - self.pos);
+                let uv = Point2::new(
+                    0.5 + normal.y.atan2(normal.x) / (2.0 * PI),
+                    normal.z.max(-1.0).min(1.0).acos() / PI,
+                );
+                f(HitInfo {
+                    incoming_dir: dir,
+                    dist,
+                    intersection,
+                    normal,
+                    uv,
+                    tangent: None,
+                    color: None,
+                    back_face: false,
+                });
+            }
+        }
+
         fn bounding_box(&self) -> Option<AABB> {
             let r = self.radius;
             let r_vec = Vector3::new(r, r, r);
             Some(AABB::new_uncheck(self.pos - r_vec, self.pos + r_vec))
         }
+
+        fn problems(&self) -> Vec<String> {
+            let mut problems = Vec::new();
+            check_non_negative(&mut problems, "radius", self.radius);
+            problems
+        }
     }
 
     #[derive(Serialize, Deserialize, Debug)]
@@ -112,11 +252,29 @@ pub mod geometric {
             let dist = ((self_pos - origin).dot(self_norm.as_ref())) / deno;
 
             if dist > 0.0 {
+                let intersection = origin + dir.as_ref() * dist;
+
+                // coordinate on the plane, using arbitrary (but fixed) tangent as u axis
+                let tangent = Unit::new_normalize(if self_norm.x.abs() < 0.9 {
+                    Vector3::x().cross(&self_norm)
+                } else {
+                    Vector3::y().cross(&self_norm)
+                });
+                let displacement = intersection - self_pos;
+                let uv = Point2::new(
+                    displacement.dot(&tangent),
+                    displacement.dot(&self_norm.cross(&tangent)),
+                );
+
                 Some(HitInfo {
                     incoming_dir: dir,
                     dist,
-                    intersection: origin + dir.as_ref() * dist,
+                    intersection,
                     normal: self_norm,
+                    uv,
+                    tangent: None,
+                    color: None,
+                    back_face: false,
                 })
             } else {
                 None
@@ -132,6 +290,12 @@ pub mod geometric {
         fn bounding_box(&self) -> Option<AABB> {
             None
         }
+
+        fn problems(&self) -> Vec<String> {
+            let mut problems = Vec::new();
+            check_direction(&mut problems, "norm", &self.norm);
+            problems
+        }
     }
 
     #[derive(Serialize, Deserialize, Debug)]
@@ -154,8 +318,16 @@ pub mod geometric {
 
     impl Shape for Disc {
         fn intersect(&self, origin: Point3<f32>, dir: Unit<Vector3<f32>>) -> Option<HitInfo> {
-            InfinitePlane::_intersect(self.pos.clone(), self.norm, origin, dir)
-                .filter(|hit| (hit.intersection - self.pos).norm_squared() < self.r_sq)
+            let mut hit = InfinitePlane::_intersect(self.pos.clone(), self.norm, origin, dir)
+                .filter(|hit| (hit.intersection - self.pos).norm_squared() < self.r_sq)?;
+
+            // (normalized radius, angle)
+            let (x, y) = (hit.uv.x, hit.uv.y);
+            hit.uv = Point2::new(
+                (x * x + y * y).sqrt() / self.r_sq.sqrt(),
+                0.5 + y.atan2(x) / (2.0 * PI),
+            );
+            Some(hit)
         }
 
         // NOTE: incomplete implementation
@@ -164,10 +336,22 @@ pub mod geometric {
             let span = Vector3::new(r, r, 0.001);
             Some(AABB::new(self.pos - span, self.pos + span))
         }
+
+        fn problems(&self) -> Vec<String> {
+            let mut problems = Vec::new();
+            check_direction(&mut problems, "norm", &self.norm);
+            // radius with its sign, see `proxy_serialize::squared`
+            let radius = self.r_sq.signum() * self.r_sq.abs().sqrt();
+            check_non_negative(&mut problems, "radius", radius);
+            problems
+        }
     }
 
-    #[derive(Serialize, Deserialize, Debug)]
-    #[serde(from = "crate::utils::proxy_serialize::PlaneProxy")]
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    #[serde(
+        from = "crate::utils::proxy_serialize::PlaneProxy",
+        into = "crate::utils::proxy_serialize::PlaneProxy"
+    )]
     // plane = t*span + u*cospan; (t,u) in [-1,1]^2
     pub struct Plane {
         pub pos: Point3<f32>,
@@ -180,18 +364,37 @@ pub mod geometric {
 
     impl Shape for Plane {
         fn intersect(&self, origin: Point3<f32>, dir: Unit<Vector3<f32>>) -> Option<HitInfo> {
-            InfinitePlane::_intersect(self.pos, self.norm, origin, dir).filter(|hit| {
-                let displacement: Vector3<f32> = hit.intersection - self.pos;
-                displacement.dot(&self.span_dir).abs() <= self.span_length
-                    && displacement.dot(&self.cospan_dir).abs() <= self.cospan_length
-            })
+            let mut hit = InfinitePlane::_intersect(self.pos, self.norm, origin, dir)?;
+
+            let displacement: Vector3<f32> = hit.intersection - self.pos;
+            let t = displacement.dot(&self.span_dir) / self.span_length;
+            let u = displacement.dot(&self.cospan_dir) / self.cospan_length;
+            if t.abs() > 1.0 || u.abs() > 1.0 {
+                return None;
+            }
+
+            hit.uv = Point2::new((t + 1.0) / 2.0, (u + 1.0) / 2.0);
+            Some(hit)
         }
 
         fn bounding_box(&self) -> Option<AABB> {
             // dbg!("ww");
             // return None;
-            let total_span = self.span_dir.scale(self.span_length) + self.cospan_dir.scale(self.cospan_length) + Vector3::new(1e-3, 1e-3, 1e-3);
+            let total_span = self.span_dir.scale(self.span_length)
+                + self.cospan_dir.scale(self.cospan_length)
+                + Vector3::new(1e-3, 1e-3, 1e-3);
             Some(AABB::new(self.pos + total_span, self.pos - total_span))
         }
+
+        fn problems(&self) -> Vec<String> {
+            let mut problems = Vec::new();
+            check_direction(&mut problems, "norm", &self.norm);
+            check_direction(&mut problems, "span", &self.span_dir);
+            // cospan default to norm x span, only worth reporting if those are fine
+            if problems.is_empty() {
+                check_direction(&mut problems, "cospan", &self.cospan_dir);
+            }
+            problems
+        }
     }
 }
@@ -0,0 +1,193 @@
+use nalgebra::{Point3, Unit, Vector3};
+use serde::{Deserialize, Serialize};
+
+use super::geometric::Shapes;
+use super::{nearest_crossing, sorted_crossings, HitInfo, Shape};
+use crate::rtracer::validation::nested_problems;
+use crate::utils::aabb::AABB;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    // left - right
+    Difference,
+}
+
+impl CsgOperation {
+    fn inside(self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOperation::Union => in_left || in_right,
+            CsgOperation::Intersection => in_left && in_right,
+            CsgOperation::Difference => in_left && !in_right,
+        }
+    }
+}
+
+/// Boolean combination of two solids.
+///
+/// Both operands must be closed, inside/outside of each operand along the ray is found
+/// from the parity of its crossings, so open surfaces (Plane, Disc, ...) give garbage
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Csg {
+    pub operation: CsgOperation,
+    pub left: Box<Shapes>,
+    pub right: Box<Shapes>,
+}
+
+impl Csg {
+    pub fn new(operation: CsgOperation, left: Shapes, right: Shapes) -> Self {
+        Csg {
+            operation,
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+}
+
+impl Shape for Csg {
+    fn intersect(&self, origin: Point3<f32>, dir: Unit<Vector3<f32>>) -> Option<HitInfo> {
+        nearest_crossing(|f| self.crossings(origin, dir, f))
+    }
+
+    fn crossings(&self, origin: Point3<f32>, dir: Unit<Vector3<f32>>, f: &mut dyn FnMut(HitInfo)) {
+        let left = sorted_crossings(|f| self.left.crossings(origin, dir, f));
+        let right = sorted_crossings(|f| self.right.crossings(origin, dir, f));
+
+        // odd number of crossings ahead = ray start inside
+        let mut in_left = left.len() % 2 == 1;
+        let mut in_right = right.len() % 2 == 1;
+        let mut inside = self.operation.inside(in_left, in_right);
+
+        // walk both interval lists in order, report where the combined inside state change
+        let mut left = left.into_iter().peekable();
+        let mut right = right.into_iter().peekable();
+        loop {
+            let from_left = match (left.peek(), right.peek()) {
+                (Some(l), Some(r)) => l.dist <= r.dist,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => break,
+            };
+            let hit = if from_left {
+                in_left = !in_left;
+                left.next()
+            } else {
+                in_right = !in_right;
+                right.next()
+            };
+            let hit = hit.expect("peeked");
+
+            let now_inside = self.operation.inside(in_left, in_right);
+            if now_inside == inside {
+                continue;
+            }
+            inside = now_inside;
+
+            // outward normal face against the ray when entering, along the ray when leaving,
+            // this also flip normal of the subtracted surface
+            let facing_ray = hit.normal.dot(&dir) < 0.0;
+            let normal = if facing_ray == now_inside {
+                hit.normal
+            } else {
+                -hit.normal
+            };
+            f(HitInfo { normal, ..hit });
+        }
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        let left = self.left.bounding_box();
+        let right = self.right.bounding_box();
+        match self.operation {
+            CsgOperation::Union => Some(left?.union(&right?)),
+            CsgOperation::Intersection => match (left, right) {
+                // empty intersection never get hit, any box will do
+                (Some(l), Some(r)) => Some(l.intersection(&r).unwrap_or(l)),
+                (l, r) => l.or(r),
+            },
+            CsgOperation::Difference => left,
+        }
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        nested_problems(&mut problems, "left", self.left.problems());
+        nested_problems(&mut problems, "right", self.right.problems());
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtracer::geometric::{Cuboid, Sphere, SphereProxy};
+    use assert_approx_eq::assert_approx_eq;
+    use nalgebra::UnitQuaternion;
+
+    fn sphere(x: f32, radius: f32) -> Shapes {
+        Sphere::from(SphereProxy {
+            pos: Point3::new(x, 0.0, 0.0),
+            radius,
+        })
+        .into()
+    }
+
+    #[test]
+    fn difference_flip_normal() {
+        // unit sphere with a bite taken out of its left side
+        let csg = Csg::new(
+            CsgOperation::Difference,
+            sphere(0.0, 1.0),
+            sphere(-1.0, 0.5),
+        );
+        let origin = Point3::new(-5.0, 0.0, 0.0);
+
+        let hit = csg.intersect(origin, Vector3::x_axis()).unwrap();
+        assert_approx_eq!(hit.dist, 4.5);
+        // inner surface of the subtracted sphere, pointing out of the result
+        assert_approx_eq!(hit.normal.x, -1.0);
+
+        let crossings = sorted_crossings(|f| csg.crossings(origin, Vector3::x_axis(), f));
+        assert_eq!(crossings.len(), 2);
+        assert_approx_eq!(crossings[1].dist, 6.0);
+        assert_approx_eq!(crossings[1].normal.x, 1.0);
+
+        // start inside the result
+        let hit = csg.intersect(Point3::origin(), -Vector3::x_axis()).unwrap();
+        assert_approx_eq!(hit.dist, 0.5);
+        assert_approx_eq!(hit.normal.x, -1.0);
+    }
+
+    #[test]
+    fn intersection_and_union() {
+        let lens = Csg::new(
+            CsgOperation::Intersection,
+            sphere(-0.5, 1.0),
+            sphere(0.5, 1.0),
+        );
+        let crossings =
+            sorted_crossings(|f| lens.crossings(Point3::new(-5.0, 0.0, 0.0), Vector3::x_axis(), f));
+        assert_eq!(crossings.len(), 2);
+        assert_approx_eq!(crossings[0].dist, 4.5);
+        assert_approx_eq!(crossings[1].dist, 5.5);
+
+        let bb = lens.bounding_box().unwrap();
+        assert_approx_eq!(bb.min().x, -0.5);
+        assert_approx_eq!(bb.max().x, 0.5);
+
+        // nested: union of the lens and a box
+        let cuboid = Cuboid::new(
+            Point3::new(3.0, 0.0, 0.0),
+            Vector3::new(1.0, 1.0, 1.0),
+            UnitQuaternion::identity(),
+        );
+        let union = Csg::new(CsgOperation::Union, lens.into(), cuboid.into());
+        let crossings = sorted_crossings(|f| {
+            union.crossings(Point3::new(-5.0, 0.0, 0.0), Vector3::x_axis(), f)
+        });
+        assert_eq!(crossings.len(), 4);
+        let bb = union.bounding_box().unwrap();
+        assert_approx_eq!(bb.max().x, 3.5);
+    }
+}
@@ -0,0 +1,212 @@
+use nalgebra::{Point2, Point3, Unit, UnitQuaternion, Vector3};
+use serde::{Deserialize, Serialize};
+
+use super::{HitInfo, Shape};
+use crate::rtracer::validation::check_positive;
+use crate::utils::aabb::AABB;
+
+/// Box with faces perpendicular to its local axes, oriented by `rotation`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(
+    from = "crate::utils::proxy_serialize::CuboidProxy",
+    into = "crate::utils::proxy_serialize::CuboidProxy"
+)]
+pub struct Cuboid {
+    pub center: Point3<f32>,
+    pub half_size: Vector3<f32>,
+    pub rotation: UnitQuaternion<f32>,
+}
+
+/// where a ray cross one face of the cuboid
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct FaceCrossing {
+    pub dist: f32,
+    pub axis: usize,
+}
+
+impl Cuboid {
+    pub fn new(center: Point3<f32>, size: Vector3<f32>, rotation: UnitQuaternion<f32>) -> Self {
+        Cuboid {
+            center,
+            half_size: size / 2.0,
+            rotation,
+        }
+    }
+
+    /// Entry and exit of the line through origin along dir (dist may be negative)
+    pub(crate) fn face_crossings(
+        &self,
+        origin: Point3<f32>,
+        dir: Unit<Vector3<f32>>,
+    ) -> Option<(FaceCrossing, FaceCrossing)> {
+        let local_origin = self
+            .rotation
+            .inverse_transform_vector(&(origin - self.center));
+        let local_dir = self.rotation.inverse_transform_vector(&dir);
+
+        let mut enter = FaceCrossing {
+            dist: f32::NEG_INFINITY,
+            axis: 0,
+        };
+        let mut exit = FaceCrossing {
+            dist: f32::INFINITY,
+            axis: 0,
+        };
+
+        for i in 0..3 {
+            let inv_d = local_dir[i].recip();
+            let mut t0 = (-self.half_size[i] - local_origin[i]) * inv_d;
+            let mut t1 = (self.half_size[i] - local_origin[i]) * inv_d;
+            if inv_d.is_sign_negative() {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // NaN (ray parallel to and on the slab boundary) is treated as missing the slab
+            if t0.is_nan() || t1.is_nan() {
+                return None;
+            }
+            if t0 > enter.dist {
+                enter = FaceCrossing { dist: t0, axis: i };
+            }
+            if t1 < exit.dist {
+                exit = FaceCrossing { dist: t1, axis: i };
+            }
+        }
+
+        (enter.dist <= exit.dist).then(|| (enter, exit))
+    }
+
+    /// outward normal and uv of the face that the ray cross
+    pub(crate) fn face_hit(
+        &self,
+        origin: Point3<f32>,
+        dir: Unit<Vector3<f32>>,
+        crossing: FaceCrossing,
+    ) -> HitInfo {
+        let intersection = origin + dir.scale(crossing.dist);
+        let local = self
+            .rotation
+            .inverse_transform_vector(&(intersection - self.center));
+
+        let axis = crossing.axis;
+        let sign = local[axis].signum();
+        let mut local_normal = Vector3::zeros();
+        local_normal[axis] = sign;
+
+        // the other two axes in cyclic order span the face
+        let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+        let uv = Point2::new(
+            (local[a] / self.half_size[a] + 1.0) / 2.0,
+            (local[b] / self.half_size[b] + 1.0) / 2.0,
+        );
+
+        HitInfo {
+            incoming_dir: dir,
+            dist: crossing.dist,
+            intersection,
+            normal: Unit::new_unchecked(self.rotation * local_normal),
+            uv,
+            tangent: None,
+            color: None,
+            back_face: false,
+        }
+    }
+}
+
+impl Shape for Cuboid {
+    fn intersect(&self, origin: Point3<f32>, dir: Unit<Vector3<f32>>) -> Option<HitInfo> {
+        let (enter, exit) = self.face_crossings(origin, dir)?;
+
+        // ray start inside the cuboid hit it on the way out
+        let crossing = if enter.dist > 0.0 { enter } else { exit };
+        if crossing.dist <= 0.0 {
+            return None;
+        }
+
+        Some(self.face_hit(origin, dir, crossing))
+    }
+
+    fn crossings(&self, origin: Point3<f32>, dir: Unit<Vector3<f32>>, f: &mut dyn FnMut(HitInfo)) {
+        if let Some((enter, exit)) = self.face_crossings(origin, dir) {
+            f(self.face_hit(origin, dir, enter));
+            f(self.face_hit(origin, dir, exit));
+        }
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        // extent of rotated box along each world axis
+        let rotation = self.rotation.to_rotation_matrix();
+        let extent = rotation.matrix().abs() * self.half_size;
+        Some(AABB::new_uncheck(
+            self.center - extent,
+            self.center + extent,
+        ))
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        // a flat or inverted box divide by zero for its normal and uv
+        for (axis, x) in ["x", "y", "z"].iter().zip(self.half_size.iter()) {
+            check_positive(&mut problems, &format!("size.{}", axis), 2.0 * x);
+        }
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+    use std::f32::consts::FRAC_PI_4;
+
+    #[test]
+    fn face_normal_and_uv() {
+        let cuboid = Cuboid::new(
+            Point3::new(5.0, 0.0, 0.0),
+            Vector3::new(2.0, 2.0, 4.0),
+            UnitQuaternion::identity(),
+        );
+
+        let hit = cuboid
+            .intersect(Point3::origin(), Vector3::x_axis())
+            .unwrap();
+        assert_approx_eq!(hit.dist, 4.0);
+        assert_approx_eq!(hit.normal.x, -1.0);
+        assert_approx_eq!(hit.uv.x, 0.5);
+        assert_approx_eq!(hit.uv.y, 0.5);
+
+        // from inside, hit the far face
+        let hit = cuboid
+            .intersect(Point3::new(5.0, 0.0, 0.0), Vector3::z_axis())
+            .unwrap();
+        assert_approx_eq!(hit.dist, 2.0);
+        assert_approx_eq!(hit.normal.z, 1.0);
+
+        assert!(cuboid
+            .intersect(Point3::origin(), -Vector3::x_axis())
+            .is_none());
+    }
+
+    #[test]
+    fn rotated_bounding_box() {
+        let cuboid = Cuboid::new(
+            Point3::origin(),
+            Vector3::new(2.0, 2.0, 2.0),
+            UnitQuaternion::from_euler_angles(0.0, 0.0, FRAC_PI_4),
+        );
+        let bb = cuboid.bounding_box().unwrap();
+        assert_approx_eq!(bb.max().x, 2.0_f32.sqrt());
+        assert_approx_eq!(bb.max().z, 1.0);
+
+        // corner of rotated cube is hit at distance 10 - sqrt(2)
+        let hit = cuboid
+            .intersect(Point3::new(-10.0, 0.0, 0.0), Vector3::x_axis())
+            .unwrap();
+        assert_approx_eq!(hit.dist, 10.0 - 2.0_f32.sqrt(), 1e-4);
+
+        // pitch of 90 degree is where euler angles lose a degree of freedom
+        let rotation = UnitQuaternion::from_euler_angles(0.3, std::f32::consts::FRAC_PI_2, 0.2);
+        let cuboid = Cuboid::new(Point3::origin(), Vector3::new(1.0, 2.0, 3.0), rotation);
+        let saved: Cuboid = ron::de::from_str(&ron::ser::to_string(&cuboid).unwrap()).unwrap();
+        assert!(saved.rotation.angle_to(&rotation) < 1e-5);
+    }
+}
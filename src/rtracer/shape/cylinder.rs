@@ -0,0 +1,439 @@
+use std::f32::consts::PI;
+
+use nalgebra::{Point2, Point3, Unit, Vector3};
+use serde::{Deserialize, Serialize};
+
+use super::{nearest_crossing, HitInfo, Shape};
+use crate::rtracer::helper::{normalize_or_zero, perpendicular, solve_quadratic};
+use crate::rtracer::validation::{check_direction, check_non_negative};
+use crate::utils::aabb::AABB;
+
+// angle around `axis` in [0, 1)
+fn angle_around(axis: &Unit<Vector3<f32>>, radial: &Vector3<f32>) -> f32 {
+    let u_axis = perpendicular(axis);
+    let v_axis = axis.cross(&u_axis);
+    0.5 + radial.dot(&v_axis).atan2(radial.dot(&u_axis)) / (2.0 * PI)
+}
+
+/// axis and length of segment, axis is zero if start = end
+fn segment(start: &Point3<f32>, end: &Point3<f32>) -> (Unit<Vector3<f32>>, f32) {
+    let v = end - start;
+    (normalize_or_zero(v), v.norm())
+}
+
+/// Every crossing of the line with the truncated cone (frustum) surface,
+/// radius vary linearly from `r0` at start to `r1` at start + height * axis
+#[allow(clippy::too_many_arguments)]
+fn frustum_crossings(
+    start: &Point3<f32>,
+    axis: &Unit<Vector3<f32>>,
+    height: f32,
+    r0: f32,
+    r1: f32,
+    capped: bool,
+    origin: Point3<f32>,
+    dir: Unit<Vector3<f32>>,
+    f: &mut dyn FnMut(HitInfo),
+) {
+    // degenerate segment has no surface, validation report it
+    if !(height > 1e-6) {
+        return;
+    }
+    let slope = (r1 - r0) / height;
+    let o = origin - start;
+    let (oh, dh) = (o.dot(axis), dir.dot(axis));
+    let op = o - axis.scale(oh);
+    let dp = dir.as_ref() - axis.scale(dh);
+
+    // |op + t*dp|^2 = (r0 + slope*(oh + t*dh))^2
+    let r_o = r0 + slope * oh;
+    let a = dp.norm_squared() - slope * slope * dh * dh;
+    let b = 2.0 * (op.dot(&dp) - slope * dh * r_o);
+    let c = op.norm_squared() - r_o * r_o;
+
+    if let Some((t0, t1)) = solve_quadratic(a, b, c) {
+        let roots = if t0 == t1 { &[t0][..] } else { &[t0, t1][..] };
+        for &t in roots {
+            let h = oh + t * dh;
+            // outside the segment, or on the mirrored nappe of the cone
+            if !(0.0..=height).contains(&h) || r0 + slope * h < 0.0 {
+                continue;
+            }
+
+            let radial = op + dp * t;
+            let radial_length = radial.norm();
+            if radial_length < 1e-9 {
+                continue;
+            }
+
+            f(HitInfo {
+                incoming_dir: dir,
+                dist: t,
+                intersection: origin + dir.scale(t),
+                normal: Unit::new_normalize(radial / radial_length - axis.scale(slope)),
+                uv: Point2::new(angle_around(axis, &radial), h / height),
+                tangent: None,
+                color: None,
+                back_face: false,
+            });
+        }
+    }
+
+    if capped && dh.abs() > 1e-9 {
+        for &(cap_h, radius, sign) in &[(0.0, r0, -1.0), (height, r1, 1.0)] {
+            if radius <= 0.0 {
+                continue;
+            }
+            let t = (cap_h - oh) / dh;
+            let radial = op + dp * t;
+            if radial.norm_squared() <= radius * radius {
+                f(HitInfo {
+                    incoming_dir: dir,
+                    dist: t,
+                    intersection: origin + dir.scale(t),
+                    normal: Unit::new_unchecked(axis.scale(sign)),
+                    uv: Point2::new(angle_around(axis, &radial), radial.norm() / radius),
+                    tangent: None,
+                    color: None,
+                    back_face: false,
+                });
+            }
+        }
+    }
+}
+
+/// tight bounding box of two discs perpendicular to axis
+fn frustum_bounding_box(
+    start: &Point3<f32>,
+    end: &Point3<f32>,
+    axis: &Unit<Vector3<f32>>,
+    r0: f32,
+    r1: f32,
+) -> AABB {
+    // extent of unit disc along world axis i = sqrt(1 - axis_i^2)
+    let disc_extent = axis.map(|a| (1.0 - a * a).max(0.0).sqrt());
+    let start_bb = AABB::new_uncheck(start - disc_extent * r0, start + disc_extent * r0);
+    let end_bb = AABB::new_uncheck(end - disc_extent * r1, end + disc_extent * r1);
+    start_bb.union(&end_bb)
+}
+
+/// Finite cylinder from `start` to `end`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(
+    from = "crate::utils::proxy_serialize::CylinderProxy",
+    into = "crate::utils::proxy_serialize::CylinderProxy"
+)]
+pub struct Cylinder {
+    pub start: Point3<f32>,
+    pub end: Point3<f32>,
+    pub radius: f32,
+    pub capped: bool,
+    axis: Unit<Vector3<f32>>,
+    height: f32,
+}
+
+impl Cylinder {
+    pub fn new(start: Point3<f32>, end: Point3<f32>, radius: f32, capped: bool) -> Self {
+        let (axis, height) = segment(&start, &end);
+        Cylinder {
+            start,
+            end,
+            radius,
+            capped,
+            axis,
+            height,
+        }
+    }
+}
+
+impl Shape for Cylinder {
+    fn intersect(&self, origin: Point3<f32>, dir: Unit<Vector3<f32>>) -> Option<HitInfo> {
+        nearest_crossing(|f| self.crossings(origin, dir, f))
+    }
+
+    fn crossings(&self, origin: Point3<f32>, dir: Unit<Vector3<f32>>, f: &mut dyn FnMut(HitInfo)) {
+        frustum_crossings(
+            &self.start,
+            &self.axis,
+            self.height,
+            self.radius,
+            self.radius,
+            self.capped,
+            origin,
+            dir,
+            f,
+        )
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(frustum_bounding_box(
+            &self.start,
+            &self.end,
+            &self.axis,
+            self.radius,
+            self.radius,
+        ))
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        check_direction(&mut problems, "end - start", &self.axis);
+        check_non_negative(&mut problems, "radius", self.radius);
+        problems
+    }
+}
+
+/// Cone (or truncated cone) from `start` to `end` with radius varying linearly between them
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(
+    from = "crate::utils::proxy_serialize::ConeProxy",
+    into = "crate::utils::proxy_serialize::ConeProxy"
+)]
+pub struct Cone {
+    pub start: Point3<f32>,
+    pub end: Point3<f32>,
+    pub start_radius: f32,
+    pub end_radius: f32,
+    pub capped: bool,
+    axis: Unit<Vector3<f32>>,
+    height: f32,
+}
+
+impl Cone {
+    pub fn new(
+        start: Point3<f32>,
+        end: Point3<f32>,
+        start_radius: f32,
+        end_radius: f32,
+        capped: bool,
+    ) -> Self {
+        let (axis, height) = segment(&start, &end);
+        Cone {
+            start,
+            end,
+            start_radius,
+            end_radius,
+            capped,
+            axis,
+            height,
+        }
+    }
+}
+
+impl Shape for Cone {
+    fn intersect(&self, origin: Point3<f32>, dir: Unit<Vector3<f32>>) -> Option<HitInfo> {
+        nearest_crossing(|f| self.crossings(origin, dir, f))
+    }
+
+    fn crossings(&self, origin: Point3<f32>, dir: Unit<Vector3<f32>>, f: &mut dyn FnMut(HitInfo)) {
+        frustum_crossings(
+            &self.start,
+            &self.axis,
+            self.height,
+            self.start_radius,
+            self.end_radius,
+            self.capped,
+            origin,
+            dir,
+            f,
+        )
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(frustum_bounding_box(
+            &self.start,
+            &self.end,
+            &self.axis,
+            self.start_radius,
+            self.end_radius,
+        ))
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        check_direction(&mut problems, "end - start", &self.axis);
+        check_non_negative(&mut problems, "start_radius", self.start_radius);
+        check_non_negative(&mut problems, "end_radius", self.end_radius);
+        problems
+    }
+}
+
+/// Cylinder with hemispherical ends, ie. every point within `radius` of segment start..end
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(
+    from = "crate::utils::proxy_serialize::CapsuleProxy",
+    into = "crate::utils::proxy_serialize::CapsuleProxy"
+)]
+pub struct Capsule {
+    pub start: Point3<f32>,
+    pub end: Point3<f32>,
+    pub radius: f32,
+    axis: Unit<Vector3<f32>>,
+    height: f32,
+}
+
+impl Capsule {
+    pub fn new(start: Point3<f32>, end: Point3<f32>, radius: f32) -> Self {
+        let (axis, height) = segment(&start, &end);
+        // zero length capsule is a sphere, any axis will do
+        let axis = if height > 1e-6 {
+            axis
+        } else {
+            Vector3::z_axis()
+        };
+        Capsule {
+            start,
+            end,
+            radius,
+            axis,
+            height,
+        }
+    }
+}
+
+impl Shape for Capsule {
+    fn intersect(&self, origin: Point3<f32>, dir: Unit<Vector3<f32>>) -> Option<HitInfo> {
+        nearest_crossing(|f| self.crossings(origin, dir, f))
+    }
+
+    fn crossings(&self, origin: Point3<f32>, dir: Unit<Vector3<f32>>, f: &mut dyn FnMut(HitInfo)) {
+        let r = self.radius;
+        let total_length = self.height + 2.0 * r;
+
+        // side, v is remapped to cover the whole capsule
+        frustum_crossings(
+            &self.start,
+            &self.axis,
+            self.height,
+            r,
+            r,
+            false,
+            origin,
+            dir,
+            &mut |mut hit| {
+                hit.uv.y = (hit.uv.y * self.height + r) / total_length;
+                f(hit)
+            },
+        );
+
+        // hemisphere, only the half pointing away from the segment
+        for &(center, sign) in &[(self.start, -1.0), (self.end, 1.0)] {
+            let o = origin - center;
+            let half_b = dir.dot(&o);
+            let discriminant = half_b * half_b - (o.norm_squared() - r * r);
+            if discriminant < 0.0 {
+                continue;
+            }
+            let sqrt_d = discriminant.sqrt();
+            for &t in &[-half_b - sqrt_d, -half_b + sqrt_d] {
+                let radial = o + dir.scale(t);
+                let h = radial.dot(&self.axis);
+                if h * sign < 0.0 {
+                    continue;
+                }
+
+                let along = if sign < 0.0 {
+                    r + h
+                } else {
+                    r + self.height + h
+                };
+                f(HitInfo {
+                    incoming_dir: dir,
+                    dist: t,
+                    intersection: origin + dir.scale(t),
+                    normal: Unit::new_normalize(radial),
+                    uv: Point2::new(angle_around(&self.axis, &radial), along / total_length),
+                    tangent: None,
+                    color: None,
+                    back_face: false,
+                });
+            }
+        }
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        let r = Vector3::new(self.radius, self.radius, self.radius);
+        let bb = AABB::new(self.start, self.end);
+        Some(AABB::new_uncheck(bb.min() - r, bb.max() + r))
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        check_non_negative(&mut problems, "radius", self.radius);
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtracer::shape::sorted_crossings;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn capped_cylinder() {
+        let cylinder = Cylinder::new(Point3::origin(), Point3::new(0.0, 0.0, 2.0), 1.0, true);
+
+        // through the side
+        let hit = cylinder
+            .intersect(Point3::new(-5.0, 0.0, 1.0), Vector3::x_axis())
+            .unwrap();
+        assert_approx_eq!(hit.dist, 4.0);
+        assert_approx_eq!(hit.normal.x, -1.0);
+        assert_approx_eq!(hit.uv.y, 0.5);
+
+        // through both caps
+        let crossings = sorted_crossings(|f| {
+            cylinder.crossings(Point3::new(0.0, 0.5, 5.0), -Vector3::z_axis(), f)
+        });
+        assert_eq!(crossings.len(), 2);
+        assert_approx_eq!(crossings[0].dist, 3.0);
+        assert_approx_eq!(crossings[0].normal.z, 1.0);
+        assert_approx_eq!(crossings[1].dist, 5.0);
+        assert_approx_eq!(crossings[1].normal.z, -1.0);
+
+        let bb = cylinder.bounding_box().unwrap();
+        assert_approx_eq!(bb.max().x, 1.0);
+        assert_approx_eq!(bb.max().z, 2.0);
+    }
+
+    #[test]
+    fn cone_normal() {
+        // 45 degree cone with apex at z = 1
+        let cone = Cone::new(Point3::origin(), Point3::new(0.0, 0.0, 1.0), 1.0, 0.0, true);
+        let hit = cone
+            .intersect(Point3::new(-5.0, 0.0, 0.5), Vector3::x_axis())
+            .unwrap();
+        assert_approx_eq!(hit.dist, 4.5);
+        assert_approx_eq!(hit.normal.x, -std::f32::consts::FRAC_1_SQRT_2);
+        assert_approx_eq!(hit.normal.z, std::f32::consts::FRAC_1_SQRT_2);
+
+        // mirrored nappe above the apex is not part of the cone
+        assert!(cone
+            .intersect(Point3::new(-5.0, 0.0, 1.5), Vector3::x_axis())
+            .is_none());
+    }
+
+    #[test]
+    fn capsule_ends() {
+        let capsule = Capsule::new(Point3::origin(), Point3::new(0.0, 0.0, 2.0), 0.5);
+        let crossings = sorted_crossings(|f| {
+            capsule.crossings(Point3::new(0.0, 0.0, 5.0), -Vector3::z_axis(), f)
+        });
+        assert_eq!(crossings.len(), 2);
+        assert_approx_eq!(crossings[0].dist, 2.5);
+        assert_approx_eq!(crossings[1].dist, 5.5);
+        assert_approx_eq!(crossings[0].uv.y, 1.0);
+        assert_approx_eq!(crossings[1].uv.y, 0.0);
+
+        // start = end is a sphere, but a cylinder has nothing to hit and is reported
+        let sphere = Capsule::new(Point3::origin(), Point3::origin(), 0.5);
+        let hit = sphere.intersect(Point3::new(0.0, 0.0, 5.0), -Vector3::z_axis());
+        assert_approx_eq!(hit.unwrap().dist, 4.5);
+        let cylinder = Cylinder::new(Point3::origin(), Point3::origin(), 1.0, true);
+        assert!(cylinder
+            .intersect(Point3::new(0.0, 0.0, 5.0), -Vector3::z_axis())
+            .is_none());
+        assert_eq!(cylinder.problems(), ["`end - start` has zero length"]);
+    }
+}
@@ -0,0 +1,356 @@
+use nalgebra::{Point2, Point3, Unit, Vector3};
+use serde::{Deserialize, Serialize};
+
+use super::{HitInfo, Shape};
+use crate::rtracer::validation::{check_non_negative, check_positive, nested_problems};
+use crate::utils::aabb::AABB;
+
+/// Signed distance function, negative inside
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum SdfNode {
+    Sphere {
+        center: Point3<f32>,
+        radius: f32,
+    },
+    Box {
+        center: Point3<f32>,
+        size: Vector3<f32>,
+        // radius of rounded edges, included in size
+        #[serde(default)]
+        rounding: f32,
+    },
+    // axis = z
+    Torus {
+        center: Point3<f32>,
+        major_radius: f32,
+        minor_radius: f32,
+    },
+    // `k` = blending distance
+    SmoothUnion {
+        a: Box<SdfNode>,
+        b: Box<SdfNode>,
+        k: f32,
+    },
+    // carve `b` out of `a`
+    SmoothSubtract {
+        a: Box<SdfNode>,
+        b: Box<SdfNode>,
+        k: f32,
+    },
+    // infinite copies of `node` with spacing `period`, zero component = no repetition
+    Repeat {
+        node: Box<SdfNode>,
+        period: Vector3<f32>,
+    },
+    Mandelbulb {
+        center: Point3<f32>,
+        #[serde(default = "default_one")]
+        scale: f32,
+        #[serde(default = "default_power")]
+        power: f32,
+        #[serde(default = "default_iterations")]
+        iterations: u32,
+    },
+}
+
+fn default_one() -> f32 {
+    1.0
+}
+
+fn default_power() -> f32 {
+    8.0
+}
+
+fn default_iterations() -> u32 {
+    12
+}
+
+fn mix(a: f32, b: f32, h: f32) -> f32 {
+    a * (1.0 - h) + b * h
+}
+
+impl SdfNode {
+    pub fn distance(&self, p: &Point3<f32>) -> f32 {
+        match self {
+            SdfNode::Sphere { center, radius } => (p - center).norm() - radius,
+            SdfNode::Box {
+                center,
+                size,
+                rounding,
+            } => {
+                let q = (p - center).abs() - (size / 2.0).add_scalar(-rounding);
+                let outside = q.sup(&Vector3::zeros()).norm();
+                let inside = q.max().min(0.0);
+                outside + inside - rounding
+            }
+            SdfNode::Torus {
+                center,
+                major_radius,
+                minor_radius,
+            } => {
+                let q = p - center;
+                let ring = q.xy().norm() - major_radius;
+                (ring * ring + q.z * q.z).sqrt() - minor_radius
+            }
+            SdfNode::SmoothUnion { a, b, k } => {
+                let (da, db) = (a.distance(p), b.distance(p));
+                // no blend radius, plain union rather than dividing by zero
+                if !(*k > 1e-6) {
+                    return da.min(db);
+                }
+                let h = (0.5 + 0.5 * (db - da) / k).max(0.0).min(1.0);
+                mix(db, da, h) - k * h * (1.0 - h)
+            }
+            SdfNode::SmoothSubtract { a, b, k } => {
+                let (da, db) = (a.distance(p), b.distance(p));
+                if !(*k > 1e-6) {
+                    return da.max(-db);
+                }
+                let h = (0.5 - 0.5 * (da + db) / k).max(0.0).min(1.0);
+                mix(da, -db, h) + k * h * (1.0 - h)
+            }
+            SdfNode::Repeat { node, period } => {
+                let mut q = *p;
+                for i in 0..3 {
+                    if period[i] > 0.0 {
+                        q[i] -= period[i] * (q[i] / period[i]).round();
+                    }
+                }
+                node.distance(&q)
+            }
+            SdfNode::Mandelbulb {
+                center,
+                scale,
+                power,
+                iterations,
+            } => {
+                // http://blog.hvidtfeldts.net/index.php/2011/09/distance-estimated-3d-fractals-v-the-mandelbulb-different-de-approximations/
+                let c = (p - center) / *scale;
+                let mut z = c;
+                let mut dr = 1.0;
+                let mut r = z.norm();
+                for _ in 0..*iterations {
+                    if r > 2.0 {
+                        break;
+                    }
+                    let theta = (z.z / r).max(-1.0).min(1.0).acos() * power;
+                    let phi = z.y.atan2(z.x) * power;
+                    dr = r.powf(power - 1.0) * power * dr + 1.0;
+                    z = Vector3::new(
+                        theta.sin() * phi.cos(),
+                        phi.sin() * theta.sin(),
+                        theta.cos(),
+                    ) * r.powf(*power)
+                        + c;
+                    r = z.norm();
+                }
+                // origin of the bulb, log(0) is -inf
+                if r < 1e-6 {
+                    return 0.0;
+                }
+                0.5 * r.ln() * r / dr * scale
+            }
+        }
+    }
+
+    // invalid parameter of this node and the nodes under it
+    fn problems(&self) -> Vec<String> {
+        fn check_vector(problems: &mut Vec<String>, name: &str, v: &Vector3<f32>) {
+            for (axis, x) in ["x", "y", "z"].iter().zip(v.iter()) {
+                check_non_negative(problems, &format!("{}.{}", name, axis), *x);
+            }
+        }
+
+        let mut problems = Vec::new();
+        match self {
+            SdfNode::Sphere { radius, .. } => check_non_negative(&mut problems, "radius", *radius),
+            SdfNode::Box { size, rounding, .. } => {
+                check_vector(&mut problems, "size", size);
+                check_non_negative(&mut problems, "rounding", *rounding);
+            }
+            SdfNode::Torus {
+                major_radius,
+                minor_radius,
+                ..
+            } => {
+                check_non_negative(&mut problems, "major_radius", *major_radius);
+                check_non_negative(&mut problems, "minor_radius", *minor_radius);
+            }
+            SdfNode::SmoothUnion { a, b, k } | SdfNode::SmoothSubtract { a, b, k } => {
+                check_non_negative(&mut problems, "k", *k);
+                nested_problems(&mut problems, "a", a.problems());
+                nested_problems(&mut problems, "b", b.problems());
+            }
+            SdfNode::Repeat { node, period } => {
+                check_vector(&mut problems, "period", period);
+                nested_problems(&mut problems, "node", node.problems());
+            }
+            SdfNode::Mandelbulb { scale, power, .. } => {
+                check_positive(&mut problems, "scale", *scale);
+                check_positive(&mut problems, "power", *power);
+            }
+        }
+        problems
+    }
+}
+
+/// Shape defined by signed distance function, intersected by sphere tracing inside `bounds`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(
+    from = "crate::utils::proxy_serialize::SdfProxy",
+    into = "crate::utils::proxy_serialize::SdfProxy"
+)]
+pub struct Sdf {
+    pub bounds: AABB,
+    pub node: SdfNode,
+    pub max_steps: u32,
+    // surface threshold, also used as gradient step
+    pub epsilon: f32,
+}
+
+impl Sdf {
+    /// outward normal from central difference gradient (tetrahedron technique)
+    fn normal_at(&self, p: &Point3<f32>) -> Unit<Vector3<f32>> {
+        let h = self.epsilon;
+        let offsets = [
+            Vector3::new(1.0, -1.0, -1.0),
+            Vector3::new(-1.0, -1.0, 1.0),
+            Vector3::new(-1.0, 1.0, -1.0),
+            Vector3::new(1.0, 1.0, 1.0),
+        ];
+        let gradient = offsets
+            .iter()
+            .map(|k| k * self.node.distance(&(p + k * h)))
+            .fold(Vector3::zeros(), |acc, v| acc + v);
+        Unit::try_new(gradient, 1e-12).unwrap_or_else(Vector3::z_axis)
+    }
+}
+
+impl Shape for Sdf {
+    fn intersect(&self, origin: Point3<f32>, dir: Unit<Vector3<f32>>) -> Option<HitInfo> {
+        let [t_start, t_end] = self.bounds.calc_ray_hit_span(origin, dir, &(0.0..))?;
+
+        let mut t = t_start;
+        for _ in 0..self.max_steps {
+            if t > t_end {
+                return None;
+            }
+            let d = self.node.distance(&(origin + dir.scale(t))).abs();
+
+            // ignore the surface right at the start, so ray leaving the surface doesn't hit it again
+            if t - t_start > 4.0 * self.epsilon {
+                if d < self.epsilon {
+                    let intersection = origin + dir.scale(t);
+                    let normal = self.normal_at(&intersection);
+
+                    // project on the bounds along dominant normal axis, same as cuboid face
+                    let axis = normal.iamax();
+                    let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+                    let extent = self.bounds.max() - self.bounds.min();
+                    let local = intersection - self.bounds.min();
+                    let uv = Point2::new(local[a] / extent[a], local[b] / extent[b]);
+
+                    return Some(HitInfo {
+                        incoming_dir: dir,
+                        dist: t,
+                        intersection,
+                        normal,
+                        uv,
+                        tangent: None,
+                        color: None,
+                        back_face: false,
+                    });
+                }
+                t += d;
+            } else {
+                t += d.max(self.epsilon);
+            }
+        }
+        None
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.bounds.clone())
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        nested_problems(&mut problems, "node", self.node.problems());
+        if self.max_steps == 0 {
+            problems.push("`max_steps` is 0, it must be at least 1".to_owned());
+        }
+        check_positive(&mut problems, "epsilon", self.epsilon);
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn sphere(x: f32) -> Box<SdfNode> {
+        Box::new(SdfNode::Sphere {
+            center: Point3::new(x, 0.0, 0.0),
+            radius: 1.0,
+        })
+    }
+
+    #[test]
+    fn sphere_trace() {
+        let sdf = Sdf {
+            bounds: AABB::from_floats(-2.0, -2.0, -2.0, 2.0, 2.0, 2.0),
+            node: *sphere(0.0),
+            max_steps: 256,
+            epsilon: 1e-5,
+        };
+
+        let hit = sdf
+            .intersect(Point3::new(-5.0, 0.0, 0.0), Vector3::x_axis())
+            .unwrap();
+        assert_approx_eq!(hit.dist, 4.0, 1e-3);
+        assert_approx_eq!(hit.normal.x, -1.0, 1e-3);
+
+        // from inside, hit the far side
+        let hit = sdf.intersect(Point3::origin(), Vector3::y_axis()).unwrap();
+        assert_approx_eq!(hit.dist, 1.0, 1e-3);
+        assert_approx_eq!(hit.normal.y, 1.0, 1e-3);
+    }
+
+    #[test]
+    fn smooth_operations() {
+        let union = SdfNode::SmoothUnion {
+            a: sphere(-1.0),
+            b: sphere(1.0),
+            k: 0.5,
+        };
+        // blend fill the gap between the two touching spheres
+        assert!(union.distance(&Point3::new(0.0, 0.1, 0.0)) < 0.0);
+        assert_approx_eq!(union.distance(&Point3::new(-3.0, 0.0, 0.0)), 1.0);
+
+        // k = 0 is a hard union, the spheres only touch at the origin
+        let hard = SdfNode::SmoothUnion {
+            a: sphere(-1.0),
+            b: sphere(1.0),
+            k: 0.0,
+        };
+        assert_approx_eq!(
+            hard.distance(&Point3::new(0.0, 1.0, 0.0)),
+            2.0_f32.sqrt() - 1.0
+        );
+
+        let subtract = SdfNode::SmoothSubtract {
+            a: sphere(0.0),
+            b: sphere(1.0),
+            k: 0.1,
+        };
+        assert!(subtract.distance(&Point3::new(0.5, 0.0, 0.0)) > 0.0);
+        assert!(subtract.distance(&Point3::new(-0.5, 0.0, 0.0)) < 0.0);
+
+        let repeat = SdfNode::Repeat {
+            node: sphere(0.0),
+            period: Vector3::new(4.0, 0.0, 0.0),
+        };
+        assert_approx_eq!(repeat.distance(&Point3::new(8.0, 2.0, 0.0)), 1.0);
+    }
+}
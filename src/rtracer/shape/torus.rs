@@ -0,0 +1,181 @@
+use std::f32::consts::PI;
+
+use nalgebra::{Point2, Point3, Unit, UnitQuaternion, Vector3};
+use serde::{Deserialize, Serialize};
+
+use super::{nearest_crossing, HitInfo, Shape};
+use crate::rtracer::helper::solve_quartic;
+use crate::rtracer::validation::{check_direction, check_non_negative};
+use crate::utils::aabb::AABB;
+
+/// Torus around `axis`, `major_radius` is distance from center to the tube center
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(
+    from = "crate::utils::proxy_serialize::TorusProxy",
+    into = "crate::utils::proxy_serialize::TorusProxy"
+)]
+pub struct Torus {
+    pub center: Point3<f32>,
+    pub axis: Unit<Vector3<f32>>,
+    pub major_radius: f32,
+    pub minor_radius: f32,
+    // rotate local space (axis = z) into world space
+    rotation: UnitQuaternion<f32>,
+}
+
+impl Torus {
+    pub fn new(
+        center: Point3<f32>,
+        axis: Unit<Vector3<f32>>,
+        major_radius: f32,
+        minor_radius: f32,
+    ) -> Self {
+        let rotation = UnitQuaternion::rotation_between(&Vector3::z(), &axis)
+            // axis is exactly -z
+            .unwrap_or_else(|| UnitQuaternion::from_axis_angle(&Vector3::x_axis(), PI));
+        Torus {
+            center,
+            axis,
+            major_radius,
+            minor_radius,
+            rotation,
+        }
+    }
+}
+
+impl Shape for Torus {
+    fn intersect(&self, origin: Point3<f32>, dir: Unit<Vector3<f32>>) -> Option<HitInfo> {
+        nearest_crossing(|f| self.crossings(origin, dir, f))
+    }
+
+    fn crossings(&self, origin: Point3<f32>, dir: Unit<Vector3<f32>>, f: &mut dyn FnMut(HitInfo)) {
+        let (big_r, small_r) = (self.major_radius as f64, self.minor_radius as f64);
+        let local_o = self
+            .rotation
+            .inverse_transform_vector(&(origin - self.center));
+        let local_d = self.rotation.inverse_transform_vector(&dir);
+
+        // start from the closest point to the center when the ray is far away,
+        // large origin distance destroy precision of quartic coefficients
+        let bound = self.major_radius + self.minor_radius;
+        let shift = (-local_o.dot(&local_d)).max(0.0);
+        let shift = if local_o.norm_squared() > 4.0 * bound * bound {
+            shift
+        } else {
+            0.0
+        };
+
+        let o = (local_o + local_d * shift).cast::<f64>();
+        let d = local_d.cast::<f64>();
+
+        // (|p|^2 + R^2 - r^2)^2 = 4 R^2 (p.x^2 + p.y^2), p = o + t*d, |d| = 1
+        let sq_big_r = big_r * big_r;
+        let e = o.norm_squared() - sq_big_r - small_r * small_r;
+        let f_ = o.dot(&d);
+        let c3 = 4.0 * f_;
+        let c2 = 2.0 * e + 4.0 * f_ * f_ + 4.0 * sq_big_r * d.z * d.z;
+        let c1 = 4.0 * f_ * e + 8.0 * sq_big_r * o.z * d.z;
+        let c0 = e * e - 4.0 * sq_big_r * (small_r * small_r - o.z * o.z);
+
+        let (roots, count) = solve_quartic(1.0, c3, c2, c1, c0);
+        for &t in &roots[..count] {
+            let dist = t as f32 + shift;
+            let p = local_o + local_d * dist;
+
+            // gradient of the implicit surface
+            let k = p.norm_squared() - self.major_radius.powi(2) - self.minor_radius.powi(2);
+            let local_normal = Vector3::new(
+                p.x * k,
+                p.y * k,
+                p.z * (k + 2.0 * self.major_radius.powi(2)),
+            );
+
+            let u = 0.5 + p.y.atan2(p.x) / (2.0 * PI);
+            let ring = (p.x * p.x + p.y * p.y).sqrt() - self.major_radius;
+            let v = 0.5 + p.z.atan2(ring) / (2.0 * PI);
+
+            f(HitInfo {
+                incoming_dir: dir,
+                dist,
+                intersection: origin + dir.scale(dist),
+                normal: Unit::new_normalize(self.rotation * local_normal),
+                uv: Point2::new(u, v),
+                tangent: None,
+                color: None,
+                back_face: false,
+            });
+        }
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        // ring of radius R perpendicular to axis, inflated by r
+        let extent = self
+            .axis
+            .map(|a| self.major_radius * (1.0 - a * a).max(0.0).sqrt() + self.minor_radius);
+        Some(AABB::new_uncheck(
+            self.center - extent,
+            self.center + extent,
+        ))
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        check_direction(&mut problems, "axis", &self.axis);
+        check_non_negative(&mut problems, "major_radius", self.major_radius);
+        check_non_negative(&mut problems, "minor_radius", self.minor_radius);
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtracer::shape::sorted_crossings;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn torus_crossings() {
+        let torus = Torus::new(Point3::origin(), Vector3::z_axis(), 2.0, 0.5);
+
+        // through both sides of the tube, far away origin
+        let crossings = sorted_crossings(|f| {
+            torus.crossings(Point3::new(-1000.0, 0.0, 0.0), Vector3::x_axis(), f)
+        });
+        assert_eq!(crossings.len(), 4);
+        for (hit, &x) in crossings.iter().zip(&[-2.5, -1.5, 1.5, 2.5]) {
+            assert_approx_eq!(hit.intersection.x, x, 1e-3);
+        }
+        assert_approx_eq!(crossings[0].normal.x, -1.0, 1e-4);
+        assert_approx_eq!(crossings[1].normal.x, 1.0, 1e-4);
+
+        // through the hole
+        assert!(torus
+            .intersect(Point3::new(0.0, 0.0, 5.0), -Vector3::z_axis())
+            .is_none());
+    }
+
+    #[test]
+    fn tilted_bounding_box() {
+        let torus = Torus::new(Point3::origin(), Vector3::x_axis(), 2.0, 0.5);
+        let bb = torus.bounding_box().unwrap();
+        assert_approx_eq!(bb.max().x, 0.5);
+        assert_approx_eq!(bb.max().y, 2.5);
+        assert_approx_eq!(bb.max().z, 2.5);
+    }
+
+    #[test]
+    fn axis_from_file() {
+        let torus: Torus = ron::de::from_str(
+            "(center: [0, 0, 0], axis: [0, 0, 2], major_radius: 2, minor_radius: 0.5)",
+        )
+        .unwrap();
+        assert_approx_eq!(torus.axis.norm(), 1.0);
+        assert!(torus.problems().is_empty());
+
+        let torus: Torus = ron::de::from_str(
+            "(center: [0, 0, 0], axis: [0, 0, 0], major_radius: 2, minor_radius: 0.5)",
+        )
+        .unwrap();
+        assert_eq!(torus.problems(), ["`axis` has zero length"]);
+    }
+}
@@ -0,0 +1,116 @@
+use std::cell::Cell;
+use std::fmt::{self, Display, Formatter};
+
+use serde::Serialize;
+
+/// Counters of one render, each thread count in its own `ThreadBuffer` and they're merged at the end
+#[derive(Clone, Default, Debug, Serialize)]
+pub struct RenderStats {
+    pub camera_rays: u64,
+    pub shadow_rays: u64,
+    // rays spawned by materials: reflection, refraction, scattering...
+    pub indirect_rays: u64,
+    // bounding box tested during BVH traversal, top level and inside meshes and curves
+    pub bvh_nodes_visited: u64,
+    // scene object intersection tested, plus the triangles and curve segments inside them
+    pub primitive_tests: u64,
+    pub max_depth: usize,
+    // seconds spent loading the scene and building the BVH,
+    // for later animation frames only the top level rebuild
+    pub build_time: f32,
+    pub render_time: f32,
+}
+
+thread_local! {
+    // (nodes visited, leaves tested) by traversal inside a shape, which doesn't have
+    // the thread's stats at hand. The renderer collect them after testing the objects
+    static NESTED_TRAVERSAL: Cell<(u64, u64)> = Cell::new((0, 0));
+}
+
+pub(crate) fn count_nested_traversal(nodes: u64, leaves: u64) {
+    NESTED_TRAVERSAL.with(|counts| {
+        let (n, l) = counts.get();
+        counts.set((n + nodes, l + leaves));
+    });
+}
+
+impl RenderStats {
+    /// add what shapes counted on this thread since the last call
+    pub(crate) fn collect_nested_traversal(&mut self) {
+        let (nodes, leaves) = NESTED_TRAVERSAL.with(|counts| counts.replace((0, 0)));
+        self.bvh_nodes_visited += nodes;
+        self.primitive_tests += leaves;
+    }
+
+    pub fn merge(&mut self, other: &RenderStats) {
+        self.camera_rays += other.camera_rays;
+        self.shadow_rays += other.shadow_rays;
+        self.indirect_rays += other.indirect_rays;
+        self.bvh_nodes_visited += other.bvh_nodes_visited;
+        self.primitive_tests += other.primitive_tests;
+        self.max_depth = self.max_depth.max(other.max_depth);
+        self.build_time = self.build_time.max(other.build_time);
+        self.render_time = self.render_time.max(other.render_time);
+    }
+
+    pub fn merged(mut self, other: RenderStats) -> RenderStats {
+        self.merge(&other);
+        self
+    }
+
+    pub fn rays(&self) -> u64 {
+        self.camera_rays + self.shadow_rays + self.indirect_rays
+    }
+
+    fn per_ray(&self, count: u64) -> f32 {
+        count as f32 / self.rays().max(1) as f32
+    }
+}
+
+impl Display for RenderStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "camera rays          {:>12}", self.camera_rays)?;
+        writeln!(f, "shadow rays          {:>12}", self.shadow_rays)?;
+        writeln!(f, "indirect rays        {:>12}", self.indirect_rays)?;
+        writeln!(
+            f,
+            "bvh nodes / ray      {:>12.2}",
+            self.per_ray(self.bvh_nodes_visited)
+        )?;
+        writeln!(
+            f,
+            "primitive tests / ray{:>12.2}",
+            self.per_ray(self.primitive_tests)
+        )?;
+        writeln!(f, "max path depth       {:>12}", self.max_depth)?;
+        writeln!(f, "build time           {:>11.2}s", self.build_time)?;
+        write!(f, "render time          {:>11.2}s", self.render_time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_thread_stats() {
+        let a = RenderStats {
+            camera_rays: 4,
+            shadow_rays: 2,
+            bvh_nodes_visited: 10,
+            max_depth: 3,
+            ..RenderStats::default()
+        };
+        let b = RenderStats {
+            camera_rays: 4,
+            indirect_rays: 10,
+            bvh_nodes_visited: 26,
+            max_depth: 1,
+            ..RenderStats::default()
+        };
+        let stats = a.merged(b);
+        assert_eq!(stats.rays(), 20);
+        assert_eq!(stats.max_depth, 3);
+        assert_eq!(stats.per_ray(stats.bvh_nodes_visited), 1.8);
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, Weak};
+
+use custom_error::custom_error;
+use image::RgbImage;
+use nalgebra::Point2;
+use serde::{Deserialize, Serialize};
+
+use super::Color3;
+
+custom_error! { pub TextureError
+    ImageError {source: image::ImageError} = "Encounter error while loading texture image"
+}
+
+/// Color image mapped by uv, repeating outside [0, 1], v = 0 at the bottom row
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(
+    try_from = "crate::utils::proxy_serialize::ImageTextureProxy",
+    into = "crate::utils::proxy_serialize::ImageTextureProxy"
+)]
+pub struct ImageTexture {
+    // as written in the scene file, loaded relative to it
+    pub file: PathBuf,
+    image: Arc<RgbImage>,
+}
+
+impl ImageTexture {
+    /// Load image file, sharing the data with every other texture loaded from the same path
+    pub fn load(file: PathBuf) -> Result<Self, TextureError> {
+        static CACHE: Mutex<Option<HashMap<PathBuf, Weak<RgbImage>>>> = Mutex::new(None);
+
+        let key = file.canonicalize().unwrap_or_else(|_| file.clone());
+        let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+        let cache = cache.get_or_insert_with(HashMap::new);
+        if let Some(image) = cache.get(&key).and_then(Weak::upgrade) {
+            return Ok(ImageTexture { file, image });
+        }
+
+        let image = Arc::new(image::open(&file)?.into_rgb8());
+        cache.insert(key, Arc::downgrade(&image));
+        Ok(ImageTexture { file, image })
+    }
+
+    /// bilinear filtered color at uv
+    pub fn sample(&self, uv: &Point2<f32>) -> Color3 {
+        let (width, height) = self.image.dimensions();
+        let x = uv.x.rem_euclid(1.0) * width as f32 - 0.5;
+        let y = (1.0 - uv.y.rem_euclid(1.0)) * height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+
+        let texel = |i: f32, j: f32| {
+            let i = (i as i64).rem_euclid(i64::from(width)) as u32;
+            let j = (j as i64).rem_euclid(i64::from(height)) as u32;
+            let p = self.image.get_pixel(i, j).0;
+            Color3::new(p[0].into(), p[1].into(), p[2].into()) / 255.0
+        };
+
+        let top = texel(x0, y0) * (1.0 - fx) + texel(x0 + 1.0, y0) * fx;
+        let bottom = texel(x0, y0 + 1.0) * (1.0 - fx) + texel(x0 + 1.0, y0 + 1.0) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+impl Debug for ImageTexture {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let (width, height) = self.image.dimensions();
+        write!(
+            f,
+            "ImageTexture({}, {}x{})",
+            self.file.display(),
+            width,
+            height
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+    use image::Rgb;
+
+    #[test]
+    fn bilinear_repeat() {
+        // black left column, white right column
+        let image = RgbImage::from_fn(2, 2, |x, _| Rgb([255 * x as u8; 3]));
+        let texture = ImageTexture {
+            file: PathBuf::new(),
+            image: Arc::new(image),
+        };
+
+        assert_approx_eq!(texture.sample(&Point2::new(0.25, 0.5)).x, 0.0);
+        assert_approx_eq!(texture.sample(&Point2::new(0.75, 0.5)).x, 1.0);
+        assert_approx_eq!(texture.sample(&Point2::new(0.5, 0.5)).x, 0.5);
+        // wrap around the edge, half way between the right and left column
+        assert_approx_eq!(texture.sample(&Point2::new(1.0, 0.5)).x, 0.5);
+        assert_approx_eq!(texture.sample(&Point2::new(1.75, -3.5)).x, 1.0);
+    }
+}
@@ -1,21 +1,57 @@
+use rand::rngs::mock::StepRng;
 use rand::SeedableRng;
 use rand_xoshiro::Xoroshiro128Plus;
-use rand::rngs::mock::StepRng;
+
+use super::sampler::{mix, Sampler, SamplerKind, Series2D};
+use super::stats::RenderStats;
 
 // per thread variable
 #[derive(Clone)]
 pub struct ThreadBuffer {
     pub bvh_buffer: Vec<usize>,
     // pub rng: StepRng,
-    pub rng: Xoroshiro128Plus
+    pub rng: Xoroshiro128Plus,
+    pub sampler: Sampler,
+    pub stats: RenderStats,
 }
 
-impl Default for ThreadBuffer {
-    fn default() -> Self {
+impl ThreadBuffer {
+    pub fn with_sampler(kind: SamplerKind, samples_per_pixel: u32) -> Self {
         ThreadBuffer {
             bvh_buffer: Vec::new(),
             rng: Xoroshiro128Plus::seed_from_u64(0),
             // rng: StepRng::new(2, 1)
+            sampler: Sampler::new(kind, samples_per_pixel),
+            stats: RenderStats::default(),
         }
     }
+
+    /// Restart the random stream for one sample of a pixel, derived only from the global seed
+    /// and the sample's position, so the image doesn't depend on which thread render what
+    pub fn seed_sample(&mut self, seed: u64, px: u32, py: u32, sample: u32) {
+        let hash = [px as u64, py as u64, sample as u64]
+            .iter()
+            .fold(mix(seed), |hash, &x| mix(hash ^ x));
+        self.rng = Xoroshiro128Plus::seed_from_u64(hash);
+        self.sampler.start_sample(seed, px, py, sample);
+    }
+
+    // sample dimensions, see `Sampler`
+    pub fn next_1d(&mut self) -> f32 {
+        self.sampler.next_1d(&mut self.rng)
+    }
+
+    pub fn next_2d(&mut self) -> (f32, f32) {
+        self.sampler.next_2d(&mut self.rng)
+    }
+
+    pub fn get_2d(&mut self, series: Series2D, i: u32) -> (f32, f32) {
+        self.sampler.get_2d(series, i, &mut self.rng)
+    }
+}
+
+impl Default for ThreadBuffer {
+    fn default() -> Self {
+        ThreadBuffer::with_sampler(SamplerKind::Independent, 1)
+    }
 }
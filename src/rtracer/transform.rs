@@ -0,0 +1,283 @@
+use std::convert::TryFrom;
+
+use custom_error::custom_error;
+use nalgebra::{Matrix4, Point3, RowVector4, Unit, UnitQuaternion, Vector3};
+use serde::{Deserialize, Serialize};
+
+use super::HitInfo;
+use crate::utils::aabb::AABB;
+use crate::utils::proxy_serialize::TransformProxy;
+
+custom_error! { pub TransformError
+    Singular = "Transform matrix is not invertible",
+    NotAffine = "Transform matrix must be affine (last row = [0, 0, 0, 1])"
+}
+
+/// Affine transform from object space to world space
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(try_from = "TransformProxy", into = "TransformProxy")]
+pub struct Transform {
+    matrix: Matrix4<f32>,
+    inverse: Matrix4<f32>,
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Transform {
+            matrix: Matrix4::identity(),
+            inverse: Matrix4::identity(),
+        }
+    }
+
+    pub fn from_matrix(matrix: Matrix4<f32>) -> Result<Self, TransformError> {
+        if (matrix.row(3) - RowVector4::new(0.0, 0.0, 0.0, 1.0)).norm() > 1e-6 {
+            return Err(TransformError::NotAffine);
+        }
+        let inverse = matrix.try_inverse().ok_or(TransformError::Singular)?;
+        Ok(Transform { matrix, inverse })
+    }
+
+    /// scale, then rotate, then translate
+    pub fn from_trs(
+        translation: Vector3<f32>,
+        rotation: UnitQuaternion<f32>,
+        scale: Vector3<f32>,
+    ) -> Result<Self, TransformError> {
+        let matrix = Matrix4::new_translation(&translation)
+            * rotation.to_homogeneous()
+            * Matrix4::new_nonuniform_scaling(&scale);
+        Transform::from_matrix(matrix)
+    }
+
+    pub fn matrix(&self) -> &Matrix4<f32> {
+        &self.matrix
+    }
+
+    /// transform that apply `self` first, then `outer`
+    pub fn then(&self, outer: &Transform) -> Transform {
+        Transform {
+            matrix: outer.matrix * self.matrix,
+            inverse: self.inverse * outer.inverse,
+        }
+    }
+
+    #[inline]
+    pub fn point_to_world(&self, p: &Point3<f32>) -> Point3<f32> {
+        self.matrix.transform_point(p)
+    }
+
+    #[inline]
+    pub fn vector_to_world(&self, v: &Vector3<f32>) -> Vector3<f32> {
+        self.matrix.transform_vector(v)
+    }
+
+    #[inline]
+    pub fn point_to_local(&self, p: &Point3<f32>) -> Point3<f32> {
+        self.inverse.transform_point(p)
+    }
+
+    #[inline]
+    pub fn vector_to_local(&self, v: &Vector3<f32>) -> Vector3<f32> {
+        self.inverse.transform_vector(v)
+    }
+
+    /// normal transform by inverse transpose
+    #[inline]
+    pub fn normal_to_world(&self, n: &Vector3<f32>) -> Unit<Vector3<f32>> {
+        Unit::new_normalize(self.inverse.fixed_slice::<3, 3>(0, 0).tr_mul(n))
+    }
+
+    /// Transform a world space ray into object space.
+    ///
+    /// Also return the scale of distance, that is, object space distance per world space distance
+    pub fn ray_to_local(
+        &self,
+        origin: Point3<f32>,
+        dir: Unit<Vector3<f32>>,
+    ) -> (Point3<f32>, Unit<Vector3<f32>>, f32) {
+        let (local_dir, dist_scale) = Unit::new_and_get(self.vector_to_local(&dir));
+        (self.point_to_local(&origin), local_dir, dist_scale)
+    }
+
+    /// Transform object space hit of ray created by `ray_to_local` back into world space
+    pub fn hit_to_world(
+        &self,
+        hit: HitInfo,
+        origin: Point3<f32>,
+        dir: Unit<Vector3<f32>>,
+        dist_scale: f32,
+    ) -> HitInfo {
+        let dist = hit.dist / dist_scale;
+        HitInfo {
+            incoming_dir: dir,
+            dist,
+            intersection: origin + dir.scale(dist),
+            normal: self.normal_to_world(&hit.normal),
+            uv: hit.uv,
+            tangent: hit
+                .tangent
+                .map(|t| Unit::new_normalize(self.vector_to_world(&t))),
+            color: hit.color,
+            back_face: hit.back_face,
+        }
+    }
+
+    pub fn aabb_to_world(&self, aabb: &AABB) -> AABB {
+        let (min, max) = (aabb.min(), aabb.max());
+        let corner = |i: usize| {
+            Point3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            )
+        };
+
+        let first = self.point_to_world(&corner(0));
+        (1..8)
+            .map(|i| self.point_to_world(&corner(i)))
+            .fold(AABB::new_uncheck(first, first), |bb, p| {
+                bb.union(&AABB::new_uncheck(p, p))
+            })
+    }
+}
+
+/// Movement of an object over time, on top of its transform.
+/// At time t the object is rotated by `angular_velocity * t` around the pivot, then moved by `velocity * t`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Motion {
+    // distance per unit time
+    #[serde(default = "Vector3::zeros")]
+    pub velocity: Vector3<f32>,
+    // rotation axis scaled by the radians turned per unit time
+    #[serde(default = "Vector3::zeros")]
+    pub angular_velocity: Vector3<f32>,
+    // center of rotation in world space, default to origin of object space
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pivot: Option<Point3<f32>>,
+}
+
+impl Motion {
+    // constant speed around a fixed axis, unlike a power of the rotation over one unit of time
+    // it doesn't wrap around when that rotation is more than half a turn
+    fn rotation_at(&self, time: f32) -> UnitQuaternion<f32> {
+        UnitQuaternion::from_scaled_axis(self.angular_velocity * time)
+    }
+
+    /// rotation angle covered in time interval
+    pub fn angle_over(&self, duration: f32) -> f32 {
+        self.angular_velocity.norm() * duration.abs()
+    }
+
+    /// world space displacement at time, `default_pivot` is used if the motion has no pivot
+    pub fn transform_at(&self, time: f32, default_pivot: Point3<f32>) -> Transform {
+        let pivot = self.pivot.unwrap_or(default_pivot).coords;
+        let rotation = self.rotation_at(time);
+        let translation = self.velocity * time;
+
+        // built directly since inverse of rigid motion is known
+        let matrix = Matrix4::new_translation(&(translation + pivot))
+            * rotation.to_homogeneous()
+            * Matrix4::new_translation(&-pivot);
+        let inverse = Matrix4::new_translation(&pivot)
+            * rotation.inverse().to_homogeneous()
+            * Matrix4::new_translation(&(-pivot - translation));
+        Transform { matrix, inverse }
+    }
+}
+
+impl TryFrom<TransformProxy> for Transform {
+    type Error = TransformError;
+
+    fn try_from(proxy: TransformProxy) -> Result<Self, Self::Error> {
+        match proxy {
+            TransformProxy::Matrix(rows) => {
+                Transform::from_matrix(Matrix4::from_fn(|i, j| rows[i][j]))
+            }
+            TransformProxy::TRS {
+                translation,
+                rotation: (roll, pitch, yaw),
+                scale,
+            } => Transform::from_trs(
+                translation,
+                UnitQuaternion::from_euler_angles(roll, pitch, yaw),
+                scale,
+            ),
+        }
+    }
+}
+
+impl From<Transform> for TransformProxy {
+    fn from(transform: Transform) -> Self {
+        let m = transform.matrix;
+        TransformProxy::Matrix([
+            [m[(0, 0)], m[(0, 1)], m[(0, 2)], m[(0, 3)]],
+            [m[(1, 0)], m[(1, 1)], m[(1, 2)], m[(1, 3)]],
+            [m[(2, 0)], m[(2, 1)], m[(2, 2)], m[(2, 3)]],
+            [m[(3, 0)], m[(3, 1)], m[(3, 2)], m[(3, 3)]],
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+    use std::f32::consts::FRAC_PI_2;
+
+    #[test]
+    fn ray_round_trip() {
+        let transform = Transform::from_trs(
+            Vector3::new(1.0, 2.0, 3.0),
+            UnitQuaternion::from_euler_angles(0.0, 0.0, FRAC_PI_2),
+            Vector3::new(2.0, 2.0, 2.0),
+        )
+        .unwrap();
+
+        let origin = Point3::new(1.0, -5.0, 3.0);
+        let dir = Vector3::y_axis();
+        let (local_origin, local_dir, dist_scale) = transform.ray_to_local(origin, dir);
+        assert_approx_eq!(dist_scale, 0.5);
+
+        // 4 unit in world space along the ray = 2 unit in object space
+        let world = origin + dir.scale(4.0);
+        let local = local_origin + local_dir.scale(4.0 * dist_scale);
+        assert!((transform.point_to_world(&local) - world).norm() < 1e-5);
+    }
+
+    #[test]
+    fn motion_inverse() {
+        let motion = Motion {
+            velocity: Vector3::new(1.0, 0.0, 0.0),
+            angular_velocity: Vector3::new(0.0, 0.0, FRAC_PI_2),
+            pivot: None,
+        };
+        let transform = motion.transform_at(0.5, Point3::new(0.0, 1.0, 0.0));
+        assert!((transform.matrix * transform.inverse - Matrix4::identity()).norm() < 1e-5);
+
+        // pivot stay in place apart from the translation
+        let moved = transform.point_to_world(&Point3::new(0.0, 1.0, 0.0));
+        assert!((moved - Point3::new(0.5, 1.0, 0.0)).norm() < 1e-5);
+        assert_approx_eq!(motion.angle_over(1.0), FRAC_PI_2);
+
+        // three quarter turn per unit time keep turning the same way
+        let motion = Motion {
+            angular_velocity: Vector3::new(0.0, 0.0, 3.0 * FRAC_PI_2),
+            ..motion
+        };
+        let x = motion
+            .transform_at(1.0 / 3.0, Point3::origin())
+            .vector_to_world(&Vector3::x());
+        assert!((x - Vector3::y()).norm() < 1e-5);
+    }
+
+    #[test]
+    fn singular_matrix() {
+        assert!(Transform::from_matrix(Matrix4::zeros()).is_err());
+        assert!(Transform::from_trs(
+            Vector3::zeros(),
+            UnitQuaternion::identity(),
+            Vector3::new(1.0, 0.0, 1.0)
+        )
+        .is_err());
+    }
+}
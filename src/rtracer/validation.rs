@@ -0,0 +1,253 @@
+use std::fmt::{self, Display, Formatter};
+
+use nalgebra::{Unit, Vector3};
+use serde::de::DeserializeOwned;
+
+use crate::utils::overrides::{deserialize_with_overrides, Override};
+
+use super::light::Light;
+use super::material::Material;
+use super::parser::RenderConfig;
+use super::scene::SceneBuilder;
+use super::{Color3, Shape};
+
+/// Every problem found while loading a scene, reported together rather than stopping at the first
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    pub problems: Vec<String>,
+}
+
+impl ValidationReport {
+    pub fn problem(&mut self, location: impl Display, message: impl Display) {
+        self.problems.push(format!("{}: {}", location, message));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl Display for ValidationReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "found {} problem(s) in the scene", self.problems.len())?;
+        for problem in &self.problems {
+            write!(f, "\n  {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+fn line_col(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let col = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    (line, col)
+}
+
+/// Deserialize ron text, error is "source:line:col: message".
+///
+/// ron only know the position of syntax error, error raised by the data (failed proxy
+/// conversion, unknown variant, ...) is located where the deserializer stopped
+pub fn parse_ron<T: DeserializeOwned>(text: &str, source: &str) -> Result<T, String> {
+    parse_ron_with(text, source, &[])
+}
+
+/// `parse_ron` with command line overrides applied
+pub fn parse_ron_with<T: DeserializeOwned>(
+    text: &str,
+    source: &str,
+    overrides: &[Override],
+) -> Result<T, String> {
+    let mut deserializer = ron::de::Deserializer::from_str(text).map_err(|e| e.to_string())?;
+    let result = deserialize_with_overrides(&mut deserializer, overrides).and_then(|value| {
+        deserializer.end()?;
+        Ok(value)
+    });
+    result.map_err(|e| {
+        let (line, col) = if e.position.line == 0 {
+            line_col(text, text.len() - deserializer.remainder().len())
+        } else {
+            (e.position.line, e.position.col)
+        };
+        format!("{}:{}:{}: {}", source, line, col, e.code)
+    })
+}
+
+pub(crate) fn check_color(problems: &mut Vec<String>, name: &str, color: &Color3) {
+    if color.iter().any(|c| !c.is_finite()) {
+        problems.push(format!("`{}` isn't a finite color", name));
+    }
+}
+
+// zero vector normalize into NaN, or stay zero with `normalize_or_zero`.
+// Unit vector read as is from the file (light's `dir`, ...) can also be of any length
+pub(crate) fn check_direction(problems: &mut Vec<String>, name: &str, dir: &Unit<Vector3<f32>>) {
+    let length = dir.norm();
+    if !(length > 1e-6) {
+        problems.push(format!("`{}` has zero length", name));
+    } else if (length - 1.0).abs() > 1e-3 {
+        problems.push(format!("`{}` must have length 1, got {}", name, length));
+    }
+}
+
+pub(crate) fn check_non_negative(problems: &mut Vec<String>, name: &str, value: f32) {
+    if !(value >= 0.0 && value.is_finite()) {
+        problems.push(format!("`{}` must be non-negative, got {}", name, value));
+    }
+}
+
+pub(crate) fn check_positive(problems: &mut Vec<String>, name: &str, value: f32) {
+    if !(value > 0.0 && value.is_finite()) {
+        problems.push(format!("`{}` must be positive, got {}", name, value));
+    }
+}
+
+/// problems of a part (operand, child node, ...) reported under its name
+pub(crate) fn nested_problems(problems: &mut Vec<String>, name: &str, nested: Vec<String>) {
+    problems.extend(nested.into_iter().map(|p| format!("{}: {}", name, p)));
+}
+
+/// Semantic check of a scene file's content, `source` name the file in the report
+pub fn validate_scene(scene: &SceneBuilder, source: &str, report: &mut ValidationReport) {
+    for (name, material) in &scene.materials {
+        for problem in material.problems() {
+            report.problem(format_args!("{}: materials[{:?}]", source, name), problem);
+        }
+    }
+    for (i, obj) in scene.objects.iter().enumerate() {
+        let problems = obj
+            .shape
+            .problems()
+            .into_iter()
+            .chain(obj.material.problems());
+        for problem in problems {
+            report.problem(format_args!("{}: objects[{}]", source, i), problem);
+        }
+    }
+    for (i, light) in scene.lights.iter().enumerate() {
+        for problem in light.problems() {
+            report.problem(format_args!("{}: lights[{}]", source, i), problem);
+        }
+    }
+    let mut problems = Vec::new();
+    check_color(&mut problems, "skylight", &scene.skylight);
+    for problem in problems {
+        report.problem(source, problem);
+    }
+}
+
+pub fn validate_config(config: &RenderConfig, source: &str, report: &mut ValidationReport) {
+    if config.image_size == 0 {
+        report.problem(source, "`config.image_size` is 0");
+    }
+    if config.image_height == Some(0) {
+        report.problem(source, "`config.image_height` is 0");
+    }
+    if !(config.viewport_size > 0.0) {
+        report.problem(source, "`config.viewport_size` must be positive");
+    }
+    if config.samples_per_pixel == 0 {
+        report.problem(source, "`config.samples_per_pixel` is 0");
+    }
+    if let Some(progressive) = &config.progressive {
+        if progressive.pass_samples == 0 {
+            report.problem(source, "`config.progressive.pass_samples` is 0");
+        }
+        let seconds = [
+            ("write_interval", progressive.write_interval),
+            ("time_budget", progressive.time_budget),
+        ];
+        for (name, value) in seconds.iter() {
+            if value.map_or(false, |v| !(v > 0.0)) {
+                report.problem(
+                    source,
+                    format_args!("`config.progressive.{}` must be positive", name),
+                );
+            }
+        }
+        if progressive
+            .variance_threshold
+            .map_or(false, |v| !(v >= 0.0))
+        {
+            report.problem(
+                source,
+                "`config.progressive.variance_threshold` is negative",
+            );
+        }
+    }
+    if let Some(adaptive) = &config.adaptive {
+        if adaptive.min_samples < 2 {
+            report.problem(
+                source,
+                "`config.adaptive.min_samples` must be at least 2 to estimate the variance",
+            );
+        }
+        if adaptive.min_samples > config.samples_per_pixel {
+            report.problem(
+                source,
+                "`config.adaptive.min_samples` is more than `config.samples_per_pixel`",
+            );
+        }
+        if config.max_samples_per_pixel() < config.samples_per_pixel {
+            report.problem(
+                source,
+                "`config.adaptive.max_samples` is less than `config.samples_per_pixel`",
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collect_problems_and_locations() {
+        let text = "(\n    objects: [\n        (material: PBRDiffuse((color: [1, 1, 1], albedo: 1, iteration: 0)),\n         shape: Sphere((pos: [0, 0, 0], radius: -1))),\n        (material: Emission((light: [1, 1, 1])),\n         shape: Plane((pos: [0, 0, 0], norm: [0, 0, 0], span: [0, 0, 0]))),\n    ],\n)";
+        let scene: SceneBuilder = parse_ron(text, "test.ron").unwrap();
+        let mut report = ValidationReport::default();
+        validate_scene(&scene, "test.ron", &mut report);
+        assert_eq!(
+            report.problems,
+            vec![
+                "test.ron: objects[0]: `radius` must be non-negative, got -1",
+                "test.ron: objects[0]: `iteration` is 0, it must be at least 1",
+                "test.ron: objects[1]: `norm` has zero length",
+                "test.ron: objects[1]: `span` has zero length",
+            ]
+        );
+
+        // nested shapes, and value the conversion would have hidden
+        let text = r#"(
+            objects: [
+                (material: Emission((light: [1, 1, 1])),
+                 shape: Csg((operation: Union, left: Sphere((pos: [0, 0, 0], radius: 1)),
+                     right: Sdf((min: [-1, -1, -1], max: [1, 1, 1],
+                         node: SmoothUnion(a: Sphere(center: [0, 0, 0], radius: -2),
+                             b: Sphere(center: [0, 0, 0], radius: 1), k: -0.5)))))),
+                (material: Emission((light: [1, 1, 1])),
+                 shape: Disc((pos: [0, 0, 0], norm: [0, 0, 1], radius: -2))),
+                (material: Emission((light: [1, 1, 1])),
+                 shape: Cuboid((center: [0, 0, 0], size: [1, 0, -1], rotation: [0, 0, 0]))),
+            ],
+            lights: [DirectionalLight((dir: [0, 0, -2], light: [1, 1, 1]))],
+        )"#;
+        let scene: SceneBuilder = parse_ron(text, "test.ron").unwrap();
+        let mut report = ValidationReport::default();
+        validate_scene(&scene, "test.ron", &mut report);
+        assert_eq!(
+            report.problems,
+            vec![
+                "test.ron: objects[0]: right: node: `k` must be non-negative, got -0.5",
+                "test.ron: objects[0]: right: node: a: `radius` must be non-negative, got -2",
+                "test.ron: objects[1]: `radius` must be non-negative, got -2",
+                "test.ron: objects[2]: `size.y` must be positive, got 0",
+                "test.ron: objects[2]: `size.z` must be positive, got -1",
+                "test.ron: lights[0]: `dir` must have length 1, got 2",
+            ]
+        );
+
+        let error = parse_ron::<SceneBuilder>("(\n  objects: [\n    (shape: Oops(()))", "test.ron");
+        assert!(error.err().unwrap().starts_with("test.ron:3:"));
+    }
+}
@@ -0,0 +1,409 @@
+use std::fmt::{Debug, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use custom_error::custom_error;
+use nalgebra::{Point2, Point3, Unit, Vector3};
+use noise::{Fbm, MultiFractal, NoiseFn, Seedable};
+use num_traits::One;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use super::shape::Shape;
+use super::validation::{check_non_negative, check_positive};
+use super::HitInfo;
+use crate::utils::aabb::AABB;
+
+// minimum distance a ray has to travel before it can (re)enter a volume,
+// prevent ray leaving the volume from immediately hitting its boundary again
+const VOLUME_EPSILON: f32 = 1e-4;
+
+custom_error! { pub VolumeError
+    IOError {source: io::Error} = "Encounter error while opening voxel file",
+    InvalidHeader = "Voxel file must start with grid size `nx ny nz`",
+    InvalidDensity {token: String} = "Invalid density value `{token}` in voxel file",
+    SizeMismatch {expected: usize, found: usize} = "Voxel file declare {expected} voxels but contain {found}",
+    EmptyBounds = "Volume bounds must have positive size on every axis"
+}
+
+/// Dense grid of density, sampled with trilinear interpolation
+///
+/// File format (whitespace separated, `#` start a comment until end of line):
+/// ```text
+/// nx ny nz
+/// d(0,0,0) d(1,0,0) ... d(nx-1,ny-1,nz-1)
+/// ```
+/// with x varying fastest.
+pub struct VoxelGrid {
+    size: [usize; 3],
+    data: Vec<f32>,
+}
+
+impl VoxelGrid {
+    pub fn new(size: [usize; 3], data: Vec<f32>) -> Result<Self, VolumeError> {
+        let expected = size[0] * size[1] * size[2];
+        if expected == 0 {
+            return Err(VolumeError::InvalidHeader);
+        }
+        if data.len() != expected {
+            return Err(VolumeError::SizeMismatch {
+                expected,
+                found: data.len(),
+            });
+        }
+        Ok(VoxelGrid { size, data })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, VolumeError> {
+        VoxelGrid::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(source: &str) -> Result<Self, VolumeError> {
+        let mut tokens = source
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or(""))
+            .flat_map(str::split_whitespace);
+
+        let mut size = [0; 3];
+        for s in size.iter_mut() {
+            *s = tokens
+                .next()
+                .and_then(|token| token.parse().ok())
+                .ok_or(VolumeError::InvalidHeader)?;
+        }
+
+        let data = tokens
+            .map(|token| {
+                token
+                    .parse::<f32>()
+                    .map_err(|_| VolumeError::InvalidDensity {
+                        token: token.to_owned(),
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        VoxelGrid::new(size, data)
+    }
+
+    pub fn size(&self) -> [usize; 3] {
+        self.size
+    }
+
+    pub fn max_density(&self) -> f32 {
+        self.data.iter().cloned().fold(0.0, f32::max)
+    }
+
+    #[inline]
+    fn voxel(&self, x: usize, y: usize, z: usize) -> f32 {
+        self.data[x + self.size[0] * (y + self.size[1] * z)]
+    }
+
+    /// sample density at `local` coordinate, where the whole grid span [0,1]^3
+    pub fn sample(&self, local: Point3<f32>) -> f32 {
+        // voxel value are located at the center of each cell
+        let mut base = [0; 3];
+        let mut frac = [0.0; 3];
+        for i in 0..3 {
+            let max_index = self.size[i] - 1;
+            let g = (local[i] * self.size[i] as f32 - 0.5).max(0.0);
+            let index = (g.floor() as usize).min(max_index);
+            base[i] = index;
+            frac[i] = if index == max_index {
+                0.0
+            } else {
+                g - index as f32
+            };
+        }
+
+        let next = |i: usize| (base[i] + 1).min(self.size[i] - 1);
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let (x0, y0, z0) = (base[0], base[1], base[2]);
+        let (x1, y1, z1) = (next(0), next(1), next(2));
+
+        let c00 = lerp(self.voxel(x0, y0, z0), self.voxel(x1, y0, z0), frac[0]);
+        let c10 = lerp(self.voxel(x0, y1, z0), self.voxel(x1, y1, z0), frac[0]);
+        let c01 = lerp(self.voxel(x0, y0, z1), self.voxel(x1, y0, z1), frac[0]);
+        let c11 = lerp(self.voxel(x0, y1, z1), self.voxel(x1, y1, z1), frac[0]);
+
+        lerp(lerp(c00, c10, frac[1]), lerp(c01, c11, frac[1]), frac[2])
+    }
+}
+
+impl Debug for VoxelGrid {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let [x, y, z] = self.size();
+        write!(f, "VoxelGrid({}x{}x{})", x, y, z)
+    }
+}
+
+fn default_octaves() -> usize {
+    4
+}
+
+/// procedural density from fractal (fbm) perlin noise
+///
+/// density = clamp(gain * (noise - threshold), 0, 1)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NoiseParams {
+    #[serde(default)]
+    pub seed: u32,
+    #[serde(default = "default_octaves")]
+    pub octaves: usize,
+    // number of noise cycle across the volume
+    #[serde(default = "f32::one")]
+    pub frequency: f32,
+    #[serde(default)]
+    pub threshold: f32,
+    #[serde(default = "f32::one")]
+    pub gain: f32,
+    // distance (in [0,1] local unit) from bounds over which density fade to zero
+    #[serde(default)]
+    pub edge_falloff: f32,
+}
+
+/// where the density of a volume come from, this is also how volume is written in scene file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum DensitySource {
+    VoxelFile(PathBuf),
+    Noise(NoiseParams),
+}
+
+#[derive(Debug, Clone)]
+enum DensityField {
+    Voxel(Arc<VoxelGrid>),
+    Noise(NoiseParams, Fbm),
+}
+
+impl DensityField {
+    fn from_source(source: &DensitySource) -> Result<Self, VolumeError> {
+        Ok(match source {
+            DensitySource::VoxelFile(path) => DensityField::Voxel(Arc::new(VoxelGrid::load(path)?)),
+            DensitySource::Noise(params) => {
+                let fbm = Fbm::new()
+                    .set_seed(params.seed)
+                    .set_octaves(params.octaves)
+                    .set_frequency(f64::from(params.frequency));
+                DensityField::Noise(params.clone(), fbm)
+            }
+        })
+    }
+
+    fn max_density(&self) -> f32 {
+        match self {
+            DensityField::Voxel(grid) => grid.max_density(),
+            DensityField::Noise(..) => 1.0,
+        }
+    }
+
+    fn sample(&self, local: Point3<f32>) -> f32 {
+        match self {
+            DensityField::Voxel(grid) => grid.sample(local).max(0.0),
+            DensityField::Noise(params, fbm) => {
+                let n =
+                    fbm.get([f64::from(local.x), f64::from(local.y), f64::from(local.z)]) as f32;
+                let density = (params.gain * (n - params.threshold)).max(0.0).min(1.0);
+
+                if params.edge_falloff > 0.0 {
+                    let edge = (0..3)
+                        .map(|i| local[i].min(1.0 - local[i]))
+                        .fold(f32::INFINITY, f32::min);
+                    let t = (edge / params.edge_falloff).max(0.0).min(1.0);
+                    density * t * t * (3.0 - 2.0 * t)
+                } else {
+                    density
+                }
+            }
+        }
+    }
+}
+
+/// Heterogeneous participating medium bounded by `bounds`
+///
+/// extinction coefficient at p = sigma_t * density(p)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(
+    try_from = "crate::utils::proxy_serialize::VolumeProxy",
+    into = "crate::utils::proxy_serialize::VolumeProxy"
+)]
+pub struct Volume {
+    pub bounds: AABB,
+    pub sigma_t: f32,
+    // as written in the scene file, saved back unchanged
+    source: DensitySource,
+    density: DensityField,
+    // upper bound of extinction coefficient inside the volume
+    majorant: f32,
+}
+
+impl Volume {
+    pub fn new(bounds: AABB, sigma_t: f32, source: DensitySource) -> Result<Self, VolumeError> {
+        let extent = bounds.max() - bounds.min();
+        if extent.iter().any(|x| *x <= 0.0) {
+            return Err(VolumeError::EmptyBounds);
+        }
+
+        let density = DensityField::from_source(&source)?;
+        let majorant = sigma_t * density.max_density();
+        Ok(Volume {
+            bounds,
+            sigma_t,
+            source,
+            density,
+            majorant,
+        })
+    }
+
+    pub fn source(&self) -> &DensitySource {
+        &self.source
+    }
+
+    /// Same volume, saved with `source` instead of the resolved one it was loaded from
+    pub fn written_as(self, source: DensitySource) -> Self {
+        Volume { source, ..self }
+    }
+
+    /// extinction coefficient at world position `p`
+    pub fn sigma_t_at(&self, p: Point3<f32>) -> f32 {
+        let extent = self.bounds.max() - self.bounds.min();
+        let local = Point3::from((p - self.bounds.min()).component_div(&extent));
+        self.sigma_t * self.density.sample(local)
+    }
+
+    /// distance from `origin` (assumed inside the volume) to where the ray leave the volume
+    pub fn exit_distance(&self, origin: Point3<f32>, dir: Unit<Vector3<f32>>) -> f32 {
+        self.bounds
+            .calc_ray_hit_span(origin, dir, &(0.0..))
+            .map_or(0.0, |[_, t1]| t1)
+    }
+
+    /// Sample distance to the next real collision along the ray using delta tracking.
+    ///
+    /// Return `None` if the ray travel further than `t_max` without colliding.
+    /// The first step use `first`, a sample of the pixel's sampler, later ones draw from `rng`
+    pub fn delta_tracking(
+        &self,
+        origin: Point3<f32>,
+        dir: Unit<Vector3<f32>>,
+        t_max: f32,
+        first: (f32, f32),
+        rng: &mut impl Rng,
+    ) -> Option<f32> {
+        if self.majorant <= 0.0 {
+            return None;
+        }
+
+        let mut t = 0.0;
+        let mut next = Some(first);
+        loop {
+            let (u, v) = next.take().unwrap_or_else(|| (rng.gen(), rng.gen()));
+            t -= (1.0 - u).ln() / self.majorant;
+            if t >= t_max {
+                return None;
+            }
+
+            let sigma_t = self.sigma_t_at(origin + dir.scale(t));
+            if v * self.majorant < sigma_t {
+                return Some(t);
+            }
+        }
+    }
+
+    /// Estimate transmittance along the ray from `origin` up to `t_max` using ratio tracking.
+    /// Like `delta_tracking`, only the first step use the sampler's `first`
+    pub fn ratio_tracking(
+        &self,
+        origin: Point3<f32>,
+        dir: Unit<Vector3<f32>>,
+        t_max: f32,
+        first: f32,
+        rng: &mut impl Rng,
+    ) -> f32 {
+        if self.majorant <= 0.0 {
+            return 1.0;
+        }
+
+        let mut t = 0.0;
+        let mut transmittance = 1.0;
+        let mut next = Some(first);
+        loop {
+            let u = next.take().unwrap_or_else(|| rng.gen());
+            t -= (1.0 - u).ln() / self.majorant;
+            if t >= t_max {
+                return transmittance;
+            }
+
+            let sigma_t = self.sigma_t_at(origin + dir.scale(t));
+            transmittance *= 1.0 - (sigma_t / self.majorant).min(1.0);
+
+            // contribution is negligible, no point continue tracking
+            if transmittance < 1e-4 {
+                return 0.0;
+            }
+        }
+    }
+}
+
+impl Shape for Volume {
+    // ray hit the volume where it enter the bounds, or at its origin if it start inside,
+    // the actual scattering is done by the medium material
+    fn intersect(&self, origin: Point3<f32>, dir: Unit<Vector3<f32>>) -> Option<HitInfo> {
+        let [t0, _] = self
+            .bounds
+            .calc_ray_hit_span(origin, dir, &(VOLUME_EPSILON..))?;
+
+        Some(HitInfo {
+            incoming_dir: dir,
+            dist: t0,
+            intersection: origin + dir.scale(t0),
+            normal: -dir,
+            uv: Point2::origin(),
+            tangent: None,
+            color: None,
+            back_face: false,
+        })
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.bounds.clone())
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        check_non_negative(&mut problems, "sigma_t", self.sigma_t);
+        if let DensitySource::Noise(params) = &self.source {
+            check_positive(&mut problems, "density.frequency", params.frequency);
+            check_non_negative(&mut problems, "density.edge_falloff", params.edge_falloff);
+        }
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn parse_voxel_grid() {
+        let grid = VoxelGrid::parse("# smoke\n2 1 1\n0.0 1.0 # end\n").unwrap();
+        assert_eq!(grid.size(), [2, 1, 1]);
+        assert_approx_eq!(grid.max_density(), 1.0);
+
+        assert!(VoxelGrid::parse("2 2 1\n0.0 1.0 0.5").is_err());
+        assert!(VoxelGrid::parse("2 1 1\n0.0 smoke").is_err());
+        assert!(VoxelGrid::parse("2 x 1").is_err());
+    }
+
+    #[test]
+    fn trilinear_sample() {
+        let grid = VoxelGrid::parse("2 1 1  0.0 1.0").unwrap();
+        // voxel centers are at 0.25 and 0.75
+        assert_approx_eq!(grid.sample(Point3::new(0.0, 0.5, 0.5)), 0.0);
+        assert_approx_eq!(grid.sample(Point3::new(0.25, 0.5, 0.5)), 0.0);
+        assert_approx_eq!(grid.sample(Point3::new(0.5, 0.5, 0.5)), 0.5);
+        assert_approx_eq!(grid.sample(Point3::new(0.75, 0.5, 0.5)), 1.0);
+        assert_approx_eq!(grid.sample(Point3::new(1.0, 0.5, 0.5)), 1.0);
+    }
+}
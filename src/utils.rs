@@ -3,7 +3,8 @@ use serde::Serialize;
 pub mod aabb;
 pub mod cell_vec;
 #[cfg(test)]
-mod fuzzing;
+pub(crate) mod fuzzing;
+pub mod overrides;
 pub mod proxy_serialize;
 
 pub fn print_ron(x: &impl Serialize) {
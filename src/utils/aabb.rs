@@ -88,6 +88,15 @@ impl AABB {
         AABB { min, max }
     }
 
+    /// overlapping region, None if the boxes don't overlap
+    pub fn intersection(&self, other: &AABB) -> Option<AABB> {
+        let min = self.min.coords.sup(&other.min.coords);
+        let max = self.max.coords.inf(&other.max.coords);
+        (0..3)
+            .all(|i| min[i] <= max[i])
+            .then(|| AABB::new_uncheck(min.into(), max.into()))
+    }
+
     pub fn min(&self) -> &Point3<f32> {
         &self.min
     }
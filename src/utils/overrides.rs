@@ -0,0 +1,612 @@
+//! Deserializer wrapper replacing values at given paths, for `--set path=value` on the command line.
+//!
+//! Path is dot separated struct field names, map keys and sequence indices
+//! (`scene.objects.2.material`), enum variants are transparent. The value is ron, and replace the
+//! whole value at the path, so it go through the same proxy conversion and validation as the file.
+//! Missing struct field and map entry are added.
+
+use std::cell::Cell;
+use std::fmt;
+use std::str::FromStr;
+
+use custom_error::custom_error;
+use serde::de::value::StrDeserializer;
+use serde::de::IntoDeserializer;
+use serde::de::{
+    self, DeserializeSeed, Deserializer, EnumAccess, IgnoredAny, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
+};
+
+custom_error! { pub OverrideError
+    Syntax {arg: String} = "Invalid override `{arg}`, expected path=value",
+    Unused {path: String} = "Nothing at `{path}` to override"
+}
+
+#[derive(Clone)]
+pub struct Override {
+    path: Vec<String>,
+    value: String,
+    used: Cell<bool>,
+}
+
+impl FromStr for Override {
+    type Err = OverrideError;
+
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        let mut split = arg.splitn(2, '=');
+        match (split.next(), split.next()) {
+            (Some(path), Some(value)) if !path.trim().is_empty() => Ok(Override {
+                path: path.trim().split('.').map(str::to_owned).collect(),
+                // so `image_height=32` works for an Option, explicit `Some` is an error with it
+                value: if value.trim_start().starts_with("Some(") {
+                    value.to_owned()
+                } else {
+                    format!("#![enable(implicit_some)]\n{}", value)
+                },
+                used: Cell::new(false),
+            }),
+            _ => Err(OverrideError::Syntax {
+                arg: arg.to_owned(),
+            }),
+        }
+    }
+}
+
+impl Override {
+    pub fn path(&self) -> String {
+        self.path.join(".")
+    }
+}
+
+/// Deserialize `T` from `deserializer` with the overrides applied,
+/// error if any of them doesn't point to a value
+pub fn deserialize_with_overrides<'de, T, D>(
+    deserializer: D,
+    overrides: &'de [Override],
+) -> Result<T, D::Error>
+where
+    T: de::Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    for o in overrides {
+        o.used.set(false);
+    }
+    let context = Context {
+        overrides,
+        path: Vec::new(),
+        fields: None,
+    };
+    let value = T::deserialize(Wrap {
+        inner: deserializer,
+        context,
+    })?;
+    match overrides.iter().find(|o| !o.used.get()) {
+        Some(unused) => Err(de::Error::custom(OverrideError::Unused {
+            path: unused.path(),
+        })),
+        None => Ok(value),
+    }
+}
+
+#[derive(Clone)]
+struct Context<'de> {
+    overrides: &'de [Override],
+    path: Vec<String>,
+    // field names of the struct being deserialized, None for map
+    fields: Option<&'static [&'static str]>,
+}
+
+impl<'de> Context<'de> {
+    fn child(&self, key: String) -> Self {
+        let mut path = self.path.clone();
+        path.push(key);
+        Context {
+            overrides: self.overrides,
+            path,
+            fields: None,
+        }
+    }
+
+    fn exact(&self) -> Option<&'de Override> {
+        self.overrides.iter().find(|o| o.path == self.path)
+    }
+
+    // some override is below this path
+    fn has_below(&self) -> bool {
+        self.overrides
+            .iter()
+            .any(|o| o.path.len() > self.path.len() && o.path.starts_with(&self.path))
+    }
+
+    // key of direct children override, used to add missing field
+    fn child_keys(&self) -> impl Iterator<Item = &'de str> + '_ {
+        let depth = self.path.len();
+        self.overrides
+            .iter()
+            .filter(move |o| o.path.len() == depth + 1 && o.path.starts_with(&self.path))
+            .map(move |o| o.path[depth].as_str())
+    }
+}
+
+fn override_value<'de, S: DeserializeSeed<'de>, E: de::Error>(
+    seed: S,
+    o: &'de Override,
+) -> Result<S::Value, E> {
+    o.used.set(true);
+    let error = |e: ron::Error| E::custom(format_args!("in --set {}: {}", o.path(), e.code));
+    let mut deserializer = ron::de::Deserializer::from_str(&o.value).map_err(error)?;
+    seed.deserialize(&mut deserializer).map_err(error)
+}
+
+struct Wrap<'de, D> {
+    inner: D,
+    context: Context<'de>,
+}
+
+struct WrapVisitor<'de, V> {
+    inner: V,
+    context: Context<'de>,
+}
+
+struct WrapSeed<'de, S> {
+    inner: S,
+    context: Context<'de>,
+}
+
+impl<'de, S: DeserializeSeed<'de>> DeserializeSeed<'de> for WrapSeed<'de, S> {
+    type Value = S::Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        self.inner.deserialize(Wrap {
+            inner: deserializer,
+            context: self.context,
+        })
+    }
+}
+
+macro_rules! forward_deserialize {
+    ($($method:ident($($arg:ident: $ty:ty),*))*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, $($arg: $ty,)* visitor: V) -> Result<V::Value, D::Error> {
+                let visitor = WrapVisitor { inner: visitor, context: self.context };
+                self.inner.$method($($arg,)* visitor)
+            }
+        )*
+    };
+}
+
+impl<'de, D: Deserializer<'de>> Deserializer<'de> for Wrap<'de, D> {
+    type Error = D::Error;
+
+    forward_deserialize! {
+        deserialize_any() deserialize_bool() deserialize_i8() deserialize_i16() deserialize_i32()
+        deserialize_i64() deserialize_u8() deserialize_u16() deserialize_u32() deserialize_u64()
+        deserialize_f32() deserialize_f64() deserialize_char() deserialize_str()
+        deserialize_string() deserialize_bytes() deserialize_byte_buf() deserialize_option()
+        deserialize_unit() deserialize_seq() deserialize_map() deserialize_identifier()
+        deserialize_ignored_any()
+        deserialize_unit_struct(name: &'static str)
+        deserialize_newtype_struct(name: &'static str)
+        deserialize_tuple(len: usize)
+        deserialize_tuple_struct(name: &'static str, len: usize)
+        deserialize_enum(name: &'static str, variants: &'static [&'static str])
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, D::Error> {
+        let mut context = self.context;
+        context.fields = Some(fields);
+        let visitor = WrapVisitor {
+            inner: visitor,
+            context,
+        };
+        self.inner.deserialize_struct(name, fields, visitor)
+    }
+}
+
+macro_rules! forward_visit {
+    ($($method:ident($ty:ty))*) => {
+        $(
+            fn $method<E: de::Error>(self, v: $ty) -> Result<V::Value, E> {
+                self.inner.$method(v)
+            }
+        )*
+    };
+}
+
+impl<'de, V: Visitor<'de>> Visitor<'de> for WrapVisitor<'de, V> {
+    type Value = V::Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.expecting(f)
+    }
+
+    forward_visit! {
+        visit_bool(bool) visit_i8(i8) visit_i16(i16) visit_i32(i32) visit_i64(i64)
+        visit_u8(u8) visit_u16(u16) visit_u32(u32) visit_u64(u64) visit_f32(f32) visit_f64(f64)
+        visit_char(char) visit_str(&str) visit_borrowed_str(&'de str) visit_string(String)
+        visit_bytes(&[u8]) visit_borrowed_bytes(&'de [u8]) visit_byte_buf(Vec<u8>)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<V::Value, E> {
+        self.inner.visit_none()
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<V::Value, E> {
+        self.inner.visit_unit()
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<V::Value, D::Error> {
+        self.inner.visit_some(Wrap {
+            inner: deserializer,
+            context: self.context,
+        })
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<V::Value, D::Error> {
+        self.inner.visit_newtype_struct(Wrap {
+            inner: deserializer,
+            context: self.context,
+        })
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<V::Value, A::Error> {
+        self.inner.visit_seq(WrapSeq {
+            inner: seq,
+            context: self.context,
+            index: 0,
+        })
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<V::Value, A::Error> {
+        let missing = self
+            .context
+            .child_keys()
+            .filter(|key| self.context.fields.map_or(true, |f| f.contains(key)))
+            .map(str::to_owned)
+            .collect();
+        self.inner.visit_map(WrapMap {
+            inner: map,
+            context: self.context,
+            key: None,
+            missing,
+            added: false,
+        })
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<V::Value, A::Error> {
+        self.inner.visit_enum(WrapEnum {
+            inner: data,
+            context: self.context,
+        })
+    }
+}
+
+struct WrapSeq<'de, A> {
+    inner: A,
+    context: Context<'de>,
+    index: usize,
+}
+
+impl<'de, A: SeqAccess<'de>> SeqAccess<'de> for WrapSeq<'de, A> {
+    type Error = A::Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, A::Error> {
+        let context = self.context.child(self.index.to_string());
+        self.index += 1;
+        if let Some(o) = context.exact() {
+            return match self.inner.next_element::<IgnoredAny>()? {
+                Some(_) => override_value(seed, o).map(Some),
+                None => Ok(None),
+            };
+        }
+        if context.has_below() {
+            self.inner.next_element_seed(WrapSeed {
+                inner: seed,
+                context,
+            })
+        } else {
+            self.inner.next_element_seed(seed)
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.inner.size_hint()
+    }
+}
+
+struct WrapMap<'de, A> {
+    inner: A,
+    context: Context<'de>,
+    // key of the value to be read next
+    key: Option<String>,
+    // overridden key that hasn't been seen, added after the existing entries
+    missing: Vec<String>,
+    // the existing entries are all read, now adding the missing ones
+    added: bool,
+}
+
+// record the key while it's being deserialized
+// the seed is taken only if there is a key, kept for added key otherwise
+struct KeySeed<'a, S> {
+    inner: &'a mut Option<S>,
+    key: &'a mut Option<String>,
+}
+
+impl<'de, 'a, S: DeserializeSeed<'de>> DeserializeSeed<'de> for KeySeed<'a, S> {
+    type Value = S::Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        let inner = self.inner.take().expect("key seed used twice");
+        inner.deserialize(KeyDeserializer {
+            inner: deserializer,
+            key: self.key,
+        })
+    }
+}
+
+struct KeyDeserializer<'a, D> {
+    inner: D,
+    key: &'a mut Option<String>,
+}
+
+struct KeyVisitor<'a, V> {
+    inner: V,
+    key: &'a mut Option<String>,
+}
+
+macro_rules! forward_key_deserialize {
+    ($($method:ident($($arg:ident: $ty:ty),*))*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, $($arg: $ty,)* visitor: V) -> Result<V::Value, D::Error> {
+                let visitor = KeyVisitor { inner: visitor, key: self.key };
+                self.inner.$method($($arg,)* visitor)
+            }
+        )*
+    };
+}
+
+impl<'de, 'a, D: Deserializer<'de>> Deserializer<'de> for KeyDeserializer<'a, D> {
+    type Error = D::Error;
+
+    forward_key_deserialize! {
+        deserialize_any() deserialize_bool() deserialize_i8() deserialize_i16() deserialize_i32()
+        deserialize_i64() deserialize_u8() deserialize_u16() deserialize_u32() deserialize_u64()
+        deserialize_f32() deserialize_f64() deserialize_char() deserialize_str()
+        deserialize_string() deserialize_bytes() deserialize_byte_buf() deserialize_option()
+        deserialize_unit() deserialize_seq() deserialize_map() deserialize_identifier()
+        deserialize_ignored_any()
+        deserialize_unit_struct(name: &'static str)
+        deserialize_newtype_struct(name: &'static str)
+        deserialize_tuple(len: usize)
+        deserialize_tuple_struct(name: &'static str, len: usize)
+        deserialize_struct(name: &'static str, fields: &'static [&'static str])
+        deserialize_enum(name: &'static str, variants: &'static [&'static str])
+    }
+}
+
+impl<'de, 'a, V: Visitor<'de>> Visitor<'de> for KeyVisitor<'a, V> {
+    type Value = V::Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.expecting(f)
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<V::Value, E> {
+        *self.key = Some(v.to_owned());
+        self.inner.visit_str(v)
+    }
+
+    fn visit_borrowed_str<E: de::Error>(self, v: &'de str) -> Result<V::Value, E> {
+        *self.key = Some(v.to_owned());
+        self.inner.visit_borrowed_str(v)
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<V::Value, E> {
+        *self.key = Some(v.clone());
+        self.inner.visit_string(v)
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<V::Value, E> {
+        *self.key = Some(v.to_string());
+        self.inner.visit_u64(v)
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<V::Value, E> {
+        *self.key = Some(v.to_string());
+        self.inner.visit_i64(v)
+    }
+}
+
+impl<'de, A: MapAccess<'de>> MapAccess<'de> for WrapMap<'de, A> {
+    type Error = A::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, A::Error> {
+        let mut seed = Some(seed);
+        let mut key = None;
+        let value = if self.added {
+            None
+        } else {
+            self.inner.next_key_seed(KeySeed {
+                inner: &mut seed,
+                key: &mut key,
+            })?
+        };
+        match value {
+            Some(value) => {
+                if let Some(key) = &key {
+                    self.missing.retain(|m| m != key);
+                }
+                self.key = key;
+                Ok(Some(value))
+            }
+            None => match (self.missing.pop(), seed) {
+                (Some(key), Some(seed)) => {
+                    self.added = true;
+                    let deserializer: StrDeserializer<A::Error> = key.as_str().into_deserializer();
+                    let value = seed.deserialize(deserializer)?;
+                    self.key = Some(key);
+                    Ok(Some(value))
+                }
+                _ => Ok(None),
+            },
+        }
+    }
+
+    fn next_value_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<T::Value, A::Error> {
+        let context = match self.key.take() {
+            Some(key) => self.context.child(key),
+            None => return self.inner.next_value_seed(seed),
+        };
+        if let Some(o) = context.exact() {
+            if !self.added {
+                self.inner.next_value::<IgnoredAny>()?;
+            }
+            return override_value(seed, o);
+        }
+        if context.has_below() {
+            self.inner.next_value_seed(WrapSeed {
+                inner: seed,
+                context,
+            })
+        } else {
+            self.inner.next_value_seed(seed)
+        }
+    }
+}
+
+struct WrapEnum<'de, A> {
+    inner: A,
+    context: Context<'de>,
+}
+
+impl<'de, A: EnumAccess<'de>> EnumAccess<'de> for WrapEnum<'de, A> {
+    type Error = A::Error;
+    type Variant = WrapVariant<'de, A::Variant>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self::Variant), A::Error> {
+        let (value, variant) = self.inner.variant_seed(seed)?;
+        Ok((
+            value,
+            WrapVariant {
+                inner: variant,
+                context: self.context,
+            },
+        ))
+    }
+}
+
+struct WrapVariant<'de, A> {
+    inner: A,
+    context: Context<'de>,
+}
+
+impl<'de, A: VariantAccess<'de>> VariantAccess<'de> for WrapVariant<'de, A> {
+    type Error = A::Error;
+
+    fn unit_variant(self) -> Result<(), A::Error> {
+        self.inner.unit_variant()
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, A::Error> {
+        self.inner.newtype_variant_seed(WrapSeed {
+            inner: seed,
+            context: self.context,
+        })
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, A::Error> {
+        self.inner.tuple_variant(
+            len,
+            WrapVisitor {
+                inner: visitor,
+                context: self.context,
+            },
+        )
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, A::Error> {
+        let mut context = self.context;
+        context.fields = Some(fields);
+        self.inner.struct_variant(
+            fields,
+            WrapVisitor {
+                inner: visitor,
+                context,
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    enum Shape {
+        Circle { radius: f32 },
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Config {
+        size: u32,
+        #[serde(default)]
+        name: Option<String>,
+        shapes: Vec<Shape>,
+        tags: BTreeMap<String, u32>,
+    }
+
+    fn parse(text: &str, args: &[&str]) -> Result<Config, ron::Error> {
+        let overrides: Vec<Override> = args.iter().map(|a| a.parse().unwrap()).collect();
+        let mut deserializer = ron::de::Deserializer::from_str(text)?;
+        deserialize_with_overrides(&mut deserializer, &overrides)
+    }
+
+    #[test]
+    fn override_nested_and_missing() {
+        let text = r#"(size: 1, shapes: [Circle(radius: 1), Circle(radius: 2)], tags: {"a": 1})"#;
+        let config = parse(
+            text,
+            &[
+                "size=5",
+                "shapes.1.radius=3.5",
+                "name=Some(\"x\")",
+                "tags.b=2",
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.size, 5);
+        assert_eq!(config.name.as_deref(), Some("x"));
+        assert_eq!(
+            config.shapes,
+            vec![Shape::Circle { radius: 1.0 }, Shape::Circle { radius: 3.5 }]
+        );
+        assert_eq!(config.tags.len(), 2);
+
+        assert!(parse(text, &["shapes.5.radius=1"]).is_err());
+        assert!(parse(text, &["color=1"]).is_err());
+        assert!("size".parse::<Override>().is_err());
+    }
+}
@@ -1,10 +1,43 @@
-use crate::rtracer::geometric::Plane;
-use crate::rtracer::helper::debug_normalize;
+use crate::rtracer::animation::{AnimationError, Interpolation, Track};
+use crate::rtracer::curve::{CurveError, CurveKind, Curves};
+use crate::rtracer::geometric::{Capsule, Cone, Cuboid, Cylinder, Plane, Sdf, SdfNode, Torus};
+use crate::rtracer::heightfield::{Heightfield, HeightfieldError};
+use crate::rtracer::helper::normalize_or_zero;
 use crate::rtracer::light::AreaLight;
+use crate::rtracer::material::PBRDiffuse;
+use crate::rtracer::mesh::{Mesh, MeshError};
+use crate::rtracer::obj_import::{ObjAsset, ObjImportError};
+use crate::rtracer::scene::Include;
+use crate::rtracer::texture::{ImageTexture, TextureError};
+use crate::rtracer::transform::Transform;
+use crate::rtracer::volume::{DensitySource, Volume, VolumeError};
 use crate::rtracer::Color3;
+use crate::utils::aabb::AABB;
 use nalgebra::{Point3, Similarity3, Unit, UnitQuaternion, Vector3};
 use serde::{Deserialize, Serialize};
-use crate::rtracer::material::PBRDiffuse;
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+
+thread_local! {
+    // directory of the scene file being deserialized
+    static BASE_DIR: RefCell<PathBuf> = RefCell::new(PathBuf::new());
+}
+
+/// Deserialize in `f` with the relative file paths (mesh, texture, ...) taken from `dir`,
+/// the scene file's directory, instead of the working directory
+pub fn with_base_dir<T>(dir: &Path, f: impl FnOnce() -> T) -> T {
+    let previous = BASE_DIR.with(|base| base.replace(dir.to_owned()));
+    let result = f();
+    BASE_DIR.with(|base| base.replace(previous));
+    result
+}
+
+// absolute path is kept as is. Loaded assets keep the path as written, so a saved scene keep
+// its relative paths
+fn resolve_file(file: &Path) -> PathBuf {
+    BASE_DIR.with(|base| base.borrow().join(file))
+}
 
 pub mod squared {
     use serde::{Deserialize, Deserializer, Serialize, Serializer};
@@ -13,23 +46,26 @@ pub mod squared {
     where
         S: Serializer,
     {
-        (data.sqrt()).serialize(serializer)
+        (data.signum() * data.abs().sqrt()).serialize(serializer)
     }
 
     pub fn deserialize<'de, D>(deserializer: D) -> Result<f32, D::Error>
     where
         D: Deserializer<'de>,
     {
-        f32::deserialize(deserializer).map(|x| x * x)
+        // sign is kept, so a negative value can still be reported by the validation
+        f32::deserialize(deserializer).map(|x| x * x.abs())
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct PlaneProxy {
     pos: Point3<f32>,
     norm: Unit<Vector3<f32>>,
     span: Vector3<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     scale: Option<[f32; 2]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     cospan: Option<Vector3<f32>>,
 }
 
@@ -59,12 +95,34 @@ impl From<PlaneProxy> for Plane {
     }
 }
 
-#[derive(Deserialize)]
+// scale is folded into the spans
+impl From<Plane> for PlaneProxy {
+    fn from(plane: Plane) -> Self {
+        PlaneProxy {
+            pos: plane.pos,
+            norm: plane.norm,
+            span: plane.span_dir.scale(plane.span_length),
+            scale: None,
+            cospan: Some(plane.cospan_dir.scale(plane.cospan_length)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct AreaLightProxy {
     pub plane: PlaneProxy,
     pub light: Color3,
 }
 
+impl From<AreaLight> for AreaLightProxy {
+    fn from(light: AreaLight) -> Self {
+        AreaLightProxy {
+            plane: light.plane.into(),
+            light: light.light,
+        }
+    }
+}
+
 impl From<AreaLightProxy> for AreaLight {
     fn from(proxy: AreaLightProxy) -> Self {
         AreaLight {
@@ -74,7 +132,7 @@ impl From<AreaLightProxy> for AreaLight {
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct PBRDiffuseProxy {
     color: Color3,
     albedo: f32,
@@ -84,11 +142,357 @@ pub struct PBRDiffuseProxy {
 impl From<PBRDiffuseProxy> for PBRDiffuse {
     fn from(proxy: PBRDiffuseProxy) -> Self {
         PBRDiffuse {
+            color: proxy.color,
+            albedo: proxy.albedo,
             color_albedo: proxy.albedo * proxy.color,
-            iteration: proxy.iteration
+            iteration: proxy.iteration,
+        }
+    }
+}
+
+impl From<PBRDiffuse> for PBRDiffuseProxy {
+    fn from(material: PBRDiffuse) -> Self {
+        PBRDiffuseProxy {
+            color: material.color,
+            albedo: material.albedo,
+            iteration: material.iteration,
+        }
+    }
+}
+#[derive(Serialize, Deserialize)]
+pub struct VolumeProxy {
+    min: Point3<f32>,
+    max: Point3<f32>,
+    sigma_t: f32,
+    density: DensitySource,
+}
+
+impl TryFrom<VolumeProxy> for Volume {
+    type Error = VolumeError;
+
+    fn try_from(proxy: VolumeProxy) -> Result<Self, Self::Error> {
+        let density = match &proxy.density {
+            DensitySource::VoxelFile(file) => DensitySource::VoxelFile(resolve_file(file)),
+            density => density.clone(),
+        };
+        Volume::new(AABB::new(proxy.min, proxy.max), proxy.sigma_t, density)
+            .map(|volume| volume.written_as(proxy.density))
+    }
+}
+
+impl From<Volume> for VolumeProxy {
+    fn from(volume: Volume) -> Self {
+        VolumeProxy {
+            min: *volume.bounds.min(),
+            max: *volume.bounds.max(),
+            sigma_t: volume.sigma_t,
+            density: volume.source().clone(),
+        }
+    }
+}
+#[derive(Serialize, Deserialize)]
+pub struct MeshProxy {
+    file: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    model: Option<usize>,
+}
+
+impl TryFrom<MeshProxy> for Mesh {
+    type Error = MeshError;
+
+    fn try_from(proxy: MeshProxy) -> Result<Self, Self::Error> {
+        Mesh::load(resolve_file(&proxy.file), proxy.model).map(|mut mesh| {
+            mesh.file = proxy.file;
+            mesh
+        })
+    }
+}
+
+impl From<Mesh> for MeshProxy {
+    fn from(mesh: Mesh) -> Self {
+        MeshProxy {
+            file: mesh.file,
+            model: mesh.model,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CuboidProxy {
+    center: Point3<f32>,
+    size: Vector3<f32>,
+    // rotation axis scaled by the angle in radians, unlike euler angles it save without loss
+    #[serde(default = "Vector3::zeros")]
+    rotation: Vector3<f32>,
+}
+
+impl From<CuboidProxy> for Cuboid {
+    fn from(proxy: CuboidProxy) -> Self {
+        Cuboid::new(
+            proxy.center,
+            proxy.size,
+            UnitQuaternion::from_scaled_axis(proxy.rotation),
+        )
+    }
+}
+
+impl From<Cuboid> for CuboidProxy {
+    fn from(cuboid: Cuboid) -> Self {
+        CuboidProxy {
+            center: cuboid.center,
+            size: cuboid.half_size * 2.0,
+            rotation: cuboid.rotation.scaled_axis(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CylinderProxy {
+    start: Point3<f32>,
+    end: Point3<f32>,
+    radius: f32,
+    #[serde(default = "default_capped")]
+    capped: bool,
+}
+
+fn default_capped() -> bool {
+    true
+}
+
+impl From<CylinderProxy> for Cylinder {
+    fn from(proxy: CylinderProxy) -> Self {
+        Cylinder::new(proxy.start, proxy.end, proxy.radius, proxy.capped)
+    }
+}
+
+impl From<Cylinder> for CylinderProxy {
+    fn from(cylinder: Cylinder) -> Self {
+        CylinderProxy {
+            start: cylinder.start,
+            end: cylinder.end,
+            radius: cylinder.radius,
+            capped: cylinder.capped,
         }
     }
 }
+
+#[derive(Serialize, Deserialize)]
+pub struct ConeProxy {
+    start: Point3<f32>,
+    end: Point3<f32>,
+    start_radius: f32,
+    // pointed cone by default
+    #[serde(default)]
+    end_radius: f32,
+    #[serde(default = "default_capped")]
+    capped: bool,
+}
+
+impl From<ConeProxy> for Cone {
+    fn from(proxy: ConeProxy) -> Self {
+        Cone::new(
+            proxy.start,
+            proxy.end,
+            proxy.start_radius,
+            proxy.end_radius,
+            proxy.capped,
+        )
+    }
+}
+
+impl From<Cone> for ConeProxy {
+    fn from(cone: Cone) -> Self {
+        ConeProxy {
+            start: cone.start,
+            end: cone.end,
+            start_radius: cone.start_radius,
+            end_radius: cone.end_radius,
+            capped: cone.capped,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CapsuleProxy {
+    start: Point3<f32>,
+    end: Point3<f32>,
+    radius: f32,
+}
+
+impl From<CapsuleProxy> for Capsule {
+    fn from(proxy: CapsuleProxy) -> Self {
+        Capsule::new(proxy.start, proxy.end, proxy.radius)
+    }
+}
+
+impl From<Capsule> for CapsuleProxy {
+    fn from(capsule: Capsule) -> Self {
+        CapsuleProxy {
+            start: capsule.start,
+            end: capsule.end,
+            radius: capsule.radius,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TorusProxy {
+    center: Point3<f32>,
+    #[serde(default = "Vector3::z")]
+    axis: Vector3<f32>,
+    major_radius: f32,
+    minor_radius: f32,
+}
+
+impl From<TorusProxy> for Torus {
+    fn from(proxy: TorusProxy) -> Self {
+        Torus::new(
+            proxy.center,
+            normalize_or_zero(proxy.axis),
+            proxy.major_radius,
+            proxy.minor_radius,
+        )
+    }
+}
+
+impl From<Torus> for TorusProxy {
+    fn from(torus: Torus) -> Self {
+        TorusProxy {
+            center: torus.center,
+            axis: torus.axis.into_inner(),
+            major_radius: torus.major_radius,
+            minor_radius: torus.minor_radius,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SdfProxy {
+    min: Point3<f32>,
+    max: Point3<f32>,
+    node: SdfNode,
+    #[serde(default = "default_max_steps")]
+    max_steps: u32,
+    #[serde(default = "default_sdf_epsilon")]
+    epsilon: f32,
+}
+
+fn default_max_steps() -> u32 {
+    256
+}
+
+fn default_sdf_epsilon() -> f32 {
+    1e-4
+}
+
+impl From<SdfProxy> for Sdf {
+    fn from(proxy: SdfProxy) -> Self {
+        Sdf {
+            bounds: AABB::new(proxy.min, proxy.max),
+            node: proxy.node,
+            max_steps: proxy.max_steps,
+            epsilon: proxy.epsilon,
+        }
+    }
+}
+
+impl From<Sdf> for SdfProxy {
+    fn from(sdf: Sdf) -> Self {
+        SdfProxy {
+            min: *sdf.bounds.min(),
+            max: *sdf.bounds.max(),
+            node: sdf.node,
+            max_steps: sdf.max_steps,
+            epsilon: sdf.epsilon,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct HeightfieldProxy {
+    file: PathBuf,
+    // corner with the lowest x and y, at height 0
+    #[serde(default = "Point3::origin")]
+    origin: Point3<f32>,
+    // size along x and y
+    extent: (f32, f32),
+    // height of white pixel
+    height_scale: f32,
+}
+
+impl TryFrom<HeightfieldProxy> for Heightfield {
+    type Error = HeightfieldError;
+
+    fn try_from(proxy: HeightfieldProxy) -> Result<Self, Self::Error> {
+        Heightfield::load(
+            resolve_file(&proxy.file),
+            proxy.origin,
+            proxy.extent,
+            proxy.height_scale,
+        )
+        .map(|mut heightfield| {
+            heightfield.file = proxy.file;
+            heightfield
+        })
+    }
+}
+
+impl From<Heightfield> for HeightfieldProxy {
+    fn from(heightfield: Heightfield) -> Self {
+        HeightfieldProxy {
+            file: heightfield.file,
+            origin: heightfield.origin,
+            extent: heightfield.extent,
+            height_scale: heightfield.height_scale,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CurvesProxy {
+    file: PathBuf,
+    #[serde(default)]
+    kind: CurveKind,
+}
+
+impl TryFrom<CurvesProxy> for Curves {
+    type Error = CurveError;
+
+    fn try_from(proxy: CurvesProxy) -> Result<Self, Self::Error> {
+        Curves::load(resolve_file(&proxy.file), proxy.kind).map(|mut curves| {
+            curves.file = proxy.file;
+            curves
+        })
+    }
+}
+
+impl From<Curves> for CurvesProxy {
+    fn from(curves: Curves) -> Self {
+        CurvesProxy {
+            file: curves.file,
+            kind: curves.kind,
+        }
+    }
+}
+
+fn default_scale() -> Vector3<f32> {
+    Vector3::new(1.0, 1.0, 1.0)
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub enum TransformProxy {
+    // row-major affine matrix
+    Matrix([[f32; 4]; 4]),
+    // scale, then rotate (euler angles: roll, pitch, yaw), then translate
+    TRS {
+        #[serde(default = "Vector3::zeros")]
+        translation: Vector3<f32>,
+        #[serde(default)]
+        rotation: (f32, f32, f32),
+        #[serde(default = "default_scale")]
+        scale: Vector3<f32>,
+    },
+}
 /*
 #[test]
 fn a() {
@@ -98,3 +502,127 @@ fn a() {
     };
     crate::utils::print_ron(&x);
 }*/
+
+#[derive(Serialize, Deserialize)]
+pub struct TrackProxy<T> {
+    #[serde(default)]
+    interpolation: Interpolation,
+    keys: Vec<(f32, T)>,
+}
+
+impl<T> TryFrom<TrackProxy<T>> for Track<T> {
+    type Error = AnimationError;
+
+    fn try_from(proxy: TrackProxy<T>) -> Result<Self, Self::Error> {
+        Track::new(proxy.interpolation, proxy.keys)
+    }
+}
+
+impl<T> From<Track<T>> for TrackProxy<T> {
+    fn from(track: Track<T>) -> Self {
+        TrackProxy {
+            interpolation: track.interpolation,
+            keys: track.keys,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ImageTextureProxy {
+    file: PathBuf,
+}
+
+impl TryFrom<ImageTextureProxy> for ImageTexture {
+    type Error = TextureError;
+
+    fn try_from(proxy: ImageTextureProxy) -> Result<Self, Self::Error> {
+        ImageTexture::load(resolve_file(&proxy.file)).map(|mut texture| {
+            texture.file = proxy.file;
+            texture
+        })
+    }
+}
+
+impl From<ImageTexture> for ImageTextureProxy {
+    fn from(texture: ImageTexture) -> Self {
+        ImageTextureProxy { file: texture.file }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ObjAssetProxy {
+    file: PathBuf,
+    #[serde(default)]
+    transform: Option<Transform>,
+}
+
+impl TryFrom<ObjAssetProxy> for ObjAsset {
+    type Error = ObjImportError;
+
+    fn try_from(proxy: ObjAssetProxy) -> Result<Self, Self::Error> {
+        let ObjAssetProxy { file, transform } = proxy;
+        ObjAsset::load(resolve_file(&file), transform).map(|mut asset| {
+            asset.file = file;
+            asset
+        })
+    }
+}
+
+#[derive(Deserialize)]
+pub struct IncludeFields {
+    file: PathBuf,
+    #[serde(default)]
+    transform: Option<Transform>,
+}
+
+/// Either just the file name, or `(file: ..., transform: ...)`
+// serde's untagged enum buffer the content, which lose ron's enum (the transform), hence the visitor
+pub enum IncludeProxy {
+    File(PathBuf),
+    Transformed(IncludeFields),
+}
+
+impl<'de> Deserialize<'de> for IncludeProxy {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct IncludeVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for IncludeVisitor {
+            type Value = IncludeProxy;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("file name or (file: ..., transform: ...)")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(IncludeProxy::File(v.into()))
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(
+                self,
+                map: A,
+            ) -> Result<Self::Value, A::Error> {
+                IncludeFields::deserialize(serde::de::value::MapAccessDeserializer::new(map))
+                    .map(IncludeProxy::Transformed)
+            }
+        }
+
+        deserializer.deserialize_any(IncludeVisitor)
+    }
+}
+
+impl From<IncludeProxy> for Include {
+    fn from(proxy: IncludeProxy) -> Self {
+        match proxy {
+            IncludeProxy::File(file) => Include {
+                file,
+                transform: None,
+            },
+            IncludeProxy::Transformed(IncludeFields { file, transform }) => {
+                Include { file, transform }
+            }
+        }
+    }
+}